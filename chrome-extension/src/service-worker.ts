@@ -1,15 +1,134 @@
-type BrowseEventType = "activate" | "update";
+type BrowseEventType =
+  | "activate"
+  | "update"
+  | "focus_gained"
+  | "focus_lost"
+  | "idle"
+  | "tab_closed"
+  | "browser_closed";
+
+type BrowseEvent = {
+  event_id: string;
+  tab_id: number;
+  timestamp: string;
+  page_url: string;
+  page_title: string;
+  page_content: string | null;
+  event_type: BrowseEventType;
+};
+
+type LogEventResult = {
+  event_id: string;
+  status: "inserted" | "duplicate" | "ignored" | "failed";
+  error?: string;
+};
 
 const BACKEND_SERVER_URL = "http://localhost:8000";
 
-function sendBrowseEvent(
+// Events that couldn't be sent are kept here and retried in batches
+const UNSENT_EVENTS_KEY = "unsentEvents";
+const FLUSH_ALARM_NAME = "flushUnsentEvents";
+const FLUSH_BATCH_SIZE = 50;
+// Events can carry page HTML and storage is limited, so the oldest events are dropped past this
+const MAX_UNSENT_EVENTS = 1000;
+
+const IDLE_DETECTION_INTERVAL_SECONDS = 60;
+
+// Last known page of each tab, since closed tabs can't be looked up anymore
+const knownTabs = new Map<number, { url: string; title: string }>();
+let lastActiveTabId: number | null = null;
+
+async function getUnsentEvents(): Promise<BrowseEvent[]> {
+  const stored = await chrome.storage.local.get(UNSENT_EVENTS_KEY);
+  return stored[UNSENT_EVENTS_KEY] ?? [];
+}
+
+// Updates are read-modify-writes of the whole buffer, so they're chained to never interleave
+let unsentEventsUpdate: Promise<void> = Promise.resolve();
+
+function updateUnsentEvents(
+  update: (unsentEvents: BrowseEvent[]) => BrowseEvent[]
+): Promise<void> {
+  const result = unsentEventsUpdate.then(async () => {
+    const unsentEvents = await getUnsentEvents();
+    await chrome.storage.local.set({
+      [UNSENT_EVENTS_KEY]: update(unsentEvents),
+    });
+  });
+  // A failed update shouldn't block the ones after it
+  unsentEventsUpdate = result.catch(() => {});
+  return result;
+}
+
+function bufferUnsentEvent(browseEvent: BrowseEvent): Promise<void> {
+  return updateUnsentEvents((unsentEvents) => {
+    unsentEvents.push(browseEvent);
+    const numDropped = unsentEvents.length - MAX_UNSENT_EVENTS;
+    if (numDropped > 0) {
+      console.warn(
+        `Unsent event buffer is full, dropping the ${numDropped} oldest events`
+      );
+      return unsentEvents.slice(numDropped);
+    }
+    return unsentEvents;
+  });
+}
+
+async function flushUnsentEvents() {
+  const unsentEvents = await getUnsentEvents();
+  if (unsentEvents.length === 0) {
+    return;
+  }
+
+  const batch = unsentEvents.slice(0, FLUSH_BATCH_SIZE);
+  const response = await fetch(`${BACKEND_SERVER_URL}/log_events`, {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify(batch),
+  });
+  if (!response.ok) {
+    throw new Error(`(status: ${response.status}) ${await response.text()}`);
+  }
+
+  // Only events the server failed on are kept for the next flush
+  const results: LogEventResult[] = await response.json();
+  const failedEventIds = new Set(
+    results
+      .filter((result) => result.status === "failed")
+      .map((result) => result.event_id)
+  );
+  const sentEventIds = new Set(
+    batch
+      .map((browseEvent) => browseEvent.event_id)
+      .filter((eventId) => !failedEventIds.has(eventId))
+  );
+
+  // Re-read in case new events were buffered while the request was in flight
+  await updateUnsentEvents((unsentEvents) =>
+    unsentEvents.filter((browseEvent) => !sentEventIds.has(browseEvent.event_id))
+  );
+
+  if (batch.length === FLUSH_BATCH_SIZE && sentEventIds.size > 0) {
+    await flushUnsentEvents();
+  }
+}
+
+function createBrowseEvent(
   tabId: number,
   pageUrl: string,
   pageTitle: string,
   pageContent: string | null,
   eventType: BrowseEventType
-) {
-  const browseEvent = {
+): BrowseEvent {
+  knownTabs.set(tabId, { url: pageUrl, title: pageTitle });
+  if (eventType === "activate" || eventType === "focus_gained") {
+    lastActiveTabId = tabId;
+  }
+
+  return {
+    event_id: crypto.randomUUID(),
     tab_id: tabId,
     timestamp: new Date().toISOString(),
     page_url: pageUrl,
@@ -17,6 +136,22 @@ function sendBrowseEvent(
     page_content: pageContent,
     event_type: eventType,
   };
+}
+
+function sendBrowseEvent(
+  tabId: number,
+  pageUrl: string,
+  pageTitle: string,
+  pageContent: string | null,
+  eventType: BrowseEventType
+) {
+  const browseEvent = createBrowseEvent(
+    tabId,
+    pageUrl,
+    pageTitle,
+    pageContent,
+    eventType
+  );
 
   console.log(`Sending event ${browseEvent}`);
 
@@ -28,20 +163,36 @@ function sendBrowseEvent(
     },
     body: JSON.stringify(browseEvent),
   })
-    .then((response) => {
+    .then(async (response) => {
       if (!response.ok) {
-        response.text().then((errorText) => {
-          throw new Error(`(status: ${response.status}) ${errorText}`);
-        });
-      } else {
-        return response.json();
+        const errorText = await response.text();
+        throw new Error(`(status: ${response.status}) ${errorText}`);
       }
+      return response.json();
     })
-    .catch((error) => {
-      console.error("Error:", error);
-    });
+    .then(
+      // Only events the server didn't accept are buffered, a failed flush retries on its own
+      () => {
+        flushUnsentEvents().catch((error) => {
+          console.error("Error flushing unsent events:", error);
+        });
+      },
+      (error) => {
+        console.error("Error:", error);
+        bufferUnsentEvent(browseEvent);
+      }
+    );
 }
 
+chrome.alarms.create(FLUSH_ALARM_NAME, { periodInMinutes: 1 });
+chrome.alarms.onAlarm.addListener((alarm) => {
+  if (alarm.name === FLUSH_ALARM_NAME) {
+    flushUnsentEvents().catch((error) => {
+      console.error("Error flushing unsent events:", error);
+    });
+  }
+});
+
 // This function can only be run via the scripting api
 function getPageContent() {
   return document.body.innerHTML;
@@ -101,16 +252,73 @@ chrome.tabs.onActivated.addListener((activeInfo) => {
   });
 });
 
-// TODO: Window focus
+// Events that aren't about a page change are attributed to the active tab of the last focused window
+function sendActiveTabEvent(eventType: BrowseEventType) {
+  chrome.tabs.query({ active: true, lastFocusedWindow: true }, (tabs) => {
+    const tab = tabs[0];
+    if (tab && tab.id && tab.url && tab.title) {
+      sendBrowseEvent(tab.id, tab.url, tab.title, null, eventType);
+    }
+  });
+}
+
+// Window focus
 chrome.windows.onFocusChanged.addListener((windowId) => {
   if (windowId === chrome.windows.WINDOW_ID_NONE) {
     console.log("Chrome window lost focus");
+    sendActiveTabEvent("focus_lost");
   } else {
     console.log("Chrome window gained focus");
+    sendActiveTabEvent("focus_gained");
   }
 });
 
-// TODO: Chrome closing
-chrome.runtime.onSuspend.addListener(() => {
-  console.log("Chrome is closing");
+// Idle and screen lock
+chrome.idle.setDetectionInterval(IDLE_DETECTION_INTERVAL_SECONDS);
+chrome.idle.onStateChanged.addListener((state) => {
+  if (state === "active") {
+    sendActiveTabEvent("focus_gained");
+  } else {
+    sendActiveTabEvent("idle");
+  }
+});
+
+// Tab closing
+chrome.tabs.onRemoved.addListener((tabId, removeInfo) => {
+  const knownTab = knownTabs.get(tabId);
+  if (knownTab && !removeInfo.isWindowClosing) {
+    sendBrowseEvent(tabId, knownTab.url, knownTab.title, null, "tab_closed");
+  }
+  knownTabs.delete(tabId);
+});
+
+// Chrome closing. `runtime.onSuspend` also fires whenever the service worker is unloaded, so
+// closing the last window is used instead. The event is buffered rather than sent, since the
+// browser may exit before a request completes, and it's flushed on the next startup.
+chrome.windows.onRemoved.addListener(() => {
+  chrome.windows.getAll((windows) => {
+    if (windows.length > 0) {
+      return;
+    }
+
+    const knownTab =
+      lastActiveTabId !== null ? knownTabs.get(lastActiveTabId) : undefined;
+    if (lastActiveTabId !== null && knownTab) {
+      bufferUnsentEvent(
+        createBrowseEvent(
+          lastActiveTabId,
+          knownTab.url,
+          knownTab.title,
+          null,
+          "browser_closed"
+        )
+      );
+    }
+  });
+});
+
+chrome.runtime.onStartup.addListener(() => {
+  flushUnsentEvents().catch((error) => {
+    console.error("Error flushing unsent events:", error);
+  });
 });
@@ -0,0 +1,34 @@
+use std::{env, process::Command};
+
+/// Embeds the commit the server is built from as `GIT_SHA`. Builds without the git checkout, e.g.
+/// in docker, can pass it in through the `GIT_SHA` env var instead.
+fn main() {
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+
+    let git_sha = env::var("GIT_SHA")
+        .ok()
+        .filter(|git_sha| !git_sha.is_empty())
+        .or_else(git_head_sha)
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=GIT_SHA={}", git_sha);
+}
+
+fn git_head_sha() -> Option<String> {
+    let git_dir = git(&["rev-parse", "--git-dir"])?;
+    // New commits and checkouts move HEAD or the ref it points at
+    println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+    println!("cargo:rerun-if-changed={}/refs", git_dir);
+
+    git(&["rev-parse", "HEAD"])
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    Some(stdout.trim().to_string())
+}
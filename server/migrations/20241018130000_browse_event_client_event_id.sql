@@ -0,0 +1,2 @@
+ALTER TABLE browse_event
+ADD COLUMN client_event_id UUID UNIQUE;
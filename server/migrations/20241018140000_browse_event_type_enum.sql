@@ -0,0 +1,12 @@
+CREATE TYPE browse_event_type AS ENUM (
+    'activate',
+    'update',
+    'focus_gained',
+    'focus_lost',
+    'idle',
+    'tab_closed',
+    'browser_closed'
+);
+
+ALTER TABLE browse_event
+ALTER COLUMN event_type TYPE browse_event_type USING event_type::browse_event_type;
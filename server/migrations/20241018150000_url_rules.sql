@@ -0,0 +1,16 @@
+CREATE TYPE url_rule_match_type AS ENUM ('domain_glob', 'regex', 'path_prefix');
+CREATE TYPE url_rule_action AS ENUM ('drop_event', 'drop_content', 'domain_only');
+
+CREATE TABLE url_rule (
+    id SERIAL PRIMARY KEY,
+    match_type url_rule_match_type NOT NULL,
+    pattern TEXT NOT NULL,
+    action url_rule_action NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Previously hard-coded in `should_ignore_event`
+INSERT INTO url_rule (match_type, pattern, action)
+VALUES
+    ('domain_glob', 'localhost', 'drop_event'),
+    ('domain_glob', 'mail.google.com', 'drop_event');
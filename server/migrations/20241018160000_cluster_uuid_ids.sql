@@ -0,0 +1,68 @@
+-- Cluster ids used to be hashes of the first page's url, shared across clustering runs. Give every
+-- cluster a generated uuid instead, and carry the run on assignments so they can only point at a
+-- cluster of the same run.
+ALTER TABLE cluster
+    ADD COLUMN new_id UUID NOT NULL DEFAULT gen_random_uuid();
+
+ALTER TABLE cluster_assignment
+    ADD COLUMN new_cluster_id UUID,
+    ADD COLUMN clustering_run TEXT;
+
+UPDATE cluster_assignment ca
+SET new_cluster_id = c.new_id,
+    clustering_run = c.clustering_run
+FROM cluster c
+WHERE c.id = ca.cluster_id;
+
+ALTER TABLE cluster_assignment
+    DROP CONSTRAINT fk_cluster_assignment_cluster_id,
+    DROP COLUMN cluster_id;
+
+ALTER TABLE cluster_assignment
+    RENAME COLUMN new_cluster_id TO cluster_id;
+
+ALTER TABLE cluster
+    DROP CONSTRAINT cluster_pkey,
+    DROP COLUMN id;
+
+ALTER TABLE cluster
+    RENAME COLUMN new_id TO id;
+
+ALTER TABLE cluster
+    ADD PRIMARY KEY (id),
+    ADD CONSTRAINT cluster_id_clustering_run_key UNIQUE (id, clustering_run);
+
+-- With ids shared across runs, an assignment made in one run could point at a cluster another run
+-- created first, and so ended up with that other run above. Which run it was made in can't be told
+-- reliably, so those assignments are dropped instead, and `server backfill` reassigns the pages.
+
+-- Online runs are `{pipeline}-online-nearest-neighbor`, and only assign pages their pipeline embedded
+DELETE FROM cluster_assignment ca
+WHERE ca.clustering_run LIKE '%-online-nearest-neighbor'
+AND NOT EXISTS (
+    SELECT 1 FROM preprocessed_page_embedding ppe
+    WHERE ppe.page_id = ca.page_id
+    AND ppe.embedding_run || '-online-nearest-neighbor' = ca.clustering_run
+);
+
+-- The same page and cluster twice is one genuine assignment plus one from another run
+DELETE FROM cluster_assignment ca
+USING cluster_assignment duplicate
+WHERE ca.page_id = duplicate.page_id
+AND ca.cluster_id = duplicate.cluster_id
+AND ca.id > duplicate.id;
+
+-- A page belongs to at most one cluster per run. When a run has several clusters for a page, any
+-- of them could be the one from another run.
+DELETE FROM cluster_assignment ca
+USING cluster_assignment conflicting
+WHERE ca.page_id = conflicting.page_id
+AND ca.clustering_run = conflicting.clustering_run
+AND ca.id <> conflicting.id;
+
+ALTER TABLE cluster_assignment
+    ALTER COLUMN cluster_id SET NOT NULL,
+    ALTER COLUMN clustering_run SET NOT NULL,
+    ADD CONSTRAINT fk_cluster_assignment_cluster FOREIGN KEY (cluster_id, clustering_run)
+        REFERENCES cluster(id, clustering_run) ON DELETE CASCADE,
+    ADD CONSTRAINT cluster_assignment_page_id_clustering_run_key UNIQUE (page_id, clustering_run);
@@ -0,0 +1,58 @@
+CREATE TYPE run_status AS ENUM ('running', 'completed', 'failed');
+
+CREATE TABLE embedding_run (
+    name TEXT PRIMARY KEY,
+    pipeline_name TEXT NOT NULL,
+    -- Unknown for runs that predate this table
+    model_id TEXT,
+    embedding_dimension INTEGER,
+    code_version TEXT,
+    status run_status NOT NULL DEFAULT 'running',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE clustering_run (
+    name TEXT PRIMARY KEY,
+    embedding_run TEXT REFERENCES embedding_run(name),
+    algorithm TEXT NOT NULL,
+    -- Thresholds and other algorithm settings, e.g. {"similarity_threshold": 0.8}
+    parameters JSONB NOT NULL DEFAULT '{}',
+    code_version TEXT,
+    status run_status NOT NULL DEFAULT 'running',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Backfill records for the runs that only exist as names so far
+INSERT INTO embedding_run (name, pipeline_name, embedding_dimension, created_at)
+SELECT embedding_run, embedding_run, 384, MIN(created_at)
+FROM preprocessed_page_embedding
+WHERE embedding_run IS NOT NULL
+GROUP BY embedding_run;
+
+INSERT INTO clustering_run (name, algorithm, status)
+SELECT DISTINCT clustering_run, 'unknown', 'completed'::run_status
+FROM cluster;
+
+-- Run names are `{pipeline}-{algorithm}`, with a timestamp suffix for batch runs. The pipeline is
+-- what's left without the suffix, since pipeline names can be prefixes of each other.
+UPDATE clustering_run cr
+SET embedding_run = er.name
+FROM embedding_run er
+WHERE cr.name ~ '-(online-nearest-neighbor|batch-kmeans|batch-dbscan)(-[0-9]{14})?$'
+AND er.name = regexp_replace(cr.name, '-(online-nearest-neighbor|batch-kmeans|batch-dbscan)(-[0-9]{14})?$', '');
+
+UPDATE clustering_run
+SET algorithm = substring(name FROM '(online-nearest-neighbor|batch-kmeans|batch-dbscan)')
+WHERE name ~ '(online-nearest-neighbor|batch-kmeans|batch-dbscan)';
+
+UPDATE clustering_run
+SET status = 'running'
+WHERE algorithm LIKE 'online-%';
+
+ALTER TABLE preprocessed_page_embedding
+    ADD CONSTRAINT fk_preprocessed_page_embedding_embedding_run
+        FOREIGN KEY (embedding_run) REFERENCES embedding_run(name);
+
+ALTER TABLE cluster
+    ADD CONSTRAINT fk_cluster_clustering_run
+        FOREIGN KEY (clustering_run) REFERENCES clustering_run(name);
@@ -0,0 +1,22 @@
+-- Running mean of each cluster's member embeddings, kept up to date as pages are assigned
+CREATE TABLE cluster_centroid (
+    cluster_id UUID PRIMARY KEY REFERENCES cluster(id) ON DELETE CASCADE,
+    clustering_run TEXT NOT NULL REFERENCES clustering_run(name),
+    centroid vector(384) NOT NULL,
+    member_count INTEGER NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO cluster_centroid (cluster_id, clustering_run, centroid, member_count)
+SELECT ca.cluster_id, ca.clustering_run, AVG(ppe.embedding), COUNT(*)
+FROM cluster_assignment ca
+JOIN clustering_run cr ON cr.name = ca.clustering_run
+JOIN preprocessed_page_embedding ppe ON ppe.page_id = ca.page_id
+    AND ppe.embedding_run IS NOT DISTINCT FROM cr.embedding_run
+WHERE ppe.embedding IS NOT NULL
+GROUP BY ca.cluster_id, ca.clustering_run;
+
+-- Lookups are always within one run. A single HNSW index over every run's centroids would only
+-- filter by run after finding neighbors, losing recall as runs multiply, so vector indexes are
+-- per run.
+CREATE INDEX cluster_centroid_clustering_run_idx ON cluster_centroid (clustering_run);
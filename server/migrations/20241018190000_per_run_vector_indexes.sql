@@ -0,0 +1,46 @@
+-- Embeddings can come from models with any dimension, so the columns no longer fix one. Indexes
+-- need a fixed dimension, so they're partial indexes per run over a cast to that run's dimension.
+
+ALTER TABLE preprocessed_page_embedding
+    ALTER COLUMN embedding TYPE vector;
+
+ALTER TABLE cluster_centroid
+    ALTER COLUMN centroid TYPE vector;
+
+-- HNSW indexes support at most 2000 dimensions, larger runs fall back to exact scans. Index names
+-- are derived from a hash since run names can be arbitrary text. The operator class has to match
+-- the run's similarity metric for lookups to use the index.
+CREATE FUNCTION create_clustering_run_index(run TEXT, dimension INTEGER, operator_class TEXT) RETURNS VOID AS $$
+BEGIN
+    IF dimension > 2000 THEN
+        RAISE NOTICE 'Not indexing clustering run %, % dimensions is more than HNSW supports', run, dimension;
+        RETURN;
+    END IF;
+    IF operator_class NOT IN ('vector_cosine_ops', 'vector_l2_ops', 'vector_ip_ops') THEN
+        RAISE EXCEPTION 'Unknown operator class %', operator_class;
+    END IF;
+
+    EXECUTE format(
+        'CREATE INDEX IF NOT EXISTS %I ON cluster_centroid USING hnsw ((centroid::vector(%s)) %I) WHERE clustering_run = %L',
+        'cluster_centroid_' || left(md5(run), 16) || '_idx',
+        dimension,
+        operator_class,
+        run
+    );
+END;
+$$ LANGUAGE plpgsql;
+
+-- Only online runs look up nearest centroids
+SELECT create_clustering_run_index(
+    cr.name,
+    er.embedding_dimension,
+    CASE cr.parameters->>'similarity_metric'
+        WHEN 'l2' THEN 'vector_l2_ops'
+        WHEN 'inner_product' THEN 'vector_ip_ops'
+        ELSE 'vector_cosine_ops'
+    END
+)
+FROM clustering_run cr
+JOIN embedding_run er ON er.name = cr.embedding_run
+WHERE cr.status = 'running'
+AND er.embedding_dimension IS NOT NULL;
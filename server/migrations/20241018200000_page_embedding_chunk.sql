@@ -0,0 +1,12 @@
+-- Embeddings of the individual chunks a page's embedding was pooled from, for pipelines that keep
+-- them
+CREATE TABLE page_embedding_chunk (
+    id SERIAL PRIMARY KEY,
+    page_id INTEGER NOT NULL REFERENCES page(id) ON DELETE CASCADE,
+    embedding_run TEXT NOT NULL REFERENCES embedding_run(name),
+    chunk_index INTEGER NOT NULL,
+    contents TEXT NOT NULL,
+    embedding vector NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (page_id, embedding_run, chunk_index)
+);
@@ -0,0 +1,5 @@
+-- Embedding runs now record the fastembed model code rather than the upstream model name, which
+-- refer to the same weights
+UPDATE embedding_run
+SET model_id = 'Qdrant/all-MiniLM-L6-v2-onnx'
+WHERE model_id = 'sentence-transformers/all-MiniLM-L6-v2';
@@ -0,0 +1,11 @@
+-- One embedding per page and run, so re-embedding a page replaces its embedding. Keep the newest
+-- of any duplicates left over from retried jobs.
+DELETE FROM preprocessed_page_embedding ppe
+USING preprocessed_page_embedding newer
+WHERE newer.page_id = ppe.page_id
+AND newer.embedding_run = ppe.embedding_run
+AND newer.id > ppe.id;
+
+ALTER TABLE preprocessed_page_embedding
+    ADD CONSTRAINT preprocessed_page_embedding_page_id_embedding_run_key
+        UNIQUE (page_id, embedding_run);
@@ -0,0 +1,81 @@
+-- Every distinct version of a page's contents, so pages that change over time aren't stuck with
+-- their first capture. `page.contents` keeps mirroring the current snapshot.
+CREATE TABLE page_snapshot (
+    id SERIAL PRIMARY KEY,
+    page_id INTEGER NOT NULL REFERENCES page(id) ON DELETE CASCADE,
+    -- Hex encoded sha256 of the contents
+    content_hash TEXT NOT NULL,
+    contents TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (page_id, content_hash)
+);
+
+ALTER TABLE page
+    ADD COLUMN current_snapshot_id INTEGER REFERENCES page_snapshot(id) ON DELETE SET NULL;
+
+ALTER TABLE browse_event
+    ADD COLUMN page_snapshot_id INTEGER REFERENCES page_snapshot(id) ON DELETE SET NULL;
+
+ALTER TABLE preprocessed_page_embedding
+    ADD COLUMN page_snapshot_id INTEGER REFERENCES page_snapshot(id) ON DELETE CASCADE;
+
+ALTER TABLE page_embedding_chunk
+    ADD COLUMN page_snapshot_id INTEGER REFERENCES page_snapshot(id) ON DELETE CASCADE;
+
+ALTER TABLE page_processing_job
+    ADD COLUMN page_snapshot_id INTEGER REFERENCES page_snapshot(id) ON DELETE CASCADE;
+
+-- Until now every page had at most one version of its contents
+INSERT INTO page_snapshot (page_id, content_hash, contents, created_at)
+SELECT id, encode(sha256(convert_to(contents, 'UTF8')), 'hex'), contents,
+    COALESCE(created_at, CURRENT_TIMESTAMP)
+FROM page
+WHERE contents IS NOT NULL;
+
+UPDATE page
+SET current_snapshot_id = ps.id
+FROM page_snapshot ps
+WHERE ps.page_id = page.id;
+
+UPDATE browse_event be
+SET page_snapshot_id = page.current_snapshot_id
+FROM page
+WHERE page.url = be.page_url;
+
+UPDATE preprocessed_page_embedding ppe
+SET page_snapshot_id = page.current_snapshot_id
+FROM page
+WHERE page.id = ppe.page_id;
+
+UPDATE page_embedding_chunk pec
+SET page_snapshot_id = page.current_snapshot_id
+FROM page
+WHERE page.id = pec.page_id;
+
+UPDATE page_processing_job ppj
+SET page_snapshot_id = page.current_snapshot_id
+FROM page
+WHERE page.id = ppj.page_id;
+
+-- Without contents there's nothing these could have been computed from
+DELETE FROM preprocessed_page_embedding WHERE page_snapshot_id IS NULL;
+DELETE FROM page_embedding_chunk WHERE page_snapshot_id IS NULL;
+DELETE FROM page_processing_job WHERE page_snapshot_id IS NULL;
+
+ALTER TABLE preprocessed_page_embedding
+    ALTER COLUMN page_snapshot_id SET NOT NULL,
+    DROP CONSTRAINT preprocessed_page_embedding_page_id_embedding_run_key,
+    ADD CONSTRAINT preprocessed_page_embedding_page_snapshot_id_embedding_run_key
+        UNIQUE (page_snapshot_id, embedding_run);
+
+ALTER TABLE page_embedding_chunk
+    ALTER COLUMN page_snapshot_id SET NOT NULL,
+    DROP CONSTRAINT page_embedding_chunk_page_id_embedding_run_chunk_index_key,
+    ADD CONSTRAINT page_embedding_chunk_page_snapshot_id_embedding_run_chunk_index_key
+        UNIQUE (page_snapshot_id, embedding_run, chunk_index);
+
+ALTER TABLE page_processing_job
+    ALTER COLUMN page_snapshot_id SET NOT NULL;
+
+CREATE INDEX preprocessed_page_embedding_page_id_idx ON preprocessed_page_embedding (page_id);
+CREATE INDEX browse_event_page_snapshot_id_idx ON browse_event (page_snapshot_id);
@@ -0,0 +1,160 @@
+-- Folds a duplicate page into the page it duplicates and deletes it. Snapshots with contents the
+-- page already has are replaced by the page's own, keeping whichever embeddings it was missing.
+-- Runs both pages were assigned in keep the page's assignment, and the clusters that lose the
+-- duplicate get their centroids recomputed.
+CREATE FUNCTION merge_duplicate_page(duplicate_id INTEGER, canonical_id INTEGER) RETURNS VOID AS $$
+DECLARE
+    affected_clusters UUID[];
+BEGIN
+    UPDATE browse_event be
+    SET page_snapshot_id = kept.id
+    FROM page_snapshot dup
+    JOIN page_snapshot kept ON kept.content_hash = dup.content_hash AND kept.page_id = canonical_id
+    WHERE dup.page_id = duplicate_id AND be.page_snapshot_id = dup.id;
+
+    UPDATE preprocessed_page_embedding ppe
+    SET page_snapshot_id = kept.id, page_id = canonical_id
+    FROM page_snapshot dup
+    JOIN page_snapshot kept ON kept.content_hash = dup.content_hash AND kept.page_id = canonical_id
+    WHERE dup.page_id = duplicate_id AND ppe.page_snapshot_id = dup.id
+    AND NOT EXISTS (
+        SELECT 1 FROM preprocessed_page_embedding existing
+        WHERE existing.page_snapshot_id = kept.id AND existing.embedding_run = ppe.embedding_run
+    );
+
+    UPDATE page_embedding_chunk pec
+    SET page_snapshot_id = kept.id, page_id = canonical_id
+    FROM page_snapshot dup
+    JOIN page_snapshot kept ON kept.content_hash = dup.content_hash AND kept.page_id = canonical_id
+    WHERE dup.page_id = duplicate_id AND pec.page_snapshot_id = dup.id
+    AND NOT EXISTS (
+        SELECT 1 FROM page_embedding_chunk existing
+        WHERE existing.page_snapshot_id = kept.id AND existing.embedding_run = pec.embedding_run
+    );
+
+    UPDATE page
+    SET current_snapshot_id = kept.id
+    FROM page_snapshot dup
+    JOIN page_snapshot kept ON kept.content_hash = dup.content_hash AND kept.page_id = canonical_id
+    WHERE page.id = duplicate_id AND page.current_snapshot_id = dup.id;
+
+    -- The page keeps whichever of the two current snapshots is newer
+    UPDATE page
+    SET current_snapshot_id = newest.id, contents = newest.contents
+    FROM (
+        SELECT ps.id, ps.contents
+        FROM page p
+        JOIN page_snapshot ps ON ps.id = p.current_snapshot_id
+        WHERE p.id IN (duplicate_id, canonical_id)
+        ORDER BY ps.created_at DESC, ps.id DESC
+        LIMIT 1
+    ) newest
+    WHERE page.id = canonical_id;
+
+    -- Whatever wasn't moved over cascades
+    DELETE FROM page_snapshot dup
+    USING page_snapshot kept
+    WHERE dup.page_id = duplicate_id
+    AND kept.page_id = canonical_id
+    AND kept.content_hash = dup.content_hash;
+
+    UPDATE page_snapshot SET page_id = canonical_id WHERE page_id = duplicate_id;
+    UPDATE preprocessed_page_embedding SET page_id = canonical_id WHERE page_id = duplicate_id;
+    UPDATE page_embedding_chunk SET page_id = canonical_id WHERE page_id = duplicate_id;
+    UPDATE page_processing_job SET page_id = canonical_id WHERE page_id = duplicate_id;
+
+    WITH removed AS (
+        DELETE FROM cluster_assignment ca
+        USING cluster_assignment kept
+        WHERE ca.page_id = duplicate_id
+        AND kept.page_id = canonical_id
+        AND kept.clustering_run = ca.clustering_run
+        RETURNING ca.cluster_id
+    )
+    SELECT array_agg(DISTINCT cluster_id) INTO affected_clusters FROM removed;
+
+    UPDATE cluster_assignment SET page_id = canonical_id WHERE page_id = duplicate_id;
+
+    UPDATE cluster_centroid cc
+    SET centroid = members.centroid,
+        member_count = members.member_count,
+        updated_at = CURRENT_TIMESTAMP
+    FROM (
+        SELECT ca.cluster_id, AVG(latest.embedding) AS centroid, COUNT(*)::INTEGER AS member_count
+        FROM cluster_assignment ca
+        JOIN clustering_run cr ON cr.name = ca.clustering_run
+        JOIN LATERAL (
+            SELECT ppe.embedding
+            FROM preprocessed_page_embedding ppe
+            WHERE ppe.page_id = ca.page_id AND ppe.embedding_run = cr.embedding_run
+            ORDER BY ppe.page_snapshot_id DESC
+            LIMIT 1
+        ) latest ON true
+        WHERE ca.cluster_id = ANY(affected_clusters)
+        GROUP BY ca.cluster_id
+    ) members
+    WHERE cc.cluster_id = members.cluster_id;
+
+    -- Clusters the duplicate was the only member of
+    DELETE FROM cluster c
+    WHERE c.id = ANY(affected_clusters)
+    AND NOT EXISTS (SELECT 1 FROM cluster_assignment ca WHERE ca.cluster_id = c.id);
+
+    DELETE FROM page WHERE id = duplicate_id;
+END;
+$$ LANGUAGE plpgsql;
+
+-- The canonicalizer's built in rules: no fragment, no trailing slash, and no tracking or session
+-- params. Per-domain rules from the config are applied by the `canonicalize` command.
+CREATE FUNCTION canonical_page_url(url TEXT) RETURNS TEXT AS $$
+DECLARE
+    base TEXT;
+    query TEXT;
+BEGIN
+    IF url !~* '^https?://' THEN
+        RETURN url;
+    END IF;
+
+    base := split_part(url, '#', 1);
+    query := substring(base FROM '\?(.*)$');
+    base := regexp_replace(split_part(base, '?', 1), '^([^/]+//[^/]+/.*?)/+$', '\1');
+
+    SELECT string_agg(param, '&' ORDER BY param_index)
+    INTO query
+    FROM regexp_split_to_table(query, '&') WITH ORDINALITY AS params(param, param_index)
+    WHERE param <> ''
+    AND lower(split_part(param, '=', 1)) !~ '^(utm_.*|fbclid|gclid|dclid|gbraid|wbraid|msclkid|mc_cid|mc_eid|igshid|yclid|_ga|_gl|_hsenc|_hsmi|sessionid|session_id|jsessionid|phpsessid)$';
+
+    IF query IS NULL THEN
+        RETURN base;
+    END IF;
+    RETURN base || '?' || query;
+END;
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+-- A page already stored under the canonical url is kept, otherwise the oldest
+CREATE TEMP TABLE page_canonical_url AS
+SELECT
+    id,
+    canonical_url,
+    FIRST_VALUE(id) OVER (
+        PARTITION BY canonical_url
+        ORDER BY url = canonical_url DESC, id
+    ) AS canonical_id
+FROM (SELECT id, url, canonical_page_url(url) AS canonical_url FROM page) pages;
+
+SELECT merge_duplicate_page(id, canonical_id)
+FROM page_canonical_url
+WHERE id <> canonical_id;
+
+UPDATE page
+SET url = pcu.canonical_url
+FROM page_canonical_url pcu
+WHERE pcu.id = page.id AND page.url <> pcu.canonical_url;
+
+UPDATE browse_event
+SET page_url = canonical_page_url(page_url)
+WHERE page_url <> canonical_page_url(page_url);
+
+DROP TABLE page_canonical_url;
+DROP FUNCTION canonical_page_url(TEXT);
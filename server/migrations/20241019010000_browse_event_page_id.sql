@@ -0,0 +1,115 @@
+-- Events were matched to pages by joining on the url, which is unindexed text
+ALTER TABLE browse_event
+    ADD COLUMN page_id INTEGER REFERENCES page(id) ON DELETE SET NULL;
+
+UPDATE browse_event be
+SET page_id = page.id
+FROM page
+WHERE page.url = be.page_url;
+
+CREATE INDEX browse_event_page_id_idx ON browse_event (page_id);
+CREATE INDEX browse_event_timestamp_idx ON browse_event (timestamp);
+
+-- Merged pages now also take over the duplicate's events
+CREATE OR REPLACE FUNCTION merge_duplicate_page(duplicate_id INTEGER, canonical_id INTEGER) RETURNS VOID AS $$
+DECLARE
+    affected_clusters UUID[];
+BEGIN
+    UPDATE browse_event be
+    SET page_snapshot_id = kept.id
+    FROM page_snapshot dup
+    JOIN page_snapshot kept ON kept.content_hash = dup.content_hash AND kept.page_id = canonical_id
+    WHERE dup.page_id = duplicate_id AND be.page_snapshot_id = dup.id;
+
+    UPDATE preprocessed_page_embedding ppe
+    SET page_snapshot_id = kept.id, page_id = canonical_id
+    FROM page_snapshot dup
+    JOIN page_snapshot kept ON kept.content_hash = dup.content_hash AND kept.page_id = canonical_id
+    WHERE dup.page_id = duplicate_id AND ppe.page_snapshot_id = dup.id
+    AND NOT EXISTS (
+        SELECT 1 FROM preprocessed_page_embedding existing
+        WHERE existing.page_snapshot_id = kept.id AND existing.embedding_run = ppe.embedding_run
+    );
+
+    UPDATE page_embedding_chunk pec
+    SET page_snapshot_id = kept.id, page_id = canonical_id
+    FROM page_snapshot dup
+    JOIN page_snapshot kept ON kept.content_hash = dup.content_hash AND kept.page_id = canonical_id
+    WHERE dup.page_id = duplicate_id AND pec.page_snapshot_id = dup.id
+    AND NOT EXISTS (
+        SELECT 1 FROM page_embedding_chunk existing
+        WHERE existing.page_snapshot_id = kept.id AND existing.embedding_run = pec.embedding_run
+    );
+
+    UPDATE page
+    SET current_snapshot_id = kept.id
+    FROM page_snapshot dup
+    JOIN page_snapshot kept ON kept.content_hash = dup.content_hash AND kept.page_id = canonical_id
+    WHERE page.id = duplicate_id AND page.current_snapshot_id = dup.id;
+
+    -- The page keeps whichever of the two current snapshots is newer
+    UPDATE page
+    SET current_snapshot_id = newest.id, contents = newest.contents
+    FROM (
+        SELECT ps.id, ps.contents
+        FROM page p
+        JOIN page_snapshot ps ON ps.id = p.current_snapshot_id
+        WHERE p.id IN (duplicate_id, canonical_id)
+        ORDER BY ps.created_at DESC, ps.id DESC
+        LIMIT 1
+    ) newest
+    WHERE page.id = canonical_id;
+
+    -- Whatever wasn't moved over cascades
+    DELETE FROM page_snapshot dup
+    USING page_snapshot kept
+    WHERE dup.page_id = duplicate_id
+    AND kept.page_id = canonical_id
+    AND kept.content_hash = dup.content_hash;
+
+    UPDATE page_snapshot SET page_id = canonical_id WHERE page_id = duplicate_id;
+    UPDATE preprocessed_page_embedding SET page_id = canonical_id WHERE page_id = duplicate_id;
+    UPDATE page_embedding_chunk SET page_id = canonical_id WHERE page_id = duplicate_id;
+    UPDATE page_processing_job SET page_id = canonical_id WHERE page_id = duplicate_id;
+    UPDATE browse_event SET page_id = canonical_id WHERE page_id = duplicate_id;
+
+    WITH removed AS (
+        DELETE FROM cluster_assignment ca
+        USING cluster_assignment kept
+        WHERE ca.page_id = duplicate_id
+        AND kept.page_id = canonical_id
+        AND kept.clustering_run = ca.clustering_run
+        RETURNING ca.cluster_id
+    )
+    SELECT array_agg(DISTINCT cluster_id) INTO affected_clusters FROM removed;
+
+    UPDATE cluster_assignment SET page_id = canonical_id WHERE page_id = duplicate_id;
+
+    UPDATE cluster_centroid cc
+    SET centroid = members.centroid,
+        member_count = members.member_count,
+        updated_at = CURRENT_TIMESTAMP
+    FROM (
+        SELECT ca.cluster_id, AVG(latest.embedding) AS centroid, COUNT(*)::INTEGER AS member_count
+        FROM cluster_assignment ca
+        JOIN clustering_run cr ON cr.name = ca.clustering_run
+        JOIN LATERAL (
+            SELECT ppe.embedding
+            FROM preprocessed_page_embedding ppe
+            WHERE ppe.page_id = ca.page_id AND ppe.embedding_run = cr.embedding_run
+            ORDER BY ppe.page_snapshot_id DESC
+            LIMIT 1
+        ) latest ON true
+        WHERE ca.cluster_id = ANY(affected_clusters)
+        GROUP BY ca.cluster_id
+    ) members
+    WHERE cc.cluster_id = members.cluster_id;
+
+    -- Clusters the duplicate was the only member of
+    DELETE FROM cluster c
+    WHERE c.id = ANY(affected_clusters)
+    AND NOT EXISTS (SELECT 1 FROM cluster_assignment ca WHERE ca.cluster_id = c.id);
+
+    DELETE FROM page WHERE id = duplicate_id;
+END;
+$$ LANGUAGE plpgsql;
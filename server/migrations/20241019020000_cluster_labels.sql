@@ -0,0 +1,32 @@
+-- Clusters were named once, from the keywords of their first page. Labels are now recomputed from
+-- all member pages whenever membership changes. `name` is the label unless the user pinned a name.
+ALTER TABLE cluster
+    ADD COLUMN name_pinned BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN label TEXT,
+    -- Bumped every time the label is recomputed
+    ADD COLUMN label_version INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN labeled_at TIMESTAMP WITH TIME ZONE,
+    -- Bumped on every membership change. The label is stale while it was computed from an older
+    -- membership.
+    ADD COLUMN membership_version INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN labeled_membership_version INTEGER;
+
+CREATE FUNCTION bump_cluster_membership_version() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        UPDATE cluster SET membership_version = membership_version + 1 WHERE id = OLD.cluster_id;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        UPDATE cluster SET membership_version = membership_version + 1 WHERE id = NEW.cluster_id;
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER cluster_assignment_membership_version
+AFTER INSERT OR UPDATE OF cluster_id, page_id OR DELETE ON cluster_assignment
+FOR EACH ROW EXECUTE FUNCTION bump_cluster_membership_version();
+
+CREATE INDEX cluster_stale_label_idx ON cluster (clustering_run)
+WHERE labeled_membership_version IS DISTINCT FROM membership_version;
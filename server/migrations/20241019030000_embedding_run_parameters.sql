@@ -0,0 +1,4 @@
+-- The pipeline's steps and embedding settings, so a pipeline whose config changed can't keep adding
+-- to a run embedded differently. Runs registered before this adopt their pipeline's current config.
+ALTER TABLE embedding_run
+ADD COLUMN parameters JSONB NOT NULL DEFAULT '{}';
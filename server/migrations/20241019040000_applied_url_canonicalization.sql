@@ -0,0 +1,8 @@
+-- The url canonicalization config stored urls were last canonicalized with, so the server can tell
+-- when the config changed. Without a row only the built in rules, applied when urls were made
+-- canonical, have been applied.
+CREATE TABLE applied_url_canonicalization (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    config JSONB NOT NULL,
+    applied_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,6 @@
+-- The terms cluster labels are computed from, counted once per snapshot instead of re-extracting
+-- every member's contents whenever a cluster is relabeled. Snapshots without terms have `{}`.
+CREATE TABLE page_snapshot_term_counts (
+    page_snapshot_id INTEGER PRIMARY KEY REFERENCES page_snapshot(id) ON DELETE CASCADE,
+    term_counts JSONB NOT NULL
+);
@@ -0,0 +1,9 @@
+-- Runs registered before their parameters were recorded were embedded by older code, e.g. only the
+-- first 256 tokens of each page without chunking or pooling. Rather than adopting their pipeline's
+-- current parameters, they're marked as legacy, so a pipeline still using one has to be renamed.
+UPDATE embedding_run
+SET parameters = '{"legacy": true}'
+WHERE parameters = '{}';
+
+ALTER TABLE embedding_run
+ALTER COLUMN parameters DROP DEFAULT;
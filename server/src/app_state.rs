@@ -0,0 +1,20 @@
+use std::sync::Arc;
+
+use axum::extract::FromRef;
+use sqlx::PgPool;
+
+use crate::services::{
+    page_snapshots::SnapshotTextExtractor, preprocessing::pipeline::PreprocessingPipeline,
+    url_canonicalization::UrlCanonicalizer, url_rules::SharedUrlRules,
+};
+
+/// Shared across handlers. Pipelines hold loaded embedding models, so they're built once at
+/// startup instead of per request.
+#[derive(Clone, FromRef)]
+pub struct AppState {
+    pub db: PgPool,
+    pub preprocessing_pipelines: Arc<Vec<PreprocessingPipeline>>,
+    pub url_rules: SharedUrlRules,
+    pub url_canonicalizer: Arc<UrlCanonicalizer>,
+    pub snapshot_text_extractor: Arc<SnapshotTextExtractor>,
+}
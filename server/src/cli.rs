@@ -0,0 +1,113 @@
+use anyhow::{Context, Error};
+use clap::{Parser, Subcommand};
+use sqlx::PgPool;
+
+use crate::services::{
+    backfill::backfill_pipeline,
+    benchmark::benchmark_nearest_cluster,
+    clustering::{batch::run_batch_clustering, cluster_algorithms::get_cluster_algorithm},
+    preprocessing::{pipeline_config::PipelinesConfig, pipelines::get_preprocessing_pipeline},
+    url_canonicalization::{apply_url_canonicalization_config, UrlCanonicalizationConfig},
+    url_rules::{load_url_rules, purge_url_rule_matches},
+};
+
+#[derive(Parser)]
+#[command(about = "Browsing analysis server")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the HTTP server (the default when no subcommand is given)
+    Serve,
+    /// Recluster every stored embedding of a pipeline into a fresh batch clustering run
+    Recluster {
+        /// Preprocessing pipeline whose embeddings should be clustered, e.g. `direct-minilm`
+        #[arg(long)]
+        pipeline: String,
+        /// Batch clustering algorithm, e.g. `batch-kmeans` or `batch-dbscan`
+        #[arg(long)]
+        algorithm: String,
+    },
+    /// Embed stored pages a pipeline hasn't embedded yet, e.g. after adding it, then cluster them.
+    /// Safe to rerun after an interruption, it picks up where it stopped.
+    Backfill {
+        /// Configured preprocessing pipeline to run, e.g. `main-content-minilm`
+        #[arg(long)]
+        pipeline: String,
+        /// Pages embedded at the same time
+        #[arg(long, default_value_t = 2)]
+        concurrency: usize,
+        /// Batch clustering algorithm to recluster with afterwards. Without it, pages are assigned
+        /// in the pipeline's online clustering runs.
+        #[arg(long)]
+        algorithm: Option<String>,
+    },
+    /// Apply the current url rules to already stored events and pages
+    Purge,
+    /// Apply the url canonicalization config to already stored events and pages, merging pages
+    /// that turn out to be the same
+    Canonicalize,
+    /// Time nearest-cluster lookups against synthetic pages, without keeping any of them
+    Benchmark {
+        #[arg(long, default_value_t = 100_000)]
+        pages: i32,
+        #[arg(long, default_value_t = 200)]
+        queries: usize,
+        #[arg(long, default_value_t = 384)]
+        dimension: i32,
+    },
+}
+
+pub async fn recluster(db: &PgPool, pipeline: &str, algorithm_name: &str) -> Result<(), Error> {
+    let algorithm = get_cluster_algorithm(algorithm_name)
+        .with_context(|| format!("Unknown clustering algorithm {}", algorithm_name))?;
+
+    let clustering_run = run_batch_clustering(db, pipeline, algorithm.as_ref()).await?;
+    println!("Created clustering run {}", clustering_run);
+
+    Ok(())
+}
+
+pub async fn backfill(
+    db: &PgPool,
+    pipelines_config: &PipelinesConfig,
+    pipeline: &str,
+    concurrency: usize,
+    algorithm: Option<&str>,
+) -> Result<(), Error> {
+    let preprocessing_pipeline = get_preprocessing_pipeline(pipelines_config, pipeline)?;
+    let summary = backfill_pipeline(db, preprocessing_pipeline, concurrency, algorithm).await?;
+    println!("Backfilled {}: {:?}", pipeline, summary);
+
+    Ok(())
+}
+
+pub async fn purge(db: &PgPool) -> Result<(), Error> {
+    let url_rules = load_url_rules(db).await?;
+    let summary = purge_url_rule_matches(db, &url_rules).await?;
+    println!("Purged url rule matches: {:?}", summary);
+
+    Ok(())
+}
+
+pub async fn canonicalize(
+    db: &PgPool,
+    url_canonicalization_config: &UrlCanonicalizationConfig,
+) -> Result<(), Error> {
+    let summary = apply_url_canonicalization_config(db, url_canonicalization_config).await?;
+    println!("Canonicalized stored urls: {:?}", summary);
+
+    Ok(())
+}
+
+pub async fn benchmark(
+    db: &PgPool,
+    pages: i32,
+    queries: usize,
+    dimension: i32,
+) -> Result<(), Error> {
+    benchmark_nearest_cluster(db, pages, queries, dimension).await
+}
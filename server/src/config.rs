@@ -1,11 +1,14 @@
 use dotenv::dotenv;
-use std::env;
+use std::{env, path::PathBuf};
 
 pub struct Config {
     pub database_url: String,
     pub frontend_url: String,
     pub extension_url: String,
     pub server_address: String,
+    pub ingestion_workers: usize,
+    pub pipelines_config: PathBuf,
+    pub url_canonicalization_config: PathBuf,
 }
 
 pub fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
@@ -15,11 +18,25 @@ pub fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
     let frontend_url = env::var("FRONTEND_URL").unwrap_or_else(|_| "0.0.0.0:5173".to_string());
     let extension_url = env::var("EXTENSION_URL")?;
     let server_address = env::var("SERVER_ADDRESS").unwrap_or_else(|_| "0.0.0.0:8000".to_string());
+    let ingestion_workers = match env::var("INGESTION_WORKERS") {
+        Ok(ingestion_workers) => ingestion_workers.parse()?,
+        Err(_) => 2,
+    };
+    let pipelines_config = PathBuf::from(
+        env::var("PIPELINES_CONFIG").unwrap_or_else(|_| "pipelines.toml".to_string()),
+    );
+    let url_canonicalization_config = PathBuf::from(
+        env::var("URL_CANONICALIZATION_CONFIG")
+            .unwrap_or_else(|_| "url_canonicalization.toml".to_string()),
+    );
 
     Ok(Config {
         database_url,
         frontend_url,
         extension_url,
         server_address,
+        ingestion_workers,
+        pipelines_config,
+        url_canonicalization_config,
     })
 }
@@ -1,4 +1,10 @@
+pub mod benchmark;
 pub mod browse_event;
 pub mod cluster;
 pub mod page;
+pub mod page_processing_job;
+pub mod page_snapshot;
 pub mod preprocessed_page_embedding;
+pub mod run;
+pub mod url_canonicalization;
+pub mod url_rule;
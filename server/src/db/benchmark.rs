@@ -0,0 +1,67 @@
+use sqlx::{Error, PgConnection};
+
+/// Inserts `num_pages` pages with random embeddings into `embedding_run`, each in its own cluster of
+/// `clustering_run`, which is the worst case for centroid lookups.
+pub async fn insert_benchmark_pages(
+    conn: &mut PgConnection,
+    embedding_run: &str,
+    clustering_run: &str,
+    num_pages: i32,
+    embedding_dimension: i32,
+) -> Result<(), Error> {
+    // The correlated subquery makes Postgres draw a new random vector for every page
+    sqlx::query(
+        r#"
+        WITH pages AS (
+            INSERT INTO page (url)
+            SELECT 'https://benchmark.invalid/' || $1 || '/' || i FROM generate_series(1, $2) i
+            RETURNING id
+        ), snapshots AS (
+            INSERT INTO page_snapshot (page_id, content_hash, contents)
+            SELECT id, encode(sha256(convert_to('', 'UTF8')), 'hex'), '' FROM pages
+            RETURNING id, page_id
+        )
+        INSERT INTO preprocessed_page_embedding (page_id, page_snapshot_id, embedding_run, embedding)
+        SELECT page_id, id, $1, (SELECT array_agg(random() - 0.5) FROM generate_series(1, $3) WHERE snapshots.id IS NOT NULL)::vector
+        FROM snapshots
+        "#,
+    )
+    .bind(embedding_run)
+    .bind(num_pages)
+    .bind(embedding_dimension)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        WITH embeddings AS (
+            SELECT page_id, embedding, gen_random_uuid() AS cluster_id
+            FROM preprocessed_page_embedding
+            WHERE embedding_run = $1
+        ), clusters AS (
+            INSERT INTO cluster (id, name, clustering_run)
+            SELECT cluster_id, 'benchmark', $2 FROM embeddings
+        ), assignments AS (
+            INSERT INTO cluster_assignment (cluster_id, page_id, clustering_run)
+            SELECT cluster_id, page_id, $2 FROM embeddings
+        )
+        INSERT INTO cluster_centroid (cluster_id, clustering_run, centroid, member_count)
+        SELECT cluster_id, $2, embedding, 1 FROM embeddings
+        "#,
+    )
+    .bind(embedding_run)
+    .bind(clustering_run)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Forces exact scans for the rest of the transaction, to compare against the indexed lookups
+pub async fn disable_index_scans(conn: &mut PgConnection) -> Result<(), Error> {
+    sqlx::query("SET LOCAL enable_indexscan = off")
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
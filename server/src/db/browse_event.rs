@@ -1,42 +1,151 @@
+use chrono::{DateTime, Utc};
 use futures::TryStreamExt;
-use sqlx::{Error, PgPool};
+use sqlx::{Error, PgExecutor, PgPool};
 
 use crate::models::browse_event::{
-    BrowseEventFromChromeExtension, BrowseEventRow, BrowseEventRowWithCluster,
+    BrowseEventFromChromeExtension, BrowseEventRow, BrowseEventRowWithCluster, BrowseEventType,
 };
 
+/// Links the event to its page, and to the version of the page it saw if there is one. Returns
+/// `None` when an event with the same client event id was already logged.
 pub async fn insert_browse_event(
-    db: &PgPool,
+    db: impl PgExecutor<'_>,
     browse_event: &BrowseEventFromChromeExtension,
-) -> Result<BrowseEventRow, Error> {
+    page_id: Option<i32>,
+    page_snapshot_id: Option<i32>,
+) -> Result<Option<BrowseEventRow>, Error> {
     sqlx::query_as!(
         BrowseEventRow,
         r#"
-        INSERT INTO browse_event (timestamp, tab_id, page_url, page_title, event_type) 
-        VALUES ($1, $2, $3, $4, $5)
-        RETURNING *
+        INSERT INTO browse_event (timestamp, tab_id, page_url, page_title, event_type, client_event_id, page_id, page_snapshot_id) 
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (client_event_id) DO NOTHING
+        RETURNING id, timestamp, tab_id, page_id, page_url, page_title, event_type AS "event_type: BrowseEventType", client_event_id
         "#,
         browse_event.timestamp,
         browse_event.tab_id,
         browse_event.page_url,
         browse_event.page_title,
-        browse_event.event_type
+        browse_event.event_type as BrowseEventType,
+        browse_event.event_id,
+        page_id,
+        page_snapshot_id
     )
-    .fetch_one(db)
+    .fetch_optional(db)
+    .await
+}
+
+/// The title the page was most recently logged with, if any event kept one
+pub async fn get_latest_page_title(db: &PgPool, page_id: i32) -> Result<Option<String>, Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT page_title FROM browse_event
+        WHERE page_id = $1 AND page_title <> '' AND page_title <> page_url
+        ORDER BY timestamp DESC
+        LIMIT 1
+        "#,
+        page_id
+    )
+    .fetch_optional(db)
     .await
 }
 
 pub async fn get_all_browse_events(db: &PgPool) -> Result<Vec<BrowseEventRowWithCluster>, Error> {
-    // TODO: should we log the page_id in browse events rather than the url?
     let stream = sqlx::query_as!(
         BrowseEventRowWithCluster,
         r#"
-        SELECT browse_event.id as id, timestamp, tab_id, browse_event.page_url as page_url, page_title, ca.cluster_id as page_cluster_id, event_type FROM browse_event
-        LEFT JOIN page ON browse_event.page_url = page.url
-        LEFT JOIN cluster_assignment ca ON page.id = ca.page_id
+        SELECT browse_event.id as id, timestamp, tab_id, browse_event.page_url as page_url, page_title, ca.cluster_id as page_cluster_id, event_type AS "event_type: BrowseEventType" FROM browse_event
+        LEFT JOIN cluster_assignment ca ON browse_event.page_id = ca.page_id
         "#
     )
     .fetch(db);
 
     stream.try_collect::<Vec<_>>().await
 }
+
+pub async fn get_browse_events_in_range(
+    db: &PgPool,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<BrowseEventRow>, Error> {
+    let stream = sqlx::query_as!(
+        BrowseEventRow,
+        r#"
+        SELECT id, timestamp, tab_id, page_id, page_url, page_title, event_type AS "event_type: BrowseEventType", client_event_id FROM browse_event
+        WHERE timestamp >= $1 AND timestamp < $2
+        ORDER BY timestamp, id
+        "#,
+        start,
+        end
+    )
+    .fetch(db);
+
+    stream.try_collect::<Vec<_>>().await
+}
+
+pub async fn get_browse_event_urls(db: &PgPool) -> Result<Vec<String>, Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT DISTINCT page_url FROM browse_event
+        "#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.page_url).collect())
+}
+
+pub async fn delete_browse_events_with_url(db: &PgPool, page_url: &str) -> Result<u64, Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM browse_event
+        WHERE page_url = $1
+        "#,
+        page_url
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn redact_browse_events_with_url(
+    db: &PgPool,
+    page_url: &str,
+    domain_url: &str,
+) -> Result<u64, Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE browse_event
+        SET page_url = $2, page_title = $2, page_id = NULL, page_snapshot_id = NULL
+        WHERE page_url = $1
+        "#,
+        page_url,
+        domain_url
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Moves events over to the url their page is now stored under, and to that page
+pub async fn rename_browse_events_url(
+    db: &PgPool,
+    page_url: &str,
+    canonical_url: &str,
+) -> Result<u64, Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE browse_event
+        SET page_url = $2, page_id = (SELECT id FROM page WHERE url = $2)
+        WHERE page_url = $1
+        "#,
+        page_url,
+        canonical_url
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
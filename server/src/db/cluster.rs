@@ -1,38 +1,29 @@
-use futures::TryStreamExt;
+use futures::{stream::BoxStream, TryStreamExt};
 use pgvector::Vector;
-use sqlx::{Error, PgPool};
+use sqlx::{Error, PgExecutor, PgPool};
+use uuid::Uuid;
 
-use crate::models::cluster::{ClusterAssignmentRow, ClusterRow, ClusteringRunRow};
-
-pub async fn check_cluster_exists(db: &PgPool, cluster_id: &str) -> Result<bool, Error> {
-    let check_row_exists_query_result = sqlx::query!(
-        r#"
-        SELECT COUNT(*) as num_clusters FROM cluster
-        WHERE id = $1
-        LIMIT 1
-        "#,
-        cluster_id
-    )
-    .fetch_one(db)
-    .await?;
-
-    Ok(check_row_exists_query_result.num_clusters.unwrap_or(0) >= 1)
-}
+use crate::models::{
+    cluster::{
+        ClusterAssignmentRow, ClusterRow, ClusterTermCountRow, PageClusterRow, SimilarityMetric,
+        StaleClusterRow,
+    },
+    PageSnapshotRow,
+};
 
+/// Cluster ids are generated by the database
 pub async fn insert_cluster(
-    db: &PgPool,
-    id: &str,
+    db: impl PgExecutor<'_>,
     name: &str,
     clustering_run: &str,
 ) -> Result<ClusterRow, Error> {
     sqlx::query_as!(
         ClusterRow,
         r#"
-        INSERT INTO cluster (id, name, clustering_run)
-        VALUES ($1, $2, $3)
-        RETURNING *
+        INSERT INTO cluster (name, clustering_run)
+        VALUES ($1, $2)
+        RETURNING id, name, clustering_run, name_pinned, label_version
         "#,
-        id,
         name,
         clustering_run
     )
@@ -40,30 +31,67 @@ pub async fn insert_cluster(
     .await
 }
 
+/// Also folds the page's embedding into the cluster's centroid as a running mean. Both happen in
+/// one statement, so concurrent assignments to the same cluster can't lose updates.
 pub async fn insert_cluster_assignment(
-    db: &PgPool,
+    db: impl PgExecutor<'_>,
     page_id: i32,
-    cluster_id: &str,
+    cluster_id: Uuid,
+    clustering_run: &str,
+    page_embedding: &Vector,
 ) -> Result<ClusterAssignmentRow, Error> {
-    sqlx::query_as!(
-        ClusterAssignmentRow,
+    sqlx::query_as(
         r#"
-        INSERT INTO cluster_assignment (cluster_id, page_id)
-        VALUES ($1, $2)
-        RETURNING *
+        WITH assignment AS (
+            INSERT INTO cluster_assignment (cluster_id, page_id, clustering_run)
+            VALUES ($1, $2, $3)
+            RETURNING id, page_id, cluster_id, clustering_run
+        ), centroid AS (
+            INSERT INTO cluster_centroid (cluster_id, clustering_run, centroid, member_count)
+            VALUES ($1, $3, $4, 1)
+            ON CONFLICT (cluster_id) DO UPDATE SET
+                centroid = cluster_centroid.centroid + (EXCLUDED.centroid - cluster_centroid.centroid)
+                    * array_fill((1.0 / (cluster_centroid.member_count + 1))::float8, ARRAY[vector_dims(cluster_centroid.centroid)])::vector,
+                member_count = cluster_centroid.member_count + 1,
+                updated_at = CURRENT_TIMESTAMP
+        )
+        SELECT id, page_id, cluster_id, clustering_run FROM assignment
         "#,
-        cluster_id,
-        page_id
     )
+    .bind(cluster_id)
+    .bind(page_id)
+    .bind(clustering_run)
+    .bind(page_embedding)
     .fetch_one(db)
     .await
 }
 
+pub async fn check_page_assigned_in_run(
+    db: &PgPool,
+    page_id: i32,
+    clustering_run: &str,
+) -> Result<bool, Error> {
+    let query_result = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM cluster_assignment
+            WHERE page_id = $1 AND clustering_run = $2
+        ) AS "assigned!"
+        "#,
+        page_id,
+        clustering_run
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(query_result.assigned)
+}
+
 pub async fn get_all_clusters(db: &PgPool) -> Result<Vec<ClusterRow>, Error> {
     let stream = sqlx::query_as!(
         ClusterRow,
         r#"
-        SELECT * FROM cluster
+        SELECT id, name, clustering_run, name_pinned, label_version FROM cluster
         "#
     )
     .fetch(db);
@@ -71,34 +99,195 @@ pub async fn get_all_clusters(db: &PgPool) -> Result<Vec<ClusterRow>, Error> {
     stream.try_collect::<Vec<_>>().await
 }
 
-pub async fn get_nearest_cluster_above_similarity_threshold(
-    db: &PgPool,
+/// `max_distance` is in the units of the metric's operator, see
+/// `SimilarityMetric::similarity_to_distance`
+pub async fn get_nearest_cluster_within_distance(
+    db: impl PgExecutor<'_>,
     page_embedding: &Vector,
-    embedding_run: &str,
-    cosine_similarity_threshold: f32,
-) -> Result<Option<ClusterAssignmentRow>, Error> {
-    sqlx::query_as(
+    clustering_run: &str,
+    similarity_metric: SimilarityMetric,
+    max_distance: f32,
+) -> Result<Option<Uuid>, Error> {
+    // Operators can't be bound as parameters, but they only ever come from `SimilarityMetric`.
+    // Casting to the run's dimension matches the expression of the run's partial HNSW index. The
+    // threshold is checked afterwards, since centroids of other runs can't be cast.
+    let query = format!(
         r#"
-        SELECT * FROM cluster_assignment ca
-        JOIN preprocessed_page_embedding ppe on ppe.page_id = ca.page_id 
-        WHERE ppe.embedding <=> $1 > $2
-        AND ppe.embedding_run = $3
-        ORDER BY ppe.embedding <=> $1 LIMIT 1
+        SELECT cluster_id, centroid::vector({dim}) {op} $1::vector({dim}) AS distance
+        FROM cluster_centroid
+        WHERE clustering_run = $2
+        ORDER BY centroid::vector({dim}) {op} $1::vector({dim}) LIMIT 1
         "#,
-    )
-    .bind(page_embedding)
-    .bind(cosine_similarity_threshold)
-    .bind(embedding_run)
-    .fetch_optional(db)
-    .await
+        dim = page_embedding.as_slice().len(),
+        op = similarity_metric.distance_operator()
+    );
+
+    // Partial indexes are only considered when the planner sees the run name, which isn't the case
+    // for the generic plans of cached statements
+    let nearest_cluster: Option<(Uuid, f64)> = sqlx::query_as(&query)
+        .persistent(false)
+        .bind(page_embedding)
+        .bind(clustering_run)
+        .fetch_optional(db)
+        .await?;
+
+    Ok(nearest_cluster
+        .filter(|(_, distance)| *distance <= f64::from(max_distance))
+        .map(|(cluster_id, _)| cluster_id))
 }
 
-pub async fn get_clustering_runs(db: &PgPool) -> Result<Vec<ClusteringRunRow>, Error> {
+pub async fn get_page_clusters(
+    db: &PgPool,
+    clustering_run: &str,
+) -> Result<Vec<PageClusterRow>, Error> {
     let stream = sqlx::query_as!(
-        ClusteringRunRow,
-        r#"SELECT DISTINCT(clustering_run) FROM cluster"#
+        PageClusterRow,
+        r#"
+        SELECT ca.page_id, c.id AS cluster_id, c.name AS cluster_name FROM cluster_assignment ca
+        JOIN cluster c ON ca.cluster_id = c.id
+        WHERE c.clustering_run = $1
+        "#,
+        clustering_run
     )
     .fetch(db);
 
     stream.try_collect::<Vec<_>>().await
 }
+
+pub async fn get_clustering_runs_with_stale_labels(db: &PgPool) -> Result<Vec<String>, Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT clustering_run FROM cluster
+        WHERE labeled_membership_version IS DISTINCT FROM membership_version
+        "#
+    )
+    .fetch_all(db)
+    .await
+}
+
+pub async fn get_stale_clusters(
+    db: &PgPool,
+    clustering_run: &str,
+) -> Result<Vec<StaleClusterRow>, Error> {
+    sqlx::query_as!(
+        StaleClusterRow,
+        r#"
+        SELECT id, membership_version FROM cluster
+        WHERE clustering_run = $1
+        AND labeled_membership_version IS DISTINCT FROM membership_version
+        "#,
+        clustering_run
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// The current snapshots of pages in the run whose terms haven't been counted yet
+pub fn stream_cluster_member_snapshots_without_term_counts<'a>(
+    db: &'a PgPool,
+    clustering_run: &'a str,
+) -> BoxStream<'a, Result<PageSnapshotRow, Error>> {
+    sqlx::query_as!(
+        PageSnapshotRow,
+        r#"
+        SELECT ps.* FROM cluster_assignment ca
+        JOIN page ON page.id = ca.page_id
+        JOIN page_snapshot ps ON ps.id = page.current_snapshot_id
+        WHERE ca.clustering_run = $1
+        AND NOT EXISTS (
+            SELECT 1 FROM page_snapshot_term_counts pstc WHERE pstc.page_snapshot_id = ps.id
+        )
+        "#,
+        clustering_run
+    )
+    .fetch(db)
+}
+
+/// The counted terms of every cluster in the run, summed over its members' current snapshots
+pub async fn get_cluster_term_counts(
+    db: &PgPool,
+    clustering_run: &str,
+) -> Result<Vec<ClusterTermCountRow>, Error> {
+    sqlx::query_as!(
+        ClusterTermCountRow,
+        r#"
+        SELECT ca.cluster_id, terms.term AS "term!", SUM(terms.count::BIGINT)::BIGINT AS "count!"
+        FROM cluster_assignment ca
+        JOIN page ON page.id = ca.page_id
+        JOIN page_snapshot_term_counts pstc ON pstc.page_snapshot_id = page.current_snapshot_id
+        CROSS JOIN LATERAL jsonb_each_text(pstc.term_counts) AS terms(term, count)
+        WHERE ca.clustering_run = $1
+        GROUP BY ca.cluster_id, terms.term
+        "#,
+        clustering_run
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Stores a recomputed label, which also becomes the name unless one was pinned. Empty labels, from
+/// clusters without any contents, keep the previous name.
+pub async fn set_cluster_label(
+    db: &PgPool,
+    cluster_id: Uuid,
+    label: &str,
+    membership_version: i32,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        UPDATE cluster
+        SET label = NULLIF($2, ''),
+            name = CASE WHEN name_pinned OR $2 = '' THEN name ELSE $2 END,
+            label_version = label_version + 1,
+            labeled_at = CURRENT_TIMESTAMP,
+            labeled_membership_version = $3
+        WHERE id = $1
+        "#,
+        cluster_id,
+        label,
+        membership_version
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn pin_cluster_name(
+    db: &PgPool,
+    cluster_id: Uuid,
+    name: &str,
+) -> Result<Option<ClusterRow>, Error> {
+    sqlx::query_as!(
+        ClusterRow,
+        r#"
+        UPDATE cluster
+        SET name = $2, name_pinned = true
+        WHERE id = $1
+        RETURNING id, name, clustering_run, name_pinned, label_version
+        "#,
+        cluster_id,
+        name
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Goes back to the computed label, if there is one yet
+pub async fn unpin_cluster_name(
+    db: &PgPool,
+    cluster_id: Uuid,
+) -> Result<Option<ClusterRow>, Error> {
+    sqlx::query_as!(
+        ClusterRow,
+        r#"
+        UPDATE cluster
+        SET name = COALESCE(label, name), name_pinned = false
+        WHERE id = $1
+        RETURNING id, name, clustering_run, name_pinned, label_version
+        "#,
+        cluster_id
+    )
+    .fetch_optional(db)
+    .await
+}
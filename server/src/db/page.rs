@@ -1,9 +1,13 @@
 use futures::TryStreamExt;
-use sqlx::{Error, PgPool};
+use sqlx::{Error, PgExecutor, PgPool};
+use uuid::Uuid;
 
-use crate::models::{PageRow, PageUrlRow};
+use crate::models::{PageIdUrlRow, PageRow, PageUrlRow};
 
-pub async fn get_page_from_url(db: &PgPool, page_url: &str) -> Result<Option<PageRow>, Error> {
+pub async fn get_page_from_url(
+    db: impl PgExecutor<'_>,
+    page_url: &str,
+) -> Result<Option<PageRow>, Error> {
     sqlx::query_as!(
         PageRow,
         r#"
@@ -16,38 +20,35 @@ pub async fn get_page_from_url(db: &PgPool, page_url: &str) -> Result<Option<Pag
     .await
 }
 
-pub async fn insert_page(db: &PgPool, url: &str, contents: &str) -> Result<PageRow, Error> {
+pub async fn get_page_from_id(db: &PgPool, page_id: i32) -> Result<Option<PageRow>, Error> {
     sqlx::query_as!(
         PageRow,
         r#"
-        INSERT INTO page (url, contents)
-        VALUES ($1, $2)
-        RETURNING *
+        SELECT * FROM page
+        WHERE id = $1
         "#,
-        url,
-        contents
+        page_id
     )
-    .fetch_one(db)
+    .fetch_optional(db)
     .await
 }
 
-pub async fn update_page(db: &PgPool, url: &str, new_contents: &str) -> Result<PageRow, Error> {
+/// Contents are only set through the page's snapshots
+pub async fn insert_page(db: impl PgExecutor<'_>, url: &str) -> Result<PageRow, Error> {
     sqlx::query_as!(
         PageRow,
         r#"
-        UPDATE page
-        SET contents = $1
-        WHERE url = $2
+        INSERT INTO page (url)
+        VALUES ($1)
         RETURNING *
         "#,
-        new_contents,
         url
     )
     .fetch_one(db)
     .await
 }
 
-pub async fn get_pages_in_cluster(db: &PgPool, cluster_id: &str) -> Result<Vec<PageUrlRow>, Error> {
+pub async fn get_pages_in_cluster(db: &PgPool, cluster_id: Uuid) -> Result<Vec<PageUrlRow>, Error> {
     let stream = sqlx::query_as!(
         PageUrlRow,
         r#"
@@ -61,3 +62,114 @@ pub async fn get_pages_in_cluster(db: &PgPool, cluster_id: &str) -> Result<Vec<P
 
     stream.try_collect::<Vec<_>>().await
 }
+
+pub async fn get_all_page_urls(db: &PgPool) -> Result<Vec<PageIdUrlRow>, Error> {
+    sqlx::query_as!(
+        PageIdUrlRow,
+        r#"
+        SELECT id, url FROM page
+        "#
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Deletes a page along with everything derived from it
+pub async fn delete_page(db: &PgPool, page_id: i32) -> Result<(), Error> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM preprocessed_page_embedding
+        WHERE page_id = $1
+        "#,
+        page_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // Cluster assignments and processing jobs cascade
+    sqlx::query!(
+        r#"
+        DELETE FROM page
+        WHERE id = $1
+        "#,
+        page_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+/// Forgets a page's contents and every earlier snapshot of them, along with the embeddings and
+/// cluster assignments computed from them
+pub async fn clear_page_contents(db: &PgPool, page_id: i32) -> Result<(), Error> {
+    let mut tx = db.begin().await?;
+
+    // Embeddings, chunks and processing jobs cascade, events keep no link to the snapshot
+    sqlx::query!(
+        r#"
+        DELETE FROM page_snapshot
+        WHERE page_id = $1
+        "#,
+        page_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM cluster_assignment
+        WHERE page_id = $1
+        "#,
+        page_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE page
+        SET contents = NULL
+        WHERE id = $1
+        "#,
+        page_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+pub async fn set_page_url(db: impl PgExecutor<'_>, page_id: i32, url: &str) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        UPDATE page
+        SET url = $2
+        WHERE id = $1
+        "#,
+        page_id,
+        url
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Folds `duplicate_id` into `page_id` along with its snapshots, embeddings, jobs and cluster
+/// assignments, then deletes it. The merge itself lives in the database so migrations can use it.
+pub async fn merge_duplicate_page(
+    db: impl PgExecutor<'_>,
+    duplicate_id: i32,
+    page_id: i32,
+) -> Result<(), Error> {
+    sqlx::query("SELECT merge_duplicate_page($1, $2)")
+        .bind(duplicate_id)
+        .bind(page_id)
+        .execute(db)
+        .await?;
+
+    Ok(())
+}
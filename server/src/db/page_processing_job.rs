@@ -0,0 +1,134 @@
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use sqlx::{Error, PgExecutor, PgPool};
+
+use crate::models::page_processing_job::{PageProcessingJobRow, QueueDepthRow};
+
+pub async fn enqueue_page_processing_job(
+    db: impl PgExecutor<'_>,
+    page_id: i32,
+    page_snapshot_id: i32,
+) -> Result<PageProcessingJobRow, Error> {
+    sqlx::query_as!(
+        PageProcessingJobRow,
+        r#"
+        INSERT INTO page_processing_job (page_id, page_snapshot_id)
+        VALUES ($1, $2)
+        RETURNING *
+        "#,
+        page_id,
+        page_snapshot_id
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Claims the oldest runnable job. `SKIP LOCKED` lets several workers poll without blocking on
+/// each other.
+pub async fn claim_next_page_processing_job(
+    db: &PgPool,
+) -> Result<Option<PageProcessingJobRow>, Error> {
+    sqlx::query_as!(
+        PageProcessingJobRow,
+        r#"
+        UPDATE page_processing_job
+        SET status = 'running', attempts = attempts + 1, updated_at = CURRENT_TIMESTAMP
+        WHERE id = (
+            SELECT id FROM page_processing_job
+            WHERE status = 'pending' AND run_after <= CURRENT_TIMESTAMP
+            ORDER BY run_after
+            FOR UPDATE SKIP LOCKED
+            LIMIT 1
+        )
+        RETURNING *
+        "#
+    )
+    .fetch_optional(db)
+    .await
+}
+
+pub async fn complete_page_processing_job(db: &PgPool, id: i32) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        UPDATE page_processing_job
+        SET status = 'done', last_error = NULL, updated_at = CURRENT_TIMESTAMP
+        WHERE id = $1
+        "#,
+        id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn retry_page_processing_job(
+    db: &PgPool,
+    id: i32,
+    run_after: DateTime<Utc>,
+    error: &str,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        UPDATE page_processing_job
+        SET status = 'pending', run_after = $2, last_error = $3, updated_at = CURRENT_TIMESTAMP
+        WHERE id = $1
+        "#,
+        id,
+        run_after,
+        error
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn dead_letter_page_processing_job(
+    db: &PgPool,
+    id: i32,
+    error: &str,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        UPDATE page_processing_job
+        SET status = 'dead', last_error = $2, updated_at = CURRENT_TIMESTAMP
+        WHERE id = $1
+        "#,
+        id,
+        error
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Jobs left running by a previous process will never finish, so put them back in the queue
+pub async fn requeue_running_page_processing_jobs(db: &PgPool) -> Result<u64, Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE page_processing_job
+        SET status = 'pending', updated_at = CURRENT_TIMESTAMP
+        WHERE status = 'running'
+        "#
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn get_page_processing_queue_depth(db: &PgPool) -> Result<Vec<QueueDepthRow>, Error> {
+    let stream = sqlx::query_as!(
+        QueueDepthRow,
+        r#"
+        SELECT status, COUNT(*) AS "job_count!" FROM page_processing_job
+        GROUP BY status
+        ORDER BY status
+        "#
+    )
+    .fetch(db);
+
+    stream.try_collect::<Vec<_>>().await
+}
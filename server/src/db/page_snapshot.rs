@@ -0,0 +1,136 @@
+use futures::stream::BoxStream;
+use sqlx::{Error, PgConnection, PgExecutor, PgPool};
+
+use crate::models::PageSnapshotRow;
+
+/// Returns the page's snapshot with this snapshot text, and whether it had to be created.
+/// Snapshots are keyed by a hash of the text extracted from their contents, so a page seen
+/// unchanged doesn't get another one.
+pub async fn get_or_insert_page_snapshot(
+    conn: &mut PgConnection,
+    page_id: i32,
+    contents: &str,
+    snapshot_text: &str,
+) -> Result<(PageSnapshotRow, bool), Error> {
+    let inserted = sqlx::query_as!(
+        PageSnapshotRow,
+        r#"
+        INSERT INTO page_snapshot (page_id, content_hash, contents)
+        VALUES ($1, encode(sha256(convert_to($3, 'UTF8')), 'hex'), $2)
+        ON CONFLICT (page_id, content_hash) DO NOTHING
+        RETURNING *
+        "#,
+        page_id,
+        contents,
+        snapshot_text
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    if let Some(page_snapshot) = inserted {
+        return Ok((page_snapshot, true));
+    }
+
+    let existing = get_page_snapshot_by_text(&mut *conn, page_id, snapshot_text)
+        .await?
+        .ok_or(Error::RowNotFound)?;
+
+    Ok((existing, false))
+}
+
+pub async fn get_page_snapshot_by_text(
+    db: impl PgExecutor<'_>,
+    page_id: i32,
+    snapshot_text: &str,
+) -> Result<Option<PageSnapshotRow>, Error> {
+    sqlx::query_as!(
+        PageSnapshotRow,
+        r#"
+        SELECT * FROM page_snapshot
+        WHERE page_id = $1 AND content_hash = encode(sha256(convert_to($2, 'UTF8')), 'hex')
+        "#,
+        page_id,
+        snapshot_text
+    )
+    .fetch_optional(db)
+    .await
+}
+
+pub async fn get_page_snapshot(
+    db: impl PgExecutor<'_>,
+    page_snapshot_id: i32,
+) -> Result<Option<PageSnapshotRow>, Error> {
+    sqlx::query_as!(
+        PageSnapshotRow,
+        r#"
+        SELECT * FROM page_snapshot
+        WHERE id = $1
+        "#,
+        page_snapshot_id
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Makes the snapshot the page's current version, mirroring its contents onto the page
+pub async fn set_current_page_snapshot(
+    db: impl PgExecutor<'_>,
+    page_id: i32,
+    page_snapshot_id: i32,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        UPDATE page
+        SET current_snapshot_id = ps.id, contents = ps.contents
+        FROM page_snapshot ps
+        WHERE page.id = $1 AND ps.id = $2 AND ps.page_id = page.id
+        "#,
+        page_id,
+        page_snapshot_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Snapshots that have no embedding in the run yet, so an interrupted backfill picks up where it
+/// left off
+pub fn stream_snapshot_ids_missing_embedding<'a>(
+    db: &'a PgPool,
+    embedding_run: &'a str,
+) -> BoxStream<'a, Result<i32, Error>> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT id FROM page_snapshot ps
+        WHERE NOT EXISTS (
+            SELECT 1 FROM preprocessed_page_embedding ppe
+            WHERE ppe.page_snapshot_id = ps.id AND ppe.embedding_run = $1
+        )
+        ORDER BY id
+        "#,
+        embedding_run
+    )
+    .fetch(db)
+}
+
+/// Term counts never change for a snapshot, so a concurrent insert is as good as this one
+pub async fn insert_page_snapshot_term_counts(
+    db: &PgPool,
+    page_snapshot_id: i32,
+    term_counts: &serde_json::Value,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO page_snapshot_term_counts (page_snapshot_id, term_counts)
+        VALUES ($1, $2)
+        ON CONFLICT (page_snapshot_id) DO NOTHING
+        "#,
+        page_snapshot_id,
+        term_counts
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
@@ -1,24 +1,155 @@
+use futures::stream::BoxStream;
 use pgvector::Vector;
-use sqlx::{Error, PgPool};
+use sqlx::{Error, PgExecutor, PgPool};
 
-use crate::models::PreprocessedPageEmbeddingRow;
+use crate::models::{PageEmbeddingRow, PreprocessedPageEmbeddingRow};
 
-pub async fn insert_preprocessed_page_embedding(
-    db: &PgPool,
+/// Replaces the snapshot's embedding for the run if it already has one
+pub async fn upsert_preprocessed_page_embedding(
+    db: impl PgExecutor<'_>,
     page_id: i32,
+    page_snapshot_id: i32,
     embedding_run: &str,
     embedding: &Vector,
 ) -> Result<PreprocessedPageEmbeddingRow, Error> {
     sqlx::query_as(
         r#"
-        INSERT INTO preprocessed_page_embedding (page_id, embedding_run, embedding)
-        VALUES ($1, $2, $3)
+        INSERT INTO preprocessed_page_embedding (page_id, page_snapshot_id, embedding_run, embedding)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (page_snapshot_id, embedding_run) DO UPDATE
+        SET embedding = EXCLUDED.embedding, created_at = CURRENT_TIMESTAMP
         RETURNING *
         "#,
     )
     .bind(page_id)
+    .bind(page_snapshot_id)
     .bind(embedding_run)
     .bind(embedding)
     .fetch_one(db)
     .await
 }
+
+pub async fn get_preprocessed_page_embedding(
+    db: &PgPool,
+    page_snapshot_id: i32,
+    embedding_run: &str,
+) -> Result<Option<PreprocessedPageEmbeddingRow>, Error> {
+    sqlx::query_as(
+        r#"
+        SELECT * FROM preprocessed_page_embedding
+        WHERE page_snapshot_id = $1 AND embedding_run = $2
+        "#,
+    )
+    .bind(page_snapshot_id)
+    .bind(embedding_run)
+    .fetch_optional(db)
+    .await
+}
+
+/// The embedding of the page's most recent snapshot that has one
+pub async fn get_latest_page_embedding(
+    db: &PgPool,
+    page_id: i32,
+    embedding_run: &str,
+) -> Result<Option<PreprocessedPageEmbeddingRow>, Error> {
+    sqlx::query_as(
+        r#"
+        SELECT * FROM preprocessed_page_embedding
+        WHERE page_id = $1 AND embedding_run = $2
+        ORDER BY page_snapshot_id DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(page_id)
+    .bind(embedding_run)
+    .fetch_optional(db)
+    .await
+}
+
+/// The embedding of each page's most recent embedded snapshot
+pub async fn get_page_embeddings_for_run(
+    db: &PgPool,
+    embedding_run: &str,
+) -> Result<Vec<PageEmbeddingRow>, Error> {
+    sqlx::query_as(
+        r#"
+        SELECT DISTINCT ON (ppe.page_id) ppe.page_id, ppe.embedding
+        FROM preprocessed_page_embedding ppe
+        WHERE ppe.embedding_run = $1
+        AND ppe.embedding IS NOT NULL
+        ORDER BY ppe.page_id, ppe.page_snapshot_id DESC
+        "#,
+    )
+    .bind(embedding_run)
+    .fetch_all(db)
+    .await
+}
+
+pub async fn delete_page_embedding_chunks(
+    db: impl PgExecutor<'_>,
+    page_snapshot_id: i32,
+    embedding_run: &str,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM page_embedding_chunk
+        WHERE page_snapshot_id = $1 AND embedding_run = $2
+        "#,
+        page_snapshot_id,
+        embedding_run
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Pages with an embedding in the run that are missing from any of the clustering runs, in the
+/// order they were stored
+pub fn stream_pages_missing_assignments<'a>(
+    db: &'a PgPool,
+    embedding_run: &'a str,
+    clustering_runs: &'a [String],
+) -> BoxStream<'a, Result<i32, Error>> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT ppe.page_id AS "page_id!" FROM preprocessed_page_embedding ppe
+        WHERE ppe.embedding_run = $1
+        AND (
+            SELECT COUNT(*) FROM cluster_assignment ca
+            WHERE ca.page_id = ppe.page_id AND ca.clustering_run = ANY($2)
+        ) < cardinality($2)
+        ORDER BY ppe.page_id
+        "#,
+        embedding_run,
+        clustering_runs
+    )
+    .fetch(db)
+}
+
+pub async fn insert_page_embedding_chunk(
+    db: impl PgExecutor<'_>,
+    page_id: i32,
+    page_snapshot_id: i32,
+    embedding_run: &str,
+    chunk_index: i32,
+    contents: &str,
+    embedding: &Vector,
+) -> Result<(), Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO page_embedding_chunk (page_id, page_snapshot_id, embedding_run, chunk_index, contents, embedding)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(page_id)
+    .bind(page_snapshot_id)
+    .bind(embedding_run)
+    .bind(chunk_index)
+    .bind(contents)
+    .bind(embedding)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use sqlx::{Error, PgExecutor, PgPool};
+
+use crate::models::{
+    cluster::SimilarityMetric,
+    run::{ClusteringRunRow, EmbeddingRunRow, NewClusteringRun, NewEmbeddingRun, RunStatus},
+};
+
+/// Does nothing if a run with the same name is already registered
+pub async fn insert_embedding_run(
+    db: impl PgExecutor<'_>,
+    embedding_run: &NewEmbeddingRun<'_>,
+    code_version: &str,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO embedding_run (name, pipeline_name, model_id, embedding_dimension, parameters, code_version)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        embedding_run.name,
+        embedding_run.pipeline_name,
+        embedding_run.model_id,
+        embedding_run.embedding_dimension,
+        embedding_run.parameters,
+        code_version
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_embedding_run(db: &PgPool, name: &str) -> Result<Option<EmbeddingRunRow>, Error> {
+    sqlx::query_as!(
+        EmbeddingRunRow,
+        r#"
+        SELECT name, pipeline_name, model_id, embedding_dimension, parameters, code_version, status AS "status: RunStatus", created_at
+        FROM embedding_run
+        WHERE name = $1
+        "#,
+        name
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Returns `false` if a run with the same name is already registered
+pub async fn insert_clustering_run(
+    db: impl PgExecutor<'_>,
+    clustering_run: &NewClusteringRun<'_>,
+    code_version: &str,
+) -> Result<bool, Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO clustering_run (name, embedding_run, algorithm, parameters, code_version, status)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        clustering_run.name,
+        clustering_run.embedding_run,
+        clustering_run.algorithm,
+        clustering_run.parameters,
+        code_version,
+        clustering_run.status as RunStatus
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Creates the run's partial HNSW index over its cluster centroids, if it doesn't exist yet
+pub async fn create_clustering_run_index(
+    db: impl PgExecutor<'_>,
+    name: &str,
+    embedding_dimension: i32,
+    similarity_metric: SimilarityMetric,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        SELECT create_clustering_run_index($1, $2, $3)
+        "#,
+        name,
+        embedding_dimension,
+        similarity_metric.index_operator_class()
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn set_clustering_run_status(
+    db: &PgPool,
+    name: &str,
+    status: RunStatus,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        UPDATE clustering_run
+        SET status = $2
+        WHERE name = $1
+        "#,
+        name,
+        status as RunStatus
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_clustering_run(
+    db: &PgPool,
+    name: &str,
+) -> Result<Option<ClusteringRunRow>, Error> {
+    sqlx::query_as!(
+        ClusteringRunRow,
+        r#"
+        SELECT cr.name AS clustering_run, cr.embedding_run, er.pipeline_name AS "pipeline_name?", er.model_id, er.embedding_dimension,
+            cr.algorithm, cr.parameters, cr.code_version, cr.status AS "status: RunStatus", cr.created_at
+        FROM clustering_run cr
+        LEFT JOIN embedding_run er ON er.name = cr.embedding_run
+        WHERE cr.name = $1
+        "#,
+        name
+    )
+    .fetch_optional(db)
+    .await
+}
+
+pub async fn get_clustering_runs(db: &PgPool) -> Result<Vec<ClusteringRunRow>, Error> {
+    sqlx::query_as!(
+        ClusteringRunRow,
+        r#"
+        SELECT cr.name AS clustering_run, cr.embedding_run, er.pipeline_name AS "pipeline_name?", er.model_id, er.embedding_dimension,
+            cr.algorithm, cr.parameters, cr.code_version, cr.status AS "status: RunStatus", cr.created_at
+        FROM clustering_run cr
+        LEFT JOIN embedding_run er ON er.name = cr.embedding_run
+        ORDER BY cr.created_at DESC
+        "#
+    )
+    .fetch_all(db)
+    .await
+}
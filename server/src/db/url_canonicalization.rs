@@ -0,0 +1,35 @@
+use sqlx::{Error, PgPool};
+
+pub async fn get_applied_url_canonicalization_config(
+    db: &PgPool,
+) -> Result<Option<serde_json::Value>, Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT config
+        FROM applied_url_canonicalization
+        "#
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(row.map(|row| row.config))
+}
+
+pub async fn set_applied_url_canonicalization_config(
+    db: &PgPool,
+    config: &serde_json::Value,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO applied_url_canonicalization (config)
+        VALUES ($1)
+        ON CONFLICT (id) DO UPDATE
+        SET config = EXCLUDED.config, applied_at = CURRENT_TIMESTAMP
+        "#,
+        config
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use sqlx::{Error, PgPool};
+
+use crate::models::url_rule::{NewUrlRule, UrlMatchType, UrlRuleAction, UrlRuleRow};
+
+pub async fn get_url_rules(db: &PgPool) -> Result<Vec<UrlRuleRow>, Error> {
+    sqlx::query_as!(
+        UrlRuleRow,
+        r#"
+        SELECT id, match_type AS "match_type: UrlMatchType", pattern, action AS "action: UrlRuleAction", created_at
+        FROM url_rule
+        ORDER BY id
+        "#
+    )
+    .fetch_all(db)
+    .await
+}
+
+pub async fn insert_url_rule(db: &PgPool, url_rule: &NewUrlRule) -> Result<UrlRuleRow, Error> {
+    sqlx::query_as!(
+        UrlRuleRow,
+        r#"
+        INSERT INTO url_rule (match_type, pattern, action)
+        VALUES ($1, $2, $3)
+        RETURNING id, match_type AS "match_type: UrlMatchType", pattern, action AS "action: UrlRuleAction", created_at
+        "#,
+        url_rule.match_type as UrlMatchType,
+        url_rule.pattern,
+        url_rule.action as UrlRuleAction
+    )
+    .fetch_one(db)
+    .await
+}
+
+pub async fn delete_url_rule(db: &PgPool, id: i32) -> Result<bool, Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM url_rule
+        WHERE id = $1
+        "#,
+        id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -1,2 +1,5 @@
 pub mod analytics_handlers;
 pub mod browse_event_handlers;
+pub mod cluster_handlers;
+pub mod queue_handlers;
+pub mod url_rule_handlers;
@@ -1,22 +1,32 @@
+use anyhow::Error;
 use axum::{
     debug_handler,
     extract::{Query, State},
     http::StatusCode,
     Json,
 };
-use futures::TryStreamExt;
+use chrono::Duration;
 use serde::Deserialize;
 use sqlx::PgPool;
+use uuid::Uuid;
 
 use crate::db;
 use crate::{
     db::{
-        browse_event::get_all_browse_events, cluster::get_all_clusters, page::get_pages_in_cluster,
+        browse_event::{get_all_browse_events, get_browse_events_in_range},
+        cluster::get_all_clusters,
+        page::get_pages_in_cluster,
     },
     models::{
-        browse_event::BrowseEventRowWithCluster,
-        cluster::{ClusterRow, ClusteringRunRow},
-        EventCountBucket, PageUrlRow,
+        browse_event::BrowseEventRowWithCluster, cluster::ClusterRow, run::ClusteringRunRow,
+        DwellTimeBucket, PageDwellTimeRow, PageUrlRow,
+    },
+    services::{
+        event_buckets::{BucketInterval, TimeRange},
+        sessionization::{
+            aggregate_dwell_time_buckets, aggregate_page_dwell_times, attention_intervals_in_range,
+            AttentionInterval, MAX_IDLE_GAP_MINUTES,
+        },
     },
 };
 
@@ -30,70 +40,101 @@ pub async fn return_all_events(
 }
 
 #[derive(Deserialize)]
-pub struct WithClusteringRun {
+pub struct EventBucketParams {
     clustering_run: String,
+    tz: Option<String>,
+    start: Option<String>,
+    end: Option<String>,
+    interval: Option<String>,
 }
 
 pub async fn get_event_buckets(
     State(pool): State<PgPool>,
-    Query(params): Query<WithClusteringRun>,
-) -> Result<Json<Vec<EventCountBucket>>, (StatusCode, String)> {
-    let stream = sqlx::query_as!(
-        // TODO: add timezone, time range, and interval as query params
-        EventCountBucket,
-        r#"
-        WITH timerange_events AS (
-            SELECT
-                be.timestamp AT TIME ZONE 'America/New_York' AS local_time,
-                ca.cluster_id
-            FROM
-                browse_event be
-                JOIN page ON be.page_url = page.url
-                JOIN cluster_assignment ca ON page.id = ca.page_id
-            WHERE 
-                be.timestamp AT TIME ZONE 'America/New_York' >= DATE_TRUNC('day', CURRENT_TIMESTAMP AT TIME ZONE 'America/New_York') - INTERVAL '1 day'
-                AND be.timestamp AT TIME ZONE 'America/New_York' < DATE_TRUNC('day', CURRENT_TIMESTAMP AT TIME ZONE 'America/New_York') + INTERVAL '1 day'
-            ORDER BY be.timestamp DESC
-        )
-        SELECT
-            DATE_TRUNC('hour', te.local_time) AS timestamp_bucket,
-            te.cluster_id,
-            c.name::TEXT AS cluster_name,
-            COUNT(*) AS event_count
-        FROM
-            timerange_events te
-        LEFT JOIN
-            cluster c ON te.cluster_id = c.id
-        WHERE c.clustering_run = $1
-        GROUP BY
-            DATE_TRUNC('hour', te.local_time),
-            te.cluster_id,
-            c.name
-        ORDER BY
-            timestamp_bucket,
-            cluster_id;
-        "#,
-        &params.clustering_run
+    Query(params): Query<EventBucketParams>,
+) -> Result<Json<Vec<DwellTimeBucket>>, (StatusCode, String)> {
+    let bad_request = |e: Error| (StatusCode::BAD_REQUEST, e.to_string());
+    let internal_error = |e: Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+
+    let time_range = TimeRange::from_params(
+        params.tz.as_deref(),
+        params.start.as_deref(),
+        params.end.as_deref(),
     )
-    .fetch(&pool);
+    .map_err(bad_request)?;
+    let bucket_interval = match &params.interval {
+        Some(interval) => interval.parse::<BucketInterval>().map_err(bad_request)?,
+        None => BucketInterval::default(),
+    };
+    bucket_interval
+        .check_bucket_count(&time_range)
+        .map_err(bad_request)?;
 
-    stream
-        .try_collect::<Vec<_>>()
+    let intervals = load_attention_intervals(&pool, &time_range)
+        .await
+        .map_err(|e| internal_error(e.into()))?;
+    let page_clusters = db::cluster::get_page_clusters(&pool, &params.clustering_run)
         .await
-        .map(Json)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        .map_err(|e| internal_error(e.into()))?;
+
+    Ok(Json(aggregate_dwell_time_buckets(
+        &intervals,
+        &page_clusters,
+        &bucket_interval,
+        &time_range.tz,
+    )))
+}
+
+#[derive(Deserialize)]
+pub struct WithTimeRange {
+    tz: Option<String>,
+    start: Option<String>,
+    end: Option<String>,
+}
+
+pub async fn get_page_dwell_times(
+    State(pool): State<PgPool>,
+    Query(params): Query<WithTimeRange>,
+) -> Result<Json<Vec<PageDwellTimeRow>>, (StatusCode, String)> {
+    let time_range = TimeRange::from_params(
+        params.tz.as_deref(),
+        params.start.as_deref(),
+        params.end.as_deref(),
+    )
+    .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let intervals = load_attention_intervals(&pool, &time_range)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(aggregate_page_dwell_times(&intervals)))
+}
+
+/// Loads the attention intervals that fall within `time_range`
+async fn load_attention_intervals(
+    pool: &PgPool,
+    time_range: &TimeRange,
+) -> Result<Vec<AttentionInterval>, sqlx::Error> {
+    // Intervals that started before the range can still run into it
+    let events_start = time_range.start - Duration::minutes(MAX_IDLE_GAP_MINUTES);
+    let events = get_browse_events_in_range(pool, events_start, time_range.end).await?;
+
+    Ok(attention_intervals_in_range(
+        &events,
+        time_range.start,
+        time_range.end,
+    ))
 }
 
 #[derive(Deserialize)]
 pub struct WithClusterId {
-    cluster_id: String,
+    cluster_id: Uuid,
 }
 
 pub async fn get_pages(
     State(db): State<PgPool>,
     Query(params): Query<WithClusterId>,
 ) -> Result<Json<Vec<PageUrlRow>>, (StatusCode, String)> {
-    match get_pages_in_cluster(&db, &params.cluster_id).await {
+    match get_pages_in_cluster(&db, params.cluster_id).await {
         Ok(pages) => Ok(Json(pages)),
         Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
     }
@@ -111,7 +152,7 @@ pub async fn get_clusters(
 pub async fn get_clustering_runs(
     State(db): State<PgPool>,
 ) -> Result<Json<Vec<ClusteringRunRow>>, (StatusCode, String)> {
-    match db::cluster::get_clustering_runs(&db).await {
+    match db::run::get_clustering_runs(&db).await {
         Ok(clustering_runs) => Ok(Json(clustering_runs)),
         Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
     }
@@ -1,46 +1,57 @@
+use std::sync::Arc;
+
 use anyhow::Error;
 use axum::{debug_handler, extract::State, http::StatusCode, Json};
-use sqlx::PgPool;
+use chrono::Utc;
+use sqlx::{Connection, PgConnection, PgPool};
+use uuid::Uuid;
 
 use crate::{
+    app_state::AppState,
     db::{
         browse_event::insert_browse_event,
-        cluster::{check_cluster_exists, insert_cluster, insert_cluster_assignment},
-        page::{self, get_page_from_url, insert_page, update_page},
-        preprocessed_page_embedding::insert_preprocessed_page_embedding,
+        page::{self, get_page_from_url, insert_page},
+        page_processing_job::enqueue_page_processing_job,
+        page_snapshot::{
+            get_or_insert_page_snapshot, get_page_snapshot, get_page_snapshot_by_text,
+            set_current_page_snapshot,
+        },
     },
-    models::{
-        browse_event::{BrowseEventFromChromeExtension, BrowseEventRow},
-        cluster::ClusterRow,
-        PageRow,
+    models::browse_event::{
+        BrowseEventFromChromeExtension, BrowseEventRow, LogEventResult, LogEventStatus,
     },
     services::{
-        clustering::{self, assign_page_to_cluster_id},
-        preprocessing::pipelines,
-        utils::{extract_keywords, html_to_markdown},
+        page_snapshots::{is_due_for_snapshot, SnapshotTextExtractor},
+        url_canonicalization::UrlCanonicalizer,
+        url_rules::{apply_url_rules, SharedUrlRules},
     },
 };
 
-#[debug_handler]
+// Events can carry page HTML, so keep batches bounded
+const MAX_EVENTS_PER_BATCH: usize = 500;
+
+#[debug_handler(state = AppState)]
 pub async fn log_browse_event(
     State(db): State<PgPool>,
-    Json(browse_event): Json<BrowseEventFromChromeExtension>,
+    State(url_rules): State<SharedUrlRules>,
+    State(url_canonicalizer): State<Arc<UrlCanonicalizer>>,
+    State(snapshot_text_extractor): State<Arc<SnapshotTextExtractor>>,
+    Json(mut browse_event): Json<BrowseEventFromChromeExtension>,
 ) -> Result<Json<Option<BrowseEventRow>>, (StatusCode, String)> {
-    if should_ignore_event(&browse_event) {
-        println!("Ignored event: {:?}", browse_event.page_url);
+    canonicalize_page_url(&url_canonicalizer, &mut browse_event);
+    let page_url = browse_event.page_url.clone();
+    let Some(browse_event) = apply_shared_url_rules(&url_rules, vec![browse_event])?
+        .pop()
+        .flatten()
+    else {
+        println!("Ignored event: {:?}", page_url);
         return Ok(Json(None));
-    }
+    };
 
     println!("Logging event: {:?}", browse_event.page_url);
 
-    match insert_browse_event(&db, &browse_event).await {
-        Ok(uploaded_row) => process_browse_event_page(&db, &browse_event)
-            .await
-            .map(|_| Json(Some(uploaded_row)))
-            .map_err(|e| {
-                eprintln!("Failed to process/upload page info: {:?}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
-            }),
+    match insert_and_store_browse_event(&db, snapshot_text_extractor, &browse_event).await {
+        Ok(uploaded_row) => Ok(Json(uploaded_row)),
         Err(e) => {
             eprintln!("Failed to upload event: {:?}", e);
             Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
@@ -48,101 +59,282 @@ pub async fn log_browse_event(
     }
 }
 
-fn should_ignore_event(browse_event: &BrowseEventFromChromeExtension) -> bool {
-    if browse_event.page_url.starts_with("http://localhost") {
-        return true;
+#[debug_handler(state = AppState)]
+pub async fn log_browse_events(
+    State(db): State<PgPool>,
+    State(url_rules): State<SharedUrlRules>,
+    State(url_canonicalizer): State<Arc<UrlCanonicalizer>>,
+    State(snapshot_text_extractor): State<Arc<SnapshotTextExtractor>>,
+    Json(mut browse_events): Json<Vec<BrowseEventFromChromeExtension>>,
+) -> Result<Json<Vec<LogEventResult>>, (StatusCode, String)> {
+    if browse_events.len() > MAX_EVENTS_PER_BATCH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Batches are limited to {} events, got {}",
+                MAX_EVENTS_PER_BATCH,
+                browse_events.len()
+            ),
+        ));
     }
 
-    if browse_event.page_url.starts_with("https://mail.google.com") {
-        return true;
+    // Replays are only idempotent for events with ids, so batches, which are replayed by design,
+    // must have them
+    let Some(event_ids) = browse_events
+        .iter()
+        .map(|e| e.event_id)
+        .collect::<Option<Vec<_>>>()
+    else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Every event in a batch needs an event_id".to_string(),
+        ));
+    };
+
+    println!("Logging {} events", browse_events.len());
+
+    for browse_event in &mut browse_events {
+        canonicalize_page_url(&url_canonicalizer, browse_event);
     }
+    let browse_events = apply_shared_url_rules(&url_rules, browse_events)?;
+    let page_contents = browse_events
+        .iter()
+        .map(|browse_event| browse_event.as_ref()?.page_content.clone())
+        .collect();
+    let snapshot_texts = extract_snapshot_texts(snapshot_text_extractor, page_contents)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to extract snapshot texts: {:?}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        })?;
 
-    false
+    match insert_and_store_browse_event_batch(&db, &event_ids, &browse_events, snapshot_texts).await
+    {
+        Ok(results) => Ok(Json(results)),
+        Err(e) => {
+            eprintln!("Failed to upload event batch: {:?}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        }
+    }
 }
 
-async fn process_browse_event_page(
+/// Urls are canonicalized before url rules apply, so rules see the url the page is stored under
+fn canonicalize_page_url(
+    url_canonicalizer: &UrlCanonicalizer,
+    browse_event: &mut BrowseEventFromChromeExtension,
+) {
+    browse_event.page_url = url_canonicalizer.canonicalize(&browse_event.page_url);
+}
+
+/// `None` in the output means the event was dropped by a rule
+fn apply_shared_url_rules(
+    url_rules: &SharedUrlRules,
+    browse_events: Vec<BrowseEventFromChromeExtension>,
+) -> Result<Vec<Option<BrowseEventFromChromeExtension>>, (StatusCode, String)> {
+    let url_rules = url_rules.read().map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Url rules lock was poisoned".to_string(),
+        )
+    })?;
+
+    Ok(browse_events
+        .into_iter()
+        .map(|browse_event| apply_url_rules(&url_rules, browse_event))
+        .collect())
+}
+
+/// The snapshot text of each of the contents, `None` where there are none. Extraction parses the
+/// whole page, so it runs on the blocking pool, before any transaction is opened.
+async fn extract_snapshot_texts(
+    snapshot_text_extractor: Arc<SnapshotTextExtractor>,
+    page_contents: Vec<Option<String>>,
+) -> Result<Vec<Result<Option<String>, Error>>, Error> {
+    Ok(tokio::task::spawn_blocking(move || {
+        page_contents
+            .iter()
+            .map(|page_content| {
+                page_content
+                    .as_deref()
+                    .map(|page_content| snapshot_text_extractor.snapshot_text(page_content))
+                    .transpose()
+            })
+            .collect()
+    })
+    .await?)
+}
+
+async fn insert_and_store_browse_event(
     db: &PgPool,
+    snapshot_text_extractor: Arc<SnapshotTextExtractor>,
     browse_event: &BrowseEventFromChromeExtension,
-) -> Result<Option<PageRow>, Error> {
-    // If the page exists already, then we don't apply online clustering strategies to it,
-    // even if the strategies are new. Batch strategies will always run on new pages.
-    let url = &browse_event.page_url;
+) -> Result<Option<BrowseEventRow>, Error> {
+    let snapshot_text = extract_snapshot_texts(
+        snapshot_text_extractor,
+        vec![browse_event.page_content.clone()],
+    )
+    .await?
+    .remove(0)?;
 
-    let (page_row_missing, page_contents_missing) = match get_page_from_url(db, url).await? {
-        Some(page_row) => (false, page_row.contents.is_none()),
-        None => (true, true),
-    };
+    let mut tx = db.begin().await?;
+    let uploaded_row =
+        insert_and_store_browse_event_in(&mut tx, browse_event, snapshot_text.as_deref()).await?;
+    tx.commit().await?;
 
-    if page_contents_missing {
-        if let Some(page_content) = &browse_event.page_content {
-            let page_row = match page_row_missing {
-                true => insert_page(db, url, page_content).await?,
-                false => update_page(db, url, page_content).await?,
-            };
-
-            // TODO: get multiple different pipelines and run them all
-            let preprocessing_pipelines = pipelines::get_all_preprocessing_pipelines()?;
-            for preprocessing_pipeline in preprocessing_pipelines {
-                let embedding = preprocessing_pipeline.run(page_content)?;
-                insert_preprocessed_page_embedding(
-                    db,
-                    page_row.id,
-                    preprocessing_pipeline.name,
-                    &embedding,
-                )
-                .await?;
+    Ok(uploaded_row)
+}
 
-                let clustering_run =
-                    format!("{}-online-nearest-neighbor", preprocessing_pipeline.name);
+/// Inserts the whole batch in one transaction. Each event gets its own savepoint, so a failing
+/// event is reported back without aborting the others.
+async fn insert_and_store_browse_event_batch(
+    db: &PgPool,
+    event_ids: &[Uuid],
+    browse_events: &[Option<BrowseEventFromChromeExtension>],
+    snapshot_texts: Vec<Result<Option<String>, Error>>,
+) -> Result<Vec<LogEventResult>, Error> {
+    let mut tx = db.begin().await?;
+    let mut results = Vec::with_capacity(browse_events.len());
 
-                let page_cluster_id = assign_page_to_cluster_id(
-                    db,
+    for ((event_id, browse_event), snapshot_text) in
+        event_ids.iter().zip(browse_events).zip(snapshot_texts)
+    {
+        let status = match (browse_event, snapshot_text) {
+            (None, _) => LogEventStatus::Ignored,
+            (Some(_), Err(e)) => {
+                eprintln!("Failed to extract the snapshot text of an event: {:?}", e);
+                LogEventStatus::Failed {
+                    error: e.to_string(),
+                }
+            }
+            (Some(browse_event), Ok(snapshot_text)) => {
+                let mut savepoint = Connection::begin(&mut *tx).await?;
+                match insert_and_store_browse_event_in(
+                    &mut savepoint,
                     browse_event,
-                    &embedding,
-                    preprocessing_pipeline.name,
+                    snapshot_text.as_deref(),
                 )
-                .await?;
-
-                println!("{:?}", page_cluster_id);
-
-                if !check_cluster_exists(db, &page_cluster_id).await? {
-                    println!("creating a new cluster!");
-                    create_cluster_and_add_to_database(
-                        db,
-                        page_content,
-                        &page_cluster_id,
-                        &clustering_run,
-                    )
-                    .await?;
-                } else {
-                    println!("cluster already exists");
+                .await
+                {
+                    Ok(Some(uploaded_row)) => {
+                        savepoint.commit().await?;
+                        LogEventStatus::Inserted {
+                            id: uploaded_row.id,
+                        }
+                    }
+                    Ok(None) => {
+                        savepoint.commit().await?;
+                        LogEventStatus::Duplicate
+                    }
+                    Err(e) => {
+                        savepoint.rollback().await?;
+                        eprintln!("Failed to upload event: {:?}", e);
+                        LogEventStatus::Failed {
+                            error: e.to_string(),
+                        }
+                    }
                 }
-
-                insert_cluster_assignment(db, page_row.id, &page_cluster_id).await?;
             }
+        };
 
-            return Ok(Some(page_row));
-        }
+        results.push(LogEventResult {
+            event_id: *event_id,
+            status,
+        });
     }
 
-    Ok(None)
+    tx.commit().await?;
+
+    Ok(results)
 }
 
-// TODO: make this into just one implementation of a clustering algo
-async fn create_cluster_and_add_to_database(
-    db: &PgPool,
-    page_content: &str,
-    cluster_id: &str,
-    clustering_run: &str,
-) -> Result<ClusterRow, Error> {
-    // TODO: make `num_keywords` into a global param/const
-    // Also rework this whole thing later
-    let num_keywords = 5;
-    let page_markdown = html_to_markdown(page_content)?;
-    let cluster_keywords = extract_keywords(&page_markdown, num_keywords);
-    let cluster_name = cluster_keywords.join(" ");
-    let cluster_row: ClusterRow =
-        insert_cluster(db, cluster_id, &cluster_name, clustering_run).await?;
-
-    Ok(cluster_row)
+/// Returns `None` for events that were already logged, in which case the page is left alone
+async fn insert_and_store_browse_event_in(
+    conn: &mut PgConnection,
+    browse_event: &BrowseEventFromChromeExtension,
+    snapshot_text: Option<&str>,
+) -> Result<Option<BrowseEventRow>, Error> {
+    // The page is stored first so the event is inserted already linked to it, and rolled back if
+    // the event turns out to be a duplicate
+    let mut savepoint = Connection::begin(&mut *conn).await?;
+    let (page_id, page_snapshot_id) =
+        match store_browse_event_page(&mut savepoint, browse_event, snapshot_text).await? {
+            Some((page_id, page_snapshot_id)) => (Some(page_id), page_snapshot_id),
+            None => (None, None),
+        };
+
+    let Some(uploaded_row) =
+        insert_browse_event(&mut *savepoint, browse_event, page_id, page_snapshot_id).await?
+    else {
+        savepoint.rollback().await?;
+        return Ok(None);
+    };
+    savepoint.commit().await?;
+
+    Ok(Some(uploaded_row))
+}
+
+/// Stores the contents the event saw as a snapshot of the page, returning the page and snapshot the
+/// event should be linked to. Events without contents are linked to the page's current snapshot,
+/// and to no page at all if it was never stored. Contents whose text hasn't been seen before only
+/// become a new snapshot if the current one isn't too recent, otherwise the event is linked to the
+/// current one. `snapshot_text` is the text extracted from the event's contents.
+async fn store_browse_event_page(
+    conn: &mut PgConnection,
+    browse_event: &BrowseEventFromChromeExtension,
+    snapshot_text: Option<&str>,
+) -> Result<Option<(i32, Option<i32>)>, Error> {
+    // Online clustering strategies only assign a page once, from its first snapshot, even if
+    // the strategies are new. Batch strategies and `server backfill` cover later snapshots.
+    let url = &browse_event.page_url;
+    let page_row = get_page_from_url(&mut *conn, url).await?;
+
+    let (Some(page_content), Some(snapshot_text)) = (&browse_event.page_content, snapshot_text)
+    else {
+        return Ok(page_row.map(|page_row| (page_row.id, page_row.current_snapshot_id)));
+    };
+
+    let page_row = match page_row {
+        Some(page_row) => page_row,
+        None => insert_page(&mut *conn, url).await?,
+    };
+
+    let (page_snapshot, is_new_snapshot) =
+        match get_page_snapshot_by_text(&mut *conn, page_row.id, snapshot_text).await? {
+            Some(page_snapshot) => (page_snapshot, false),
+            None => {
+                let current_snapshot = match page_row.current_snapshot_id {
+                    Some(current_snapshot_id) => {
+                        get_page_snapshot(&mut *conn, current_snapshot_id).await?
+                    }
+                    None => None,
+                };
+                match current_snapshot {
+                    Some(current_snapshot)
+                        if !is_due_for_snapshot(current_snapshot.created_at, Utc::now()) =>
+                    {
+                        (current_snapshot, false)
+                    }
+                    _ => {
+                        get_or_insert_page_snapshot(
+                            &mut *conn,
+                            page_row.id,
+                            page_content,
+                            snapshot_text,
+                        )
+                        .await?
+                    }
+                }
+            }
+        };
+    if page_row.current_snapshot_id != Some(page_snapshot.id) {
+        set_current_page_snapshot(&mut *conn, page_row.id, page_snapshot.id).await?;
+    }
+
+    // Unchanged contents were already embedded, or are queued to be
+    if is_new_snapshot {
+        // Embedding and clustering happen in the ingestion queue's workers
+        enqueue_page_processing_job(&mut *conn, page_row.id, page_snapshot.id).await?;
+    }
+
+    Ok(Some((page_row.id, Some(page_snapshot.id))))
 }
@@ -0,0 +1,57 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    db::cluster::{pin_cluster_name, unpin_cluster_name},
+    models::cluster::ClusterRow,
+};
+
+#[derive(Deserialize)]
+pub struct PinClusterName {
+    cluster_id: Uuid,
+    name: String,
+}
+
+/// Names the cluster by hand, recomputed labels won't overwrite it until it's unpinned
+pub async fn pin_name(
+    State(db): State<PgPool>,
+    Json(params): Json<PinClusterName>,
+) -> Result<Json<ClusterRow>, (StatusCode, String)> {
+    let name = params.name.trim();
+    if name.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Cluster names can't be empty".to_string(),
+        ));
+    }
+
+    match pin_cluster_name(&db, params.cluster_id, name).await {
+        Ok(Some(cluster)) => Ok(Json(cluster)),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            format!("Cluster {} doesn't exist", params.cluster_id),
+        )),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct WithClusterId {
+    cluster_id: Uuid,
+}
+
+pub async fn unpin_name(
+    State(db): State<PgPool>,
+    Json(params): Json<WithClusterId>,
+) -> Result<Json<ClusterRow>, (StatusCode, String)> {
+    match unpin_cluster_name(&db, params.cluster_id).await {
+        Ok(Some(cluster)) => Ok(Json(cluster)),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            format!("Cluster {} doesn't exist", params.cluster_id),
+        )),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
@@ -0,0 +1,16 @@
+use axum::{extract::State, http::StatusCode, Json};
+use sqlx::PgPool;
+
+use crate::{
+    db::page_processing_job::get_page_processing_queue_depth,
+    models::page_processing_job::QueueDepthRow,
+};
+
+pub async fn get_queue_depth(
+    State(db): State<PgPool>,
+) -> Result<Json<Vec<QueueDepthRow>>, (StatusCode, String)> {
+    match get_page_processing_queue_depth(&db).await {
+        Ok(queue_depth) => Ok(Json(queue_depth)),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
@@ -0,0 +1,67 @@
+use axum::{debug_handler, extract::State, http::StatusCode, Json};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    app_state::AppState,
+    db::url_rule::{delete_url_rule, get_url_rules, insert_url_rule},
+    models::url_rule::{NewUrlRule, UrlRuleRow},
+    services::url_rules::{reload_url_rules, validate_url_rule_pattern, SharedUrlRules},
+};
+
+pub async fn list_url_rules(
+    State(db): State<PgPool>,
+) -> Result<Json<Vec<UrlRuleRow>>, (StatusCode, String)> {
+    match get_url_rules(&db).await {
+        Ok(url_rules) => Ok(Json(url_rules)),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+#[debug_handler(state = AppState)]
+pub async fn add_url_rule(
+    State(db): State<PgPool>,
+    State(url_rules): State<SharedUrlRules>,
+    Json(new_url_rule): Json<NewUrlRule>,
+) -> Result<Json<UrlRuleRow>, (StatusCode, String)> {
+    if let Err(e) = validate_url_rule_pattern(new_url_rule.match_type, &new_url_rule.pattern) {
+        return Err((StatusCode::BAD_REQUEST, e.to_string()));
+    }
+
+    let url_rule = insert_url_rule(&db, &new_url_rule)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    reload_url_rules(&db, &url_rules)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(url_rule))
+}
+
+#[derive(Deserialize)]
+pub struct WithUrlRuleId {
+    id: i32,
+}
+
+#[debug_handler(state = AppState)]
+pub async fn remove_url_rule(
+    State(db): State<PgPool>,
+    State(url_rules): State<SharedUrlRules>,
+    Json(params): Json<WithUrlRuleId>,
+) -> Result<Json<()>, (StatusCode, String)> {
+    let deleted = delete_url_rule(&db, params.id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !deleted {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Url rule {} doesn't exist", params.id),
+        ));
+    }
+
+    reload_url_rules(&db, &url_rules)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(()))
+}
@@ -1,3 +1,5 @@
+mod app_state;
+mod cli;
 mod config;
 mod db;
 mod handlers;
@@ -5,12 +7,33 @@ mod models;
 mod routes;
 mod services;
 
-use sqlx::postgres::PgPoolOptions;
+use std::sync::{Arc, RwLock};
 
+use clap::Parser;
+use sqlx::{postgres::PgPoolOptions, PgPool};
+
+use app_state::AppState;
+use cli::{Cli, Command};
+use config::Config;
 use routes::create_router;
+use services::{
+    clustering::cluster_labels::spawn_cluster_labeler,
+    ingestion_queue,
+    page_snapshots::SnapshotTextExtractor,
+    preprocessing::{
+        pipeline_config::load_pipelines_config, pipelines::get_all_preprocessing_pipelines,
+    },
+    runs::register_runs,
+    url_canonicalization::{
+        apply_url_canonicalization_config_if_changed, load_url_canonicalization_config,
+        UrlCanonicalizer,
+    },
+    url_rules::load_url_rules,
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
     let config = config::load_config()?;
 
     let db = PgPoolOptions::new()
@@ -18,7 +41,71 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .connect(&config.database_url)
         .await?;
 
-    let app = create_router(db, &config);
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(db, &config).await?,
+        Command::Recluster {
+            pipeline,
+            algorithm,
+        } => cli::recluster(&db, &pipeline, &algorithm).await?,
+        Command::Backfill {
+            pipeline,
+            concurrency,
+            algorithm,
+        } => {
+            let pipelines_config = load_pipelines_config(&config.pipelines_config)?;
+            cli::backfill(
+                &db,
+                &pipelines_config,
+                &pipeline,
+                concurrency,
+                algorithm.as_deref(),
+            )
+            .await?
+        }
+        Command::Purge => cli::purge(&db).await?,
+        Command::Canonicalize => {
+            let url_canonicalization_config =
+                load_url_canonicalization_config(&config.url_canonicalization_config)?;
+            cli::canonicalize(&db, &url_canonicalization_config).await?
+        }
+        Command::Benchmark {
+            pages,
+            queries,
+            dimension,
+        } => cli::benchmark(&db, pages, queries, dimension).await?,
+    }
+
+    Ok(())
+}
+
+async fn serve(db: PgPool, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let pipelines_config = load_pipelines_config(&config.pipelines_config)?;
+    let preprocessing_pipelines = get_all_preprocessing_pipelines(&pipelines_config)?;
+    register_runs(&db, &preprocessing_pipelines).await?;
+    let url_rules = load_url_rules(&db).await?;
+    let url_canonicalization_config =
+        load_url_canonicalization_config(&config.url_canonicalization_config)?;
+    let url_canonicalizer = UrlCanonicalizer::new(&url_canonicalization_config)?;
+    if let Some(summary) =
+        apply_url_canonicalization_config_if_changed(&db, &url_canonicalization_config).await?
+    {
+        println!(
+            "Url canonicalization config changed, canonicalized stored urls: {:?}",
+            summary
+        );
+    }
+    let state = AppState {
+        db,
+        preprocessing_pipelines: Arc::new(preprocessing_pipelines),
+        url_rules: Arc::new(RwLock::new(url_rules)),
+        url_canonicalizer: Arc::new(url_canonicalizer),
+        snapshot_text_extractor: Arc::new(SnapshotTextExtractor::new()?),
+    };
+
+    ingestion_queue::spawn_workers(state.clone(), config.ingestion_workers).await?;
+    spawn_cluster_labeler(state.db.clone());
+
+    let app = create_router(state, config);
 
     let listener = tokio::net::TcpListener::bind(&config.server_address)
         .await
@@ -1,16 +1,33 @@
 pub mod browse_event;
 pub mod cluster;
+pub mod page_processing_job;
+pub mod run;
+pub mod url_rule;
 
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, FixedOffset, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use uuid::Uuid;
 
 #[derive(FromRow)]
 pub struct PageRow {
     pub id: i32,
     pub url: String,
+    /// Contents of the current snapshot
     pub contents: Option<String>,
     pub created_at: Option<DateTime<Utc>>,
+    pub current_snapshot_id: Option<i32>,
+}
+
+#[derive(FromRow)]
+pub struct PageSnapshotRow {
+    pub id: i32,
+    pub page_id: i32,
+    /// Hash of the snapshot text extracted from the contents, not of the contents themselves.
+    /// Snapshots migrated from `page.contents` hash their raw contents instead.
+    pub content_hash: String,
+    pub contents: String,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Serialize, FromRow)]
@@ -18,6 +35,12 @@ pub struct PageUrlRow {
     pub url: String,
 }
 
+#[derive(FromRow)]
+pub struct PageIdUrlRow {
+    pub id: i32,
+    pub url: String,
+}
+
 #[derive(FromRow)]
 pub struct PreprocessedPageEmbeddingRow {
     pub id: i32,
@@ -25,20 +48,33 @@ pub struct PreprocessedPageEmbeddingRow {
     pub embedding_run: String,
     pub embedding: pgvector::Vector,
     pub created_at: Option<DateTime<Utc>>,
+    pub page_snapshot_id: i32,
+}
+
+#[derive(FromRow)]
+pub struct PageEmbeddingRow {
+    pub page_id: i32,
+    pub embedding: pgvector::Vector,
 }
 
 #[derive(FromRow)]
 pub struct PageInfoRowWithCluster {
     pub page_url: String,
     pub page_embedding: pgvector::Vector,
-    pub cluster_id: String,
+    pub cluster_id: Uuid,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DwellTimeBucket {
+    /// Local start of the bucket, with its UTC offset so repeated local times stay apart
+    pub timestamp_bucket: DateTime<FixedOffset>,
+    pub cluster_id: Uuid,
+    pub cluster_name: String,
+    pub seconds: f64,
 }
 
-#[derive(Deserialize, Serialize, FromRow, Debug)]
-pub struct EventCountBucket {
-    pub timestamp_bucket: Option<NaiveDateTime>,
-    pub cluster_id: Option<String>,
-    pub cluster_name: Option<String>,
-    // pub clustering_algorithm: Option<String>,
-    pub event_count: Option<i64>,
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PageDwellTimeRow {
+    pub page_url: String,
+    pub seconds: f64,
 }
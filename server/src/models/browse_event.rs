@@ -1,24 +1,47 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Deserialize, Serialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq)]
+#[sqlx(type_name = "browse_event_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum BrowseEventType {
+    /// A tab was switched to or created
+    Activate,
+    /// A tab finished loading new contents
+    Update,
+    /// A browser window gained focus, or the user came back from being idle
+    FocusGained,
+    /// No browser window has focus anymore
+    FocusLost,
+    /// The user went idle or locked the screen
+    Idle,
+    TabClosed,
+    BrowserClosed,
+}
 
 #[derive(Deserialize, Serialize, FromRow, Debug)]
 pub struct BrowseEventFromChromeExtension {
+    /// Generated by the extension so replayed events can be deduplicated. Only required in batches.
+    pub event_id: Option<Uuid>,
     pub tab_id: i32,
     pub timestamp: DateTime<Utc>,
     pub page_url: String,
     pub page_title: String,
     pub page_content: Option<String>,
-    pub event_type: String, // TODO: make this into an enum
+    pub event_type: BrowseEventType,
 }
 #[derive(Deserialize, Serialize, FromRow, Debug)]
 pub struct BrowseEventRow {
     pub id: i32,
     pub timestamp: DateTime<Utc>,
     pub tab_id: i32,
+    pub page_id: Option<i32>,
     pub page_url: String,
     pub page_title: String,
-    pub event_type: String,
+    pub event_type: BrowseEventType,
+    pub client_event_id: Option<Uuid>,
 }
 
 #[derive(Deserialize, Serialize, FromRow, Debug)]
@@ -28,6 +51,22 @@ pub struct BrowseEventRowWithCluster {
     pub tab_id: i32,
     pub page_url: String,
     pub page_title: String,
-    pub page_cluster_id: Option<String>,
-    pub event_type: String,
+    pub page_cluster_id: Option<Uuid>,
+    pub event_type: BrowseEventType,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum LogEventStatus {
+    Inserted { id: i32 },
+    Duplicate,
+    Ignored,
+    Failed { error: String },
+}
+
+#[derive(Serialize, Debug)]
+pub struct LogEventResult {
+    pub event_id: Uuid,
+    #[serde(flatten)]
+    pub status: LogEventStatus,
 }
@@ -1,21 +1,139 @@
+use anyhow::{bail, Error};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use uuid::Uuid;
 
 #[derive(Deserialize, Serialize, FromRow)]
 pub struct ClusterRow {
-    pub id: String,
+    pub id: Uuid,
     pub name: String,
     pub clustering_run: String,
+    /// Set when the user named the cluster, recomputed labels then leave the name alone
+    pub name_pinned: bool,
+    pub label_version: i32,
+}
+
+/// A cluster whose label was computed from an older membership, or never
+#[derive(FromRow)]
+pub struct StaleClusterRow {
+    pub id: Uuid,
+    pub membership_version: i32,
+}
+
+/// How often a term occurs in the current snapshots of a cluster's members
+#[derive(FromRow)]
+pub struct ClusterTermCountRow {
+    pub cluster_id: Uuid,
+    pub term: String,
+    pub count: i64,
 }
 
 #[derive(FromRow)]
 pub struct ClusterAssignmentRow {
     pub id: i32,
     pub page_id: i32,
-    pub cluster_id: String,
+    pub cluster_id: Uuid,
+    pub clustering_run: String,
 }
 
-#[derive(FromRow, Serialize)]
-pub struct ClusteringRunRow {
-    pub clustering_run: String,
+#[derive(FromRow)]
+pub struct PageClusterRow {
+    pub page_id: i32,
+    pub cluster_id: Uuid,
+    pub cluster_name: String,
+}
+
+/// How embeddings are compared, and how that maps onto pgvector's distance operators.
+///
+/// Thresholds are always given as similarities (higher is closer) and converted to the distance
+/// pgvector's operator returns (lower is closer) before querying.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SimilarityMetric {
+    /// Cosine similarity in [-1, 1], where `<=>` returns `1 - similarity`
+    Cosine,
+    /// `1 / (1 + d)` for the euclidean distance `d` returned by `<->`
+    L2,
+    /// Raw inner product, where `<#>` returns its negation
+    InnerProduct,
+}
+
+impl SimilarityMetric {
+    pub fn distance_operator(&self) -> &'static str {
+        match self {
+            SimilarityMetric::Cosine => "<=>",
+            SimilarityMetric::L2 => "<->",
+            SimilarityMetric::InnerProduct => "<#>",
+        }
+    }
+
+    /// The pgvector operator class of HNSW indexes that serve `distance_operator`
+    pub fn index_operator_class(&self) -> &'static str {
+        match self {
+            SimilarityMetric::Cosine => "vector_cosine_ops",
+            SimilarityMetric::L2 => "vector_l2_ops",
+            SimilarityMetric::InnerProduct => "vector_ip_ops",
+        }
+    }
+
+    /// L2 similarities are in (0, 1], anything outside has no distance
+    pub fn similarity_to_distance(&self, similarity: f32) -> Result<f32, Error> {
+        match self {
+            SimilarityMetric::Cosine => Ok(1.0 - similarity),
+            SimilarityMetric::L2 => {
+                if !(similarity > 0.0 && similarity <= 1.0) {
+                    bail!("L2 similarity must be in (0, 1], got {}", similarity);
+                }
+                Ok(1.0 / similarity - 1.0)
+            }
+            SimilarityMetric::InnerProduct => Ok(-similarity),
+        }
+    }
+
+    pub fn distance_to_similarity(&self, distance: f32) -> f32 {
+        match self {
+            SimilarityMetric::Cosine => 1.0 - distance,
+            SimilarityMetric::L2 => 1.0 / (1.0 + distance),
+            SimilarityMetric::InnerProduct => -distance,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn similarity_to_distance_round_trips() {
+        for metric in [
+            SimilarityMetric::Cosine,
+            SimilarityMetric::L2,
+            SimilarityMetric::InnerProduct,
+        ] {
+            for similarity in [0.25, 0.8, 0.95, 1.0] {
+                let distance = metric.similarity_to_distance(similarity).unwrap();
+                let round_tripped = metric.distance_to_similarity(distance);
+                assert!(
+                    (round_tripped - similarity).abs() < 1e-6,
+                    "{:?}: {} became {}",
+                    metric,
+                    similarity,
+                    round_tripped
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn l2_similarity_outside_unit_interval_is_rejected() {
+        for similarity in [0.0, -0.5, 1.5, f32::NAN] {
+            assert!(SimilarityMetric::L2
+                .similarity_to_distance(similarity)
+                .is_err());
+        }
+        assert_eq!(
+            SimilarityMetric::L2.similarity_to_distance(1.0).unwrap(),
+            0.0
+        );
+    }
 }
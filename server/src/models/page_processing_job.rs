@@ -0,0 +1,22 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct PageProcessingJobRow {
+    pub id: i32,
+    pub page_id: i32,
+    pub status: String,
+    pub attempts: i32,
+    pub run_after: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub page_snapshot_id: i32,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct QueueDepthRow {
+    pub status: String,
+    pub job_count: i64,
+}
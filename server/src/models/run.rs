@@ -0,0 +1,56 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// Online runs stay `Running` for as long as pages keep getting embedded and assigned
+#[derive(Deserialize, Serialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq)]
+#[sqlx(type_name = "run_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct EmbeddingRunRow {
+    pub name: String,
+    pub pipeline_name: String,
+    pub model_id: Option<String>,
+    pub embedding_dimension: Option<i32>,
+    pub parameters: serde_json::Value,
+    pub code_version: Option<String>,
+    pub status: RunStatus,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct NewEmbeddingRun<'a> {
+    pub name: &'a str,
+    pub pipeline_name: &'a str,
+    pub model_id: &'a str,
+    pub embedding_dimension: i32,
+    pub parameters: serde_json::Value,
+}
+
+pub struct NewClusteringRun<'a> {
+    pub name: &'a str,
+    pub embedding_run: &'a str,
+    pub algorithm: &'a str,
+    pub parameters: serde_json::Value,
+    pub status: RunStatus,
+}
+
+/// A clustering run along with the embedding run it clusters
+#[derive(Serialize, FromRow, Debug)]
+pub struct ClusteringRunRow {
+    pub clustering_run: String,
+    pub embedding_run: Option<String>,
+    pub pipeline_name: Option<String>,
+    pub model_id: Option<String>,
+    pub embedding_dimension: Option<i32>,
+    pub algorithm: String,
+    pub parameters: serde_json::Value,
+    pub code_version: Option<String>,
+    pub status: RunStatus,
+    pub created_at: DateTime<Utc>,
+}
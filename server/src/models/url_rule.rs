@@ -0,0 +1,46 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+#[derive(Deserialize, Serialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq)]
+#[sqlx(type_name = "url_rule_match_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum UrlMatchType {
+    /// Matches the host, with `*` matching any run of characters, e.g. `*.google.com`
+    DomainGlob,
+    /// Matches anywhere in the full url
+    Regex,
+    /// Matches the start of host + path, e.g. `docs.google.com/document`
+    PathPrefix,
+}
+
+/// Ordered from least to most restrictive, so the strictest matching rule wins
+#[derive(
+    Deserialize, Serialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[sqlx(type_name = "url_rule_action", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum UrlRuleAction {
+    /// Keep the event, but never store the page's contents
+    DropContent,
+    /// Keep the event with its url reduced to the domain, and no title or contents
+    DomainOnly,
+    /// Don't log the event at all
+    DropEvent,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct UrlRuleRow {
+    pub id: i32,
+    pub match_type: UrlMatchType,
+    pub pattern: String,
+    pub action: UrlRuleAction,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewUrlRule {
+    pub match_type: UrlMatchType,
+    pub pattern: String,
+    pub action: UrlRuleAction,
+}
@@ -1,28 +1,46 @@
 use axum::{
+    extract::DefaultBodyLimit,
     http::{HeaderValue, Method},
     routing::{get, post},
     Router,
 };
-use sqlx::PgPool;
 use tower_http::cors::{Any, CorsLayer};
 
+use crate::app_state::AppState;
 use crate::handlers::analytics_handlers::{
-    get_clustering_runs, get_event_buckets, get_pages, return_all_events,
+    get_clustering_runs, get_event_buckets, get_page_dwell_times, get_pages, return_all_events,
 };
-use crate::handlers::browse_event_handlers::log_browse_event;
+use crate::handlers::browse_event_handlers::{log_browse_event, log_browse_events};
+use crate::handlers::cluster_handlers::{pin_name, unpin_name};
+use crate::handlers::queue_handlers::get_queue_depth;
+use crate::handlers::url_rule_handlers::{add_url_rule, list_url_rules, remove_url_rule};
 use crate::{config::Config, handlers::analytics_handlers::get_clusters};
 
-pub fn create_router(db: PgPool, config: &Config) -> Router {
+// Batches of buffered events carry page HTML, so they need more than axum's default 2MB
+const LOG_EVENTS_BODY_LIMIT: usize = 64 * 1024 * 1024;
+
+pub fn create_router(state: AppState, config: &Config) -> Router {
     let cors = create_cors_layer(config);
 
     Router::new()
         .route("/log_event", post(log_browse_event))
+        .route(
+            "/log_events",
+            post(log_browse_events).layer(DefaultBodyLimit::max(LOG_EVENTS_BODY_LIMIT)),
+        )
         .route("/return_all_events", get(return_all_events))
         .route("/get_event_buckets", get(get_event_buckets))
+        .route("/get_page_dwell_times", get(get_page_dwell_times))
         .route("/get_pages", get(get_pages))
         .route("/get_clusters", get(get_clusters))
         .route("/get_clustering_runs", get(get_clustering_runs))
-        .with_state(db)
+        .route("/pin_cluster_name", post(pin_name))
+        .route("/unpin_cluster_name", post(unpin_name))
+        .route("/get_queue_depth", get(get_queue_depth))
+        .route("/get_url_rules", get(list_url_rules))
+        .route("/add_url_rule", post(add_url_rule))
+        .route("/delete_url_rule", post(remove_url_rule))
+        .with_state(state)
         .layer(cors)
 }
 
@@ -1,3 +1,12 @@
+pub mod backfill;
+pub mod benchmark;
 pub mod clustering;
+pub mod event_buckets;
+pub mod ingestion_queue;
+pub mod page_snapshots;
 pub mod preprocessing;
+pub mod runs;
+pub mod sessionization;
+pub mod url_canonicalization;
+pub mod url_rules;
 pub mod utils;
@@ -0,0 +1,196 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Error};
+use futures::TryStreamExt;
+use sqlx::PgPool;
+
+use crate::{
+    db::{
+        page::get_page_from_id,
+        page_snapshot::{get_page_snapshot, stream_snapshot_ids_missing_embedding},
+        preprocessed_page_embedding::{
+            get_latest_page_embedding, stream_pages_missing_assignments,
+        },
+    },
+    services::{
+        clustering::{
+            batch::run_batch_clustering,
+            cluster_algorithms::{
+                clustering_run_name, get_all_cluster_algorithms, get_cluster_algorithm,
+            },
+        },
+        ingestion_queue::{
+            assign_page_to_online_runs, get_or_embed_page_snapshot, load_page_context,
+        },
+        preprocessing::pipeline::PreprocessingPipeline,
+        runs::register_runs,
+    },
+};
+
+const PROGRESS_INTERVAL: usize = 100;
+
+#[derive(Debug, Default)]
+pub struct BackfillSummary {
+    pub embedded: usize,
+    pub failed: usize,
+    pub assigned: usize,
+    pub batch_clustering_run: Option<String>,
+}
+
+/// Embeds every page snapshot the pipeline hasn't embedded yet, up to `concurrency` at a time,
+/// then clusters them: with `batch_algorithm` into a fresh batch run, otherwise by assigning them
+/// in the pipeline's online runs.
+///
+/// Only missing embeddings and assignments are computed, so rerunning after an interruption picks
+/// up where it stopped. Pages that fail are logged and left for the next run.
+pub async fn backfill_pipeline(
+    db: &PgPool,
+    preprocessing_pipeline: PreprocessingPipeline,
+    concurrency: usize,
+    batch_algorithm: Option<&str>,
+) -> Result<BackfillSummary, Error> {
+    if concurrency == 0 {
+        bail!("Concurrency has to be at least 1");
+    }
+    let batch_algorithm = batch_algorithm
+        .map(|name| {
+            get_cluster_algorithm(name)
+                .with_context(|| format!("Unknown clustering algorithm {}", name))
+        })
+        .transpose()?;
+    if let Some(algorithm) = &batch_algorithm {
+        if algorithm.is_online() {
+            bail!(
+                "{} is an online algorithm, leave out --algorithm to assign pages online",
+                algorithm.name()
+            );
+        }
+    }
+
+    let preprocessing_pipelines = Arc::new(vec![preprocessing_pipeline]);
+    register_runs(db, &preprocessing_pipelines).await?;
+    let embedding_run = preprocessing_pipelines[0].name.clone();
+
+    let mut summary = BackfillSummary::default();
+    embed_missing_pages(db, &preprocessing_pipelines, concurrency, &mut summary).await?;
+
+    match batch_algorithm {
+        Some(algorithm) => {
+            let clustering_run =
+                run_batch_clustering(db, &embedding_run, algorithm.as_ref()).await?;
+            summary.batch_clustering_run = Some(clustering_run);
+        }
+        None => assign_missing_pages(db, &embedding_run, &mut summary).await?,
+    }
+
+    Ok(summary)
+}
+
+async fn embed_missing_pages(
+    db: &PgPool,
+    preprocessing_pipelines: &Arc<Vec<PreprocessingPipeline>>,
+    concurrency: usize,
+    summary: &mut BackfillSummary,
+) -> Result<(), Error> {
+    let embedding_run = &preprocessing_pipelines[0].name;
+    let mut results = stream_snapshot_ids_missing_embedding(db, embedding_run)
+        .map_err(Error::from)
+        .map_ok(|page_snapshot_id| async move {
+            let result = embed_page_snapshot(db, preprocessing_pipelines, page_snapshot_id).await;
+            Ok::<_, Error>((page_snapshot_id, result))
+        })
+        .try_buffer_unordered(concurrency);
+
+    while let Some((page_snapshot_id, result)) = results.try_next().await? {
+        match result {
+            Ok(()) => summary.embedded += 1,
+            Err(e) => {
+                eprintln!(
+                    "Failed to embed page snapshot {}: {:?}",
+                    page_snapshot_id, e
+                );
+                summary.failed += 1;
+            }
+        }
+
+        let processed = summary.embedded + summary.failed;
+        if processed % PROGRESS_INTERVAL == 0 {
+            println!(
+                "Embedded {} page snapshots with {}",
+                processed, embedding_run
+            );
+        }
+    }
+
+    Ok(())
+}
+
+async fn embed_page_snapshot(
+    db: &PgPool,
+    preprocessing_pipelines: &Arc<Vec<PreprocessingPipeline>>,
+    page_snapshot_id: i32,
+) -> Result<(), Error> {
+    let page_snapshot = get_page_snapshot(db, page_snapshot_id)
+        .await?
+        .with_context(|| format!("Page snapshot {} doesn't exist", page_snapshot_id))?;
+    let page_row = get_page_from_id(db, page_snapshot.page_id)
+        .await?
+        .with_context(|| format!("Page {} doesn't exist", page_snapshot.page_id))?;
+    let page_context = load_page_context(db, &page_row, &page_snapshot).await?;
+    get_or_embed_page_snapshot(
+        db,
+        preprocessing_pipelines,
+        0,
+        &page_context,
+        &page_snapshot,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Online assignment depends on the pages that came before, so pages are assigned one at a time,
+/// in the order they were stored, each with the embedding of its latest snapshot
+async fn assign_missing_pages(
+    db: &PgPool,
+    embedding_run: &str,
+    summary: &mut BackfillSummary,
+) -> Result<(), Error> {
+    let cluster_algorithms: Vec<_> = get_all_cluster_algorithms()
+        .into_iter()
+        .filter(|algorithm| algorithm.is_online())
+        .collect();
+    let clustering_runs: Vec<String> = cluster_algorithms
+        .iter()
+        .map(|algorithm| clustering_run_name(embedding_run, algorithm.as_ref()))
+        .collect();
+
+    let mut page_ids = stream_pages_missing_assignments(db, embedding_run, &clustering_runs);
+    while let Some(page_id) = page_ids.try_next().await? {
+        let page_row = get_page_from_id(db, page_id)
+            .await?
+            .with_context(|| format!("Page {} doesn't exist", page_id))?;
+        let embedding_row = get_latest_page_embedding(db, page_id, embedding_run)
+            .await?
+            .with_context(|| format!("Page {} has no {} embedding", page_id, embedding_run))?;
+
+        assign_page_to_online_runs(
+            db,
+            &page_row,
+            &embedding_row.embedding,
+            embedding_run,
+            &cluster_algorithms,
+        )
+        .await?;
+
+        summary.assigned += 1;
+        if summary.assigned % PROGRESS_INTERVAL == 0 {
+            println!(
+                "Assigned {} pages in {:?}",
+                summary.assigned, clustering_runs
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use chrono::Utc;
+use pgvector::Vector;
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    db::{
+        benchmark::{disable_index_scans, insert_benchmark_pages},
+        cluster::get_nearest_cluster_within_distance,
+        run::{create_clustering_run_index, insert_clustering_run, insert_embedding_run},
+    },
+    models::{
+        cluster::SimilarityMetric,
+        run::{NewClusteringRun, NewEmbeddingRun, RunStatus},
+    },
+    services::{
+        clustering::cluster_algorithms::ONLINE_NEAREST_NEIGHBOR_ALGORITHM, runs::CODE_VERSION,
+    },
+};
+
+const BENCHMARK_SIMILARITY_THRESHOLD: f32 = 0.8;
+
+/// Times nearest-cluster lookups against `num_pages` synthetic pages, first through the run's HNSW
+/// index and then with exact scans.
+///
+/// Everything happens in a transaction that's rolled back at the end, so the benchmark leaves no
+/// trace in the database.
+pub async fn benchmark_nearest_cluster(
+    db: &PgPool,
+    num_pages: i32,
+    num_queries: usize,
+    embedding_dimension: i32,
+) -> Result<(), Error> {
+    let mut tx = db.begin().await?;
+
+    let embedding_run = format!("benchmark-{}", Utc::now().format("%Y%m%d%H%M%S"));
+    let clustering_run = format!("{}-{}", embedding_run, ONLINE_NEAREST_NEIGHBOR_ALGORITHM);
+    let new_embedding_run = NewEmbeddingRun {
+        name: &embedding_run,
+        pipeline_name: &embedding_run,
+        model_id: "random",
+        embedding_dimension,
+        parameters: json!({}),
+    };
+    insert_embedding_run(&mut *tx, &new_embedding_run, CODE_VERSION).await?;
+    let new_clustering_run = NewClusteringRun {
+        name: &clustering_run,
+        embedding_run: &embedding_run,
+        algorithm: ONLINE_NEAREST_NEIGHBOR_ALGORITHM,
+        parameters: json!({}),
+        status: RunStatus::Running,
+    };
+    insert_clustering_run(&mut *tx, &new_clustering_run, CODE_VERSION).await?;
+
+    let started = Instant::now();
+    insert_benchmark_pages(
+        &mut tx,
+        &embedding_run,
+        &clustering_run,
+        num_pages,
+        embedding_dimension,
+    )
+    .await?;
+    println!("Inserted {} pages in {:?}", num_pages, started.elapsed());
+
+    let started = Instant::now();
+    create_clustering_run_index(
+        &mut *tx,
+        &clustering_run,
+        embedding_dimension,
+        SimilarityMetric::Cosine,
+    )
+    .await?;
+    println!("Built the index in {:?}", started.elapsed());
+
+    let query_embeddings: Vec<Vector> = (0..num_queries)
+        .map(|query| random_embedding(query as u64 + 1, embedding_dimension as usize))
+        .collect();
+
+    let latencies = time_lookups(&mut tx, &query_embeddings, &clustering_run).await?;
+    print_latencies("HNSW index", latencies);
+
+    disable_index_scans(&mut tx).await?;
+    let latencies = time_lookups(&mut tx, &query_embeddings, &clustering_run).await?;
+    print_latencies("Exact scan", latencies);
+
+    tx.rollback().await?;
+
+    Ok(())
+}
+
+async fn time_lookups(
+    conn: &mut PgConnection,
+    query_embeddings: &[Vector],
+    clustering_run: &str,
+) -> Result<Vec<Duration>, Error> {
+    let max_distance =
+        SimilarityMetric::Cosine.similarity_to_distance(BENCHMARK_SIMILARITY_THRESHOLD)?;
+    let mut latencies = Vec::with_capacity(query_embeddings.len());
+    for query_embedding in query_embeddings {
+        let started = Instant::now();
+        get_nearest_cluster_within_distance(
+            &mut *conn,
+            query_embedding,
+            clustering_run,
+            SimilarityMetric::Cosine,
+            max_distance,
+        )
+        .await?;
+        latencies.push(started.elapsed());
+    }
+
+    Ok(latencies)
+}
+
+fn print_latencies(label: &str, mut latencies: Vec<Duration>) {
+    if latencies.is_empty() {
+        return;
+    }
+
+    latencies.sort();
+    let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+    let mean = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+    println!(
+        "{}: mean {:?}, p50 {:?}, p95 {:?}, max {:?}",
+        label,
+        mean,
+        percentile(50),
+        percentile(95),
+        percentile(100)
+    );
+}
+
+/// Deterministic pseudo-random vector with components in [-0.5, 0.5), using xorshift
+fn random_embedding(seed: u64, embedding_dimension: usize) -> Vector {
+    let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    let components = (0..embedding_dimension)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+        })
+        .collect::<Vec<f32>>();
+
+    Vector::from(components)
+}
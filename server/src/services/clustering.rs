@@ -1,12 +1,7 @@
-use anyhow::Error;
-use pgvector::Vector;
-use sqlx::PgPool;
-use std::hash::{DefaultHasher, Hash, Hasher};
-
-use crate::{
-    db::cluster::get_nearest_cluster_above_similarity_threshold,
-    models::{browse_event::BrowseEventFromChromeExtension, cluster::ClusterAssignmentRow},
-};
+pub mod batch;
+pub mod cluster_algorithm;
+pub mod cluster_algorithms;
+pub mod cluster_labels;
 
 fn cosine_similarity(v1: Vec<f32>, v2: Vec<f32>) -> f32 {
     // TODO: make this cleaner, error check for vecs of same length
@@ -19,33 +14,3 @@ fn cosine_similarity(v1: Vec<f32>, v2: Vec<f32>) -> f32 {
     let v2_norm = v2.iter().fold(0.0, |acc, x| acc + (x * x)).sqrt();
     dot_product / (v1_norm * v2_norm)
 }
-
-pub async fn assign_page_to_cluster_id(
-    db: &PgPool,
-    browse_event: &BrowseEventFromChromeExtension,
-    page_embedding: &Vector,
-    embedding_run: &str,
-) -> Result<String, Error> {
-    let close_enough_cluster_distance = 0.95;
-
-    let cluster_assignment_row: Option<ClusterAssignmentRow> =
-        get_nearest_cluster_above_similarity_threshold(
-            db,
-            page_embedding,
-            embedding_run,
-            close_enough_cluster_distance,
-        )
-        .await?;
-
-    let page_cluster_id = match cluster_assignment_row {
-        Some(cluster_assignment_row) => cluster_assignment_row.cluster_id,
-        None => {
-            // TODO: need a better way to come up with cluster ids
-            let mut hasher = DefaultHasher::new();
-            browse_event.page_url.hash(&mut hasher);
-            hasher.finish().to_string()
-        }
-    };
-
-    Ok(page_cluster_id)
-}
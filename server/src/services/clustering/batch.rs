@@ -0,0 +1,262 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use anyhow::{bail, Error};
+use chrono::Utc;
+use sqlx::PgPool;
+
+use crate::{
+    db::{
+        cluster::{insert_cluster, insert_cluster_assignment},
+        preprocessed_page_embedding::get_page_embeddings_for_run,
+        run::{get_embedding_run, insert_clustering_run, set_clustering_run_status},
+    },
+    models::{
+        run::{NewClusteringRun, RunStatus},
+        PageEmbeddingRow,
+    },
+    services::{
+        clustering::{cluster_algorithm::ClusterAlgorithm, cluster_labels::refresh_cluster_labels},
+        runs::CODE_VERSION,
+    },
+};
+
+/// Batch runs get a timestamp suffix so reruns don't overwrite each other
+pub fn batch_clustering_run_name(pipeline_name: &str, algorithm: &dyn ClusterAlgorithm) -> String {
+    format!(
+        "{}-{}-{}",
+        pipeline_name,
+        algorithm.name(),
+        Utc::now().format("%Y%m%d%H%M%S")
+    )
+}
+
+/// Reclusters every stored embedding of `embedding_run` into a fresh clustering run, returning
+/// the new run's name.
+pub async fn run_batch_clustering(
+    db: &PgPool,
+    embedding_run: &str,
+    algorithm: &dyn ClusterAlgorithm,
+) -> Result<String, Error> {
+    if algorithm.is_online() {
+        bail!("{} is an online algorithm", algorithm.name());
+    }
+
+    if get_embedding_run(db, embedding_run).await?.is_none() {
+        bail!("Embedding run {} doesn't exist", embedding_run);
+    }
+
+    let clustering_run = batch_clustering_run_name(embedding_run, algorithm);
+    let new_clustering_run = NewClusteringRun {
+        name: &clustering_run,
+        embedding_run,
+        algorithm: algorithm.name(),
+        parameters: algorithm.parameters(),
+        status: RunStatus::Running,
+    };
+    insert_clustering_run(db, &new_clustering_run, CODE_VERSION).await?;
+
+    match algorithm
+        .recluster(db, embedding_run, &clustering_run)
+        .await
+    {
+        Ok(()) => {
+            set_clustering_run_status(db, &clustering_run, RunStatus::Completed).await?;
+            Ok(clustering_run)
+        }
+        Err(e) => {
+            set_clustering_run_status(db, &clustering_run, RunStatus::Failed).await?;
+            Err(e)
+        }
+    }
+}
+
+pub async fn load_page_embeddings(
+    db: &PgPool,
+    embedding_run: &str,
+) -> Result<(Vec<PageEmbeddingRow>, Vec<Vec<f32>>), Error> {
+    let pages = get_page_embeddings_for_run(db, embedding_run).await?;
+    if pages.is_empty() {
+        bail!(
+            "No page embeddings stored for embedding run {}",
+            embedding_run
+        );
+    }
+
+    let points = pages
+        .iter()
+        .map(|page| normalize(page.embedding.as_slice()))
+        .collect();
+
+    Ok((pages, points))
+}
+
+/// Writes one cluster per label, skipping pages without a label. Everything happens in one
+/// transaction so a failed run doesn't leave half a clustering behind. Clusters are named once
+/// they're all written, since labels are scored against the run's other clusters.
+pub async fn write_clustering_run(
+    db: &PgPool,
+    clustering_run: &str,
+    pages: &[PageEmbeddingRow],
+    labels: &[Option<usize>],
+) -> Result<usize, Error> {
+    let mut members_by_label: BTreeMap<usize, Vec<&PageEmbeddingRow>> = BTreeMap::new();
+    for (page, label) in pages.iter().zip(labels) {
+        if let Some(label) = label {
+            members_by_label.entry(*label).or_default().push(page);
+        }
+    }
+
+    let mut tx = db.begin().await?;
+    for members in members_by_label.values() {
+        let cluster = insert_cluster(&mut *tx, "", clustering_run).await?;
+
+        for member in members {
+            insert_cluster_assignment(
+                &mut *tx,
+                member.page_id,
+                cluster.id,
+                clustering_run,
+                &member.embedding,
+            )
+            .await?;
+        }
+    }
+    tx.commit().await?;
+
+    refresh_cluster_labels(db, clustering_run).await?;
+
+    Ok(members_by_label.len())
+}
+
+fn normalize(v: &[f32]) -> Vec<f32> {
+    let norm = v.iter().fold(0.0, |acc, x| acc + (x * x)).sqrt();
+    if norm == 0.0 {
+        return v.to_vec();
+    }
+
+    v.iter().map(|x| x / norm).collect()
+}
+
+/// Cosine distance between two unit vectors
+fn cosine_distance(v1: &[f32], v2: &[f32]) -> f32 {
+    let dot_product = v1
+        .iter()
+        .zip(v2.iter())
+        .fold(0.0, |acc, (x1, x2)| acc + (x1 * x2));
+    1.0 - dot_product
+}
+
+fn nearest_centroid(point: &[f32], centroids: &[Vec<f32>]) -> (usize, f32) {
+    centroids
+        .iter()
+        .enumerate()
+        .map(|(i, centroid)| (i, cosine_distance(point, centroid)))
+        .fold((0, f32::INFINITY), |best, current| {
+            if current.1 < best.1 {
+                current
+            } else {
+                best
+            }
+        })
+}
+
+/// Spherical k-means over unit vectors. Centroids are seeded deterministically by repeatedly
+/// picking the point farthest from the centroids chosen so far.
+pub fn kmeans(points: &[Vec<f32>], num_clusters: usize, max_iterations: usize) -> Vec<usize> {
+    let Some(first_point) = points.first() else {
+        return vec![];
+    };
+
+    let k = num_clusters.clamp(1, points.len());
+    let mut centroids = vec![first_point.clone()];
+    while centroids.len() < k {
+        let (farthest, _) = points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| (i, nearest_centroid(point, &centroids).1))
+            .fold((0, f32::NEG_INFINITY), |best, current| {
+                if current.1 > best.1 {
+                    current
+                } else {
+                    best
+                }
+            });
+        centroids.push(points[farthest].clone());
+    }
+
+    let dimension = first_point.len();
+    let mut labels: Vec<usize> = vec![];
+    for _ in 0..max_iterations {
+        let new_labels: Vec<usize> = points
+            .iter()
+            .map(|point| nearest_centroid(point, &centroids).0)
+            .collect();
+        if new_labels == labels {
+            break;
+        }
+        labels = new_labels;
+
+        let mut sums = vec![vec![0.0; dimension]; k];
+        for (point, label) in points.iter().zip(&labels) {
+            for (sum, x) in sums[*label].iter_mut().zip(point) {
+                *sum += x;
+            }
+        }
+
+        // Empty clusters keep their previous centroid
+        for (centroid, sum) in centroids.iter_mut().zip(&sums) {
+            if sum.iter().any(|x| *x != 0.0) {
+                *centroid = normalize(sum);
+            }
+        }
+    }
+
+    labels
+}
+
+/// DBSCAN with cosine distance. Points that aren't density-reachable from a core point are
+/// labelled `None`.
+pub fn dbscan(points: &[Vec<f32>], epsilon: f32, min_points: usize) -> Vec<Option<usize>> {
+    let neighbors = |i: usize| -> Vec<usize> {
+        (0..points.len())
+            .filter(|&j| cosine_distance(&points[i], &points[j]) <= epsilon)
+            .collect()
+    };
+
+    let mut labels: Vec<Option<usize>> = vec![None; points.len()];
+    let mut visited = vec![false; points.len()];
+    let mut next_label = 0;
+
+    for i in 0..points.len() {
+        if visited[i] {
+            continue;
+        }
+        visited[i] = true;
+
+        let seed_neighbors = neighbors(i);
+        if seed_neighbors.len() < min_points {
+            continue;
+        }
+
+        labels[i] = Some(next_label);
+        let mut frontier: VecDeque<usize> = seed_neighbors.into();
+        while let Some(j) = frontier.pop_front() {
+            if labels[j].is_none() {
+                labels[j] = Some(next_label);
+            }
+            if visited[j] {
+                continue;
+            }
+            visited[j] = true;
+
+            let j_neighbors = neighbors(j);
+            if j_neighbors.len() >= min_points {
+                frontier.extend(j_neighbors);
+            }
+        }
+
+        next_label += 1;
+    }
+
+    labels
+}
@@ -0,0 +1,374 @@
+use anyhow::{bail, Error};
+use async_trait::async_trait;
+use pgvector::Vector;
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{
+    db::cluster::{get_nearest_cluster_within_distance, insert_cluster, insert_cluster_assignment},
+    models::{
+        cluster::{ClusterAssignmentRow, ClusterRow, SimilarityMetric},
+        PageRow,
+    },
+    services::{
+        clustering::{
+            batch::{dbscan, kmeans, load_page_embeddings, write_clustering_run},
+            cluster_algorithms::{BATCH_DBSCAN_ALGORITHM, BATCH_KMEANS_ALGORITHM},
+        },
+        utils::{extract_keywords, html_to_markdown},
+    },
+};
+
+#[async_trait]
+pub trait ClusterAlgorithm: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Online algorithms assign each page as it's embedded, batch ones only run through `recluster`
+    fn is_online(&self) -> bool;
+
+    /// Thresholds and other settings, stored with each clustering run
+    fn parameters(&self) -> serde_json::Value;
+
+    /// How embeddings are compared, which decides the operator class of the run's vector index
+    fn similarity_metric(&self) -> SimilarityMetric;
+
+    async fn assign_page(
+        &self,
+        _db: &PgPool,
+        _page: &PageRow,
+        _page_embedding: &Vector,
+        _clustering_run: &str,
+    ) -> Result<ClusterAssignmentRow, Error> {
+        bail!("{} can't assign individual pages", self.name())
+    }
+
+    async fn recluster(
+        &self,
+        _db: &PgPool,
+        _embedding_run: &str,
+        _clustering_run: &str,
+    ) -> Result<(), Error> {
+        bail!("{} can't recluster in batch", self.name())
+    }
+}
+
+/// Assigns each page to the cluster with the most similar centroid, or starts a new cluster when
+/// nothing clears the threshold. Each registered instance is its own clustering run, so thresholds
+/// and metrics are configured per run.
+pub struct NearestNeighborAlgorithm {
+    name: &'static str,
+    similarity_metric: SimilarityMetric,
+    similarity_threshold: f32,
+}
+
+impl NearestNeighborAlgorithm {
+    pub fn new(
+        name: &'static str,
+        similarity_metric: SimilarityMetric,
+        similarity_threshold: f32,
+    ) -> Self {
+        NearestNeighborAlgorithm {
+            name,
+            similarity_metric,
+            similarity_threshold,
+        }
+    }
+}
+
+#[async_trait]
+impl ClusterAlgorithm for NearestNeighborAlgorithm {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn is_online(&self) -> bool {
+        true
+    }
+
+    fn similarity_metric(&self) -> SimilarityMetric {
+        self.similarity_metric
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "similarity_metric": self.similarity_metric,
+            "similarity_threshold": self.similarity_threshold,
+        })
+    }
+
+    async fn assign_page(
+        &self,
+        db: &PgPool,
+        page: &PageRow,
+        page_embedding: &Vector,
+        clustering_run: &str,
+    ) -> Result<ClusterAssignmentRow, Error> {
+        let max_distance = self
+            .similarity_metric
+            .similarity_to_distance(self.similarity_threshold)?;
+        let nearest_cluster_id = get_nearest_cluster_within_distance(
+            db,
+            page_embedding,
+            clustering_run,
+            self.similarity_metric,
+            max_distance,
+        )
+        .await?;
+
+        let page_cluster_id = match nearest_cluster_id {
+            Some(cluster_id) => cluster_id,
+            None => {
+                println!("creating a new cluster!");
+                let page_content = page.contents.as_deref().unwrap_or_default();
+                create_cluster_and_add_to_database(db, page_content, clustering_run)
+                    .await?
+                    .id
+            }
+        };
+
+        let cluster_assignment =
+            insert_cluster_assignment(db, page.id, page_cluster_id, clustering_run, page_embedding)
+                .await?;
+
+        Ok(cluster_assignment)
+    }
+}
+
+pub struct KMeansAlgorithm {
+    num_clusters: usize,
+    max_iterations: usize,
+}
+
+impl KMeansAlgorithm {
+    pub fn new(num_clusters: usize, max_iterations: usize) -> Self {
+        KMeansAlgorithm {
+            num_clusters,
+            max_iterations,
+        }
+    }
+}
+
+#[async_trait]
+impl ClusterAlgorithm for KMeansAlgorithm {
+    fn name(&self) -> &'static str {
+        BATCH_KMEANS_ALGORITHM
+    }
+
+    fn is_online(&self) -> bool {
+        false
+    }
+
+    fn similarity_metric(&self) -> SimilarityMetric {
+        SimilarityMetric::Cosine
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "num_clusters": self.num_clusters,
+            "max_iterations": self.max_iterations,
+        })
+    }
+
+    async fn recluster(
+        &self,
+        db: &PgPool,
+        embedding_run: &str,
+        clustering_run: &str,
+    ) -> Result<(), Error> {
+        let (pages, points) = load_page_embeddings(db, embedding_run).await?;
+        let labels: Vec<Option<usize>> = kmeans(&points, self.num_clusters, self.max_iterations)
+            .into_iter()
+            .map(Some)
+            .collect();
+
+        let num_clusters = write_clustering_run(db, clustering_run, &pages, &labels).await?;
+        println!(
+            "{}: {} pages in {} clusters",
+            clustering_run,
+            pages.len(),
+            num_clusters
+        );
+
+        Ok(())
+    }
+}
+
+pub struct DbscanAlgorithm {
+    max_cosine_distance: f32,
+    min_points: usize,
+}
+
+impl DbscanAlgorithm {
+    pub fn new(max_cosine_distance: f32, min_points: usize) -> Self {
+        DbscanAlgorithm {
+            max_cosine_distance,
+            min_points,
+        }
+    }
+}
+
+#[async_trait]
+impl ClusterAlgorithm for DbscanAlgorithm {
+    fn name(&self) -> &'static str {
+        BATCH_DBSCAN_ALGORITHM
+    }
+
+    fn is_online(&self) -> bool {
+        false
+    }
+
+    fn similarity_metric(&self) -> SimilarityMetric {
+        SimilarityMetric::Cosine
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "max_cosine_distance": self.max_cosine_distance,
+            "min_points": self.min_points,
+        })
+    }
+
+    async fn recluster(
+        &self,
+        db: &PgPool,
+        embedding_run: &str,
+        clustering_run: &str,
+    ) -> Result<(), Error> {
+        let (pages, points) = load_page_embeddings(db, embedding_run).await?;
+        let labels = dbscan(&points, self.max_cosine_distance, self.min_points);
+
+        let num_clusters = write_clustering_run(db, clustering_run, &pages, &labels).await?;
+        let num_noise_pages = labels.iter().filter(|label| label.is_none()).count();
+        println!(
+            "{}: {} pages in {} clusters, {} left as noise",
+            clustering_run,
+            pages.len(),
+            num_clusters,
+            num_noise_pages
+        );
+
+        Ok(())
+    }
+}
+
+async fn create_cluster_and_add_to_database(
+    db: &PgPool,
+    page_content: &str,
+    clustering_run: &str,
+) -> Result<ClusterRow, Error> {
+    // Only a placeholder until the cluster labeler names the cluster from all its members
+    let num_keywords = 5;
+    let page_markdown = html_to_markdown(page_content)?;
+    let cluster_keywords = extract_keywords(&page_markdown, num_keywords);
+    let cluster_name = cluster_keywords.join(" ");
+    let cluster_row: ClusterRow = insert_cluster(db, &cluster_name, clustering_run).await?;
+
+    Ok(cluster_row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{
+            page::insert_page,
+            run::{insert_clustering_run, insert_embedding_run},
+        },
+        models::run::{NewClusteringRun, NewEmbeddingRun, RunStatus},
+    };
+    use uuid::Uuid;
+
+    const EMBEDDING_RUN: &str = "test";
+    const CLUSTERING_RUN: &str = "test-online-nearest-neighbor";
+
+    async fn insert_runs(db: &PgPool, algorithm: &NearestNeighborAlgorithm) -> Result<(), Error> {
+        let new_embedding_run = NewEmbeddingRun {
+            name: EMBEDDING_RUN,
+            pipeline_name: EMBEDDING_RUN,
+            model_id: "test",
+            embedding_dimension: 3,
+            parameters: json!({}),
+        };
+        insert_embedding_run(db, &new_embedding_run, "test").await?;
+        let new_clustering_run = NewClusteringRun {
+            name: CLUSTERING_RUN,
+            embedding_run: EMBEDDING_RUN,
+            algorithm: algorithm.name(),
+            parameters: algorithm.parameters(),
+            status: RunStatus::Running,
+        };
+        insert_clustering_run(db, &new_clustering_run, "test").await?;
+
+        Ok(())
+    }
+
+    /// Assigns a page per embedding, in order, returning the cluster of each
+    async fn assign_pages(
+        db: &PgPool,
+        algorithm: &NearestNeighborAlgorithm,
+        embeddings: &[[f32; 3]],
+    ) -> Result<Vec<Uuid>, Error> {
+        insert_runs(db, algorithm).await?;
+
+        let mut cluster_ids = Vec::new();
+        for (i, embedding) in embeddings.iter().enumerate() {
+            let page = insert_page(db, &format!("https://example.com/{}", i)).await?;
+            let cluster_assignment = algorithm
+                .assign_page(db, &page, &Vector::from(embedding.to_vec()), CLUSTERING_RUN)
+                .await?;
+            cluster_ids.push(cluster_assignment.cluster_id);
+        }
+
+        Ok(cluster_ids)
+    }
+
+    #[sqlx::test]
+    async fn near_duplicates_join_the_same_cluster_by_cosine(db: PgPool) -> Result<(), Error> {
+        let algorithm = NearestNeighborAlgorithm::new(
+            "online-nearest-neighbor",
+            SimilarityMetric::Cosine,
+            0.95,
+        );
+        let cluster_ids = assign_pages(
+            &db,
+            &algorithm,
+            &[[1.0, 0.0, 0.0], [0.99, 0.05, 0.0], [0.0, 1.0, 0.0]],
+        )
+        .await?;
+
+        assert_eq!(cluster_ids[0], cluster_ids[1]);
+        assert_ne!(cluster_ids[0], cluster_ids[2]);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn near_duplicates_join_the_same_cluster_by_l2(db: PgPool) -> Result<(), Error> {
+        // Within a euclidean distance of 0.25
+        let algorithm =
+            NearestNeighborAlgorithm::new("online-nearest-neighbor", SimilarityMetric::L2, 0.8);
+        let cluster_ids = assign_pages(
+            &db,
+            &algorithm,
+            &[[1.0, 0.0, 0.0], [1.0, 0.1, 0.0], [1.0, 1.0, 0.0]],
+        )
+        .await?;
+
+        assert_eq!(cluster_ids[0], cluster_ids[1]);
+        assert_ne!(cluster_ids[0], cluster_ids[2]);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn invalid_l2_threshold_fails_instead_of_clustering(db: PgPool) -> Result<(), Error> {
+        let algorithm =
+            NearestNeighborAlgorithm::new("online-nearest-neighbor", SimilarityMetric::L2, 0.0);
+
+        assert!(assign_pages(&db, &algorithm, &[[1.0, 0.0, 0.0]])
+            .await
+            .is_err());
+
+        Ok(())
+    }
+}
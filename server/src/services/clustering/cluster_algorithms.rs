@@ -0,0 +1,33 @@
+use crate::{
+    models::cluster::SimilarityMetric,
+    services::clustering::cluster_algorithm::{
+        ClusterAlgorithm, DbscanAlgorithm, KMeansAlgorithm, NearestNeighborAlgorithm,
+    },
+};
+
+pub const ONLINE_NEAREST_NEIGHBOR_ALGORITHM: &str = "online-nearest-neighbor";
+pub const BATCH_KMEANS_ALGORITHM: &str = "batch-kmeans";
+pub const BATCH_DBSCAN_ALGORITHM: &str = "batch-dbscan";
+
+pub fn get_all_cluster_algorithms() -> Vec<Box<dyn ClusterAlgorithm>> {
+    vec![
+        Box::new(NearestNeighborAlgorithm::new(
+            ONLINE_NEAREST_NEIGHBOR_ALGORITHM,
+            SimilarityMetric::Cosine,
+            0.95,
+        )),
+        Box::new(KMeansAlgorithm::new(20, 100)),
+        Box::new(DbscanAlgorithm::new(0.25, 3)),
+    ]
+}
+
+pub fn get_cluster_algorithm(name: &str) -> Option<Box<dyn ClusterAlgorithm>> {
+    get_all_cluster_algorithms()
+        .into_iter()
+        .find(|algorithm| algorithm.name() == name)
+}
+
+/// Each (pipeline, algorithm) pair gets its own clustering run
+pub fn clustering_run_name(pipeline_name: &str, algorithm: &dyn ClusterAlgorithm) -> String {
+    format!("{}-{}", pipeline_name, algorithm.name())
+}
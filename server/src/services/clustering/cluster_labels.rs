@@ -0,0 +1,256 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use anyhow::Error;
+use futures::TryStreamExt;
+use regex::Regex;
+use serde_json::json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        cluster::{
+            get_cluster_term_counts, get_clustering_runs_with_stale_labels, get_stale_clusters,
+            set_cluster_label, stream_cluster_member_snapshots_without_term_counts,
+        },
+        page_snapshot::insert_page_snapshot_term_counts,
+    },
+    services::utils::html_to_markdown,
+};
+
+const CLUSTER_LABEL_NUM_KEYWORDS: usize = 4;
+const MIN_TERM_LENGTH: usize = 3;
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+type TermCounts = HashMap<String, usize>;
+
+/// Relabels the clusters of every run whose membership changed since they were last labeled
+pub fn spawn_cluster_labeler(db: PgPool) {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = refresh_stale_cluster_labels(&db).await {
+                eprintln!("Failed to refresh cluster labels: {:?}", e);
+            }
+            tokio::time::sleep(REFRESH_INTERVAL).await;
+        }
+    });
+}
+
+async fn refresh_stale_cluster_labels(db: &PgPool) -> Result<(), Error> {
+    for clustering_run in get_clustering_runs_with_stale_labels(db).await? {
+        let relabeled = refresh_cluster_labels(db, &clustering_run).await?;
+        println!("Relabeled {} clusters in {}", relabeled, clustering_run);
+    }
+
+    Ok(())
+}
+
+/// Recomputes the labels of the run's stale clusters from all their member pages, returning how
+/// many were relabeled.
+///
+/// Labels are scored against every cluster of the run, so a membership change also shifts the
+/// scores of other clusters. Only stale clusters are relabeled though, so labels don't get a new
+/// version without their own members changing. Each snapshot's terms are only extracted once, the
+/// first time it's a member, so relabeling just sums stored counts.
+pub async fn refresh_cluster_labels(db: &PgPool, clustering_run: &str) -> Result<usize, Error> {
+    // Read before the members, so changes made while labeling leave the clusters stale
+    let stale_clusters = get_stale_clusters(db, clustering_run).await?;
+    if stale_clusters.is_empty() {
+        return Ok(0);
+    }
+
+    count_member_snapshot_terms(db, clustering_run).await?;
+
+    let mut term_counts_by_cluster: HashMap<Uuid, TermCounts> = HashMap::new();
+    for row in get_cluster_term_counts(db, clustering_run).await? {
+        term_counts_by_cluster
+            .entry(row.cluster_id)
+            .or_default()
+            .insert(row.term, row.count as usize);
+    }
+
+    let labels = c_tf_idf_labels(&term_counts_by_cluster, CLUSTER_LABEL_NUM_KEYWORDS);
+    for stale_cluster in &stale_clusters {
+        let label = labels
+            .get(&stale_cluster.id)
+            .map(|keywords| keywords.join(" "))
+            .unwrap_or_default();
+        set_cluster_label(
+            db,
+            stale_cluster.id,
+            &label,
+            stale_cluster.membership_version,
+        )
+        .await?;
+    }
+
+    Ok(stale_clusters.len())
+}
+
+/// Stores the term counts of the run's member snapshots that don't have them yet
+async fn count_member_snapshot_terms(db: &PgPool, clustering_run: &str) -> Result<(), Error> {
+    let term_extractor = TermExtractor::new()?;
+    let mut snapshots = stream_cluster_member_snapshots_without_term_counts(db, clustering_run);
+    while let Some(page_snapshot) = snapshots.try_next().await? {
+        let mut term_counts = TermCounts::new();
+        for term in term_extractor.terms(&html_to_markdown(&page_snapshot.contents)?) {
+            *term_counts.entry(term).or_default() += 1;
+        }
+        insert_page_snapshot_term_counts(db, page_snapshot.id, &json!(term_counts)).await?;
+    }
+
+    Ok(())
+}
+
+struct TermExtractor {
+    /// Link and image targets in the markdown, whose url parts would otherwise end up as terms
+    link_targets: Regex,
+    stop_words: HashSet<String>,
+}
+
+impl TermExtractor {
+    fn new() -> Result<Self, Error> {
+        Ok(TermExtractor {
+            link_targets: Regex::new(r"\]\([^)]*\)")?,
+            stop_words: stop_words::get(stop_words::LANGUAGE::English)
+                .into_iter()
+                .collect(),
+        })
+    }
+
+    fn terms(&self, markdown: &str) -> Vec<String> {
+        self.link_targets
+            .replace_all(markdown, "]")
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| word.chars().count() >= MIN_TERM_LENGTH)
+            .filter(|word| word.chars().any(char::is_alphabetic))
+            .map(str::to_lowercase)
+            .filter(|word| !self.stop_words.contains(word))
+            .collect()
+    }
+}
+
+/// The `num_keywords` best terms of each cluster by class-based TF-IDF, treating all of a
+/// cluster's pages as one document: `tf(t, c) * ln(1 + A / f(t))`, where `tf(t, c)` is the term's
+/// share of the cluster's terms, `A` the average number of terms per cluster and `f(t)` the term's
+/// count across all clusters.
+fn c_tf_idf_labels(
+    term_counts_by_cluster: &HashMap<Uuid, TermCounts>,
+    num_keywords: usize,
+) -> HashMap<Uuid, Vec<String>> {
+    let mut run_term_counts: TermCounts = HashMap::new();
+    for term_counts in term_counts_by_cluster.values() {
+        for (term, count) in term_counts {
+            *run_term_counts.entry(term.clone()).or_default() += count;
+        }
+    }
+
+    let total_terms: usize = run_term_counts.values().sum();
+    let average_terms = total_terms as f64 / term_counts_by_cluster.len().max(1) as f64;
+
+    term_counts_by_cluster
+        .iter()
+        .map(|(cluster_id, term_counts)| {
+            let cluster_terms: usize = term_counts.values().sum();
+            let mut scored_terms: Vec<(&String, f64)> = term_counts
+                .iter()
+                .map(|(term, count)| {
+                    let tf = *count as f64 / cluster_terms as f64;
+                    let idf = (1.0 + average_terms / run_term_counts[term] as f64).ln();
+                    (term, tf * idf)
+                })
+                .collect();
+            // Ties are broken by term so labels don't change between identical runs
+            scored_terms.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+            let keywords = scored_terms
+                .into_iter()
+                .take(num_keywords)
+                .map(|(term, _)| term.clone())
+                .collect();
+            (*cluster_id, keywords)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term_counts(terms: &[(&str, usize)]) -> TermCounts {
+        terms
+            .iter()
+            .map(|(term, count)| (term.to_string(), *count))
+            .collect()
+    }
+
+    #[test]
+    fn extracts_lowercase_terms_without_stop_words_or_link_targets() {
+        let term_extractor = TermExtractor::new().unwrap();
+
+        assert_eq!(
+            term_extractor.terms(
+                "# Sourdough\n\nThe [starter](https://example.com/wild-yeast) rises in 12 hours, \
+                 or 2x faster when it's warm"
+            ),
+            vec!["sourdough", "starter", "rises", "hours", "faster", "warm"]
+        );
+    }
+
+    #[test]
+    fn terms_shared_by_every_cluster_rank_below_distinctive_ones() {
+        let baking = Uuid::from_u128(1);
+        let programming = Uuid::from_u128(2);
+        let term_counts_by_cluster = HashMap::from([
+            (baking, term_counts(&[("recipe", 2), ("bread", 2)])),
+            (programming, term_counts(&[("recipe", 2), ("rust", 2)])),
+        ]);
+
+        let labels = c_tf_idf_labels(&term_counts_by_cluster, 2);
+
+        assert_eq!(labels[&baking], vec!["bread", "recipe"]);
+        assert_eq!(labels[&programming], vec!["rust", "recipe"]);
+    }
+
+    #[test]
+    fn frequent_terms_rank_above_rare_ones_within_a_cluster() {
+        let baking = Uuid::from_u128(1);
+        let programming = Uuid::from_u128(2);
+        let term_counts_by_cluster = HashMap::from([
+            (
+                baking,
+                term_counts(&[("bread", 5), ("starter", 3), ("oven", 1)]),
+            ),
+            (programming, term_counts(&[("rust", 4), ("compiler", 4)])),
+        ]);
+
+        let labels = c_tf_idf_labels(&term_counts_by_cluster, 2);
+
+        assert_eq!(labels[&baking], vec!["bread", "starter"]);
+    }
+
+    #[test]
+    fn ties_are_broken_by_term() {
+        let cluster = Uuid::from_u128(1);
+        let term_counts_by_cluster = HashMap::from([(
+            cluster,
+            term_counts(&[("zest", 1), ("flour", 1), ("salt", 1)]),
+        )]);
+
+        let labels = c_tf_idf_labels(&term_counts_by_cluster, 4);
+
+        assert_eq!(labels[&cluster], vec!["flour", "salt", "zest"]);
+    }
+
+    #[test]
+    fn clusters_without_terms_get_empty_labels() {
+        let empty = Uuid::from_u128(1);
+        let term_counts_by_cluster = HashMap::from([(empty, TermCounts::new())]);
+
+        assert!(c_tf_idf_labels(&term_counts_by_cluster, 4)[&empty].is_empty());
+        assert!(c_tf_idf_labels(&HashMap::new(), 4).is_empty());
+    }
+}
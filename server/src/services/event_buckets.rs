@@ -0,0 +1,433 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Error};
+use chrono::{
+    DateTime, Duration, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+
+use crate::services::sessionization::{local_midnight, recent_days_range};
+
+pub const DEFAULT_TIMEZONE: Tz = Tz::America__New_York;
+const MAX_RANGE_DAYS: i64 = 366;
+/// Every bucket is a row per cluster, so e.g. a year of minutes is too much
+const MAX_BUCKETS: i64 = 10_000;
+
+/// A validated time range, along with the timezone it's bucketed in.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeRange {
+    pub tz: Tz,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl TimeRange {
+    /// Resolves the optional `tz`, `start` and `end` query params. Bounds are RFC 3339 timestamps
+    /// or dates, which are taken as midnight in `tz`. Missing bounds default to yesterday and today.
+    pub fn from_params(
+        tz: Option<&str>,
+        start: Option<&str>,
+        end: Option<&str>,
+    ) -> Result<Self, Error> {
+        let tz = match tz {
+            Some(tz) => Tz::from_str(tz).map_err(|_| anyhow!("Unknown timezone {:?}", tz))?,
+            None => DEFAULT_TIMEZONE,
+        };
+
+        let (default_start, default_end) = recent_days_range(&tz)?;
+        let start = match start {
+            Some(start) => parse_time_bound(start, &tz)?,
+            None => default_start,
+        };
+        let end = match end {
+            Some(end) => parse_time_bound(end, &tz)?,
+            None => default_end,
+        };
+
+        if start >= end {
+            bail!("start ({}) must be before end ({})", start, end);
+        }
+        if end - start > Duration::days(MAX_RANGE_DAYS) {
+            bail!("Time ranges are limited to {} days", MAX_RANGE_DAYS);
+        }
+
+        Ok(TimeRange { tz, start, end })
+    }
+}
+
+fn parse_time_bound(value: &str, tz: &Tz) -> Result<DateTime<Utc>, Error> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+        anyhow!(
+            "Invalid time {:?}, expected an RFC 3339 timestamp or a YYYY-MM-DD date",
+            value
+        )
+    })?;
+    local_midnight(date, tz)
+}
+
+/// The width of event buckets. Buckets are aligned in local time, so hours start on the hour, days
+/// at midnight and weeks on Monday, even across DST changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BucketInterval(Duration);
+
+impl Default for BucketInterval {
+    fn default() -> Self {
+        BucketInterval(Duration::hours(1))
+    }
+}
+
+impl FromStr for BucketInterval {
+    type Err = Error;
+
+    /// Accepts `minute`, `hour`, `day` and `week`, or a whole number of `m`, `h`, `d` or `w`,
+    /// e.g. `15m`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let duration = match s {
+            "minute" => Duration::minutes(1),
+            "hour" => Duration::hours(1),
+            "day" => Duration::days(1),
+            "week" => Duration::weeks(1),
+            _ => parse_duration(s)?,
+        };
+
+        if duration < Duration::minutes(1) || duration > Duration::weeks(1) {
+            bail!("Interval {:?} must be between a minute and a week", s);
+        }
+
+        Ok(BucketInterval(duration))
+    }
+}
+
+fn parse_duration(s: &str) -> Result<Duration, Error> {
+    let unit_start = s
+        .find(|c: char| !c.is_ascii_digit())
+        .with_context(|| format!("Interval {:?} is missing a unit", s))?;
+    let (amount, unit) = s.split_at(unit_start);
+    let amount: i64 = amount
+        .parse()
+        .with_context(|| format!("Invalid interval {:?}", s))?;
+
+    let duration = match unit {
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        _ => bail!(
+            "Unknown interval unit {:?}, expected one of m, h, d or w",
+            unit
+        ),
+    };
+
+    duration.with_context(|| format!("Interval {:?} is too long", s))
+}
+
+impl BucketInterval {
+    /// Rejects intervals that would split `time_range` into more than `MAX_BUCKETS` buckets
+    pub fn check_bucket_count(&self, time_range: &TimeRange) -> Result<(), Error> {
+        let range_ms = (time_range.end - time_range.start).num_milliseconds();
+        let interval_ms = self.0.num_milliseconds();
+        let num_buckets = (range_ms + interval_ms - 1) / interval_ms;
+        if num_buckets > MAX_BUCKETS {
+            bail!(
+                "The range would have {} buckets, at most {} are allowed. Use a longer interval \
+                 or a shorter range.",
+                num_buckets,
+                MAX_BUCKETS
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The local start of the bucket containing `local`. Buckets are counted from a Monday
+    /// midnight, so every interval that divides a week lines up with the calendar.
+    fn bucket_start(&self, local: NaiveDateTime) -> NaiveDateTime {
+        let origin = DateTime::UNIX_EPOCH.naive_utc() + Duration::days(4);
+        let interval_ms = self.0.num_milliseconds();
+        let since_origin_ms = (local - origin).num_milliseconds();
+
+        origin + Duration::milliseconds(since_origin_ms.div_euclid(interval_ms) * interval_ms)
+    }
+
+    /// Splits `[start, end)` along local bucket boundaries, returning the seconds spent in each
+    /// piece keyed by the start of its bucket, with the offset it started at.
+    ///
+    /// Local times that repeat when clocks go back get their own buckets, with the later offset.
+    /// Buckets that contain the whole change, e.g. days, come out as several pieces with the same
+    /// key instead.
+    pub fn split(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        tz: &Tz,
+    ) -> Vec<(DateTime<FixedOffset>, f64)> {
+        let mut pieces = Vec::new();
+        let mut cursor = start;
+
+        while cursor < end {
+            let bucket_start = self.bucket_start(cursor.with_timezone(tz).naive_local());
+            let bucket_end = local_to_utc_after(bucket_start + self.0, tz, cursor);
+            // Otherwise a bucket whose local times repeat would run on until their second
+            // occurrence ends
+            let bucket_end = offset_change_between(tz, cursor, bucket_end).unwrap_or(bucket_end);
+
+            let piece_end = bucket_end.min(end);
+            let seconds = (piece_end - cursor).num_milliseconds() as f64 / 1000.0;
+            let key = local_to_utc_at_or_before(bucket_start, tz, cursor)
+                .with_timezone(tz)
+                .fixed_offset();
+
+            pieces.push((key, seconds));
+            cursor = piece_end;
+        }
+
+        pieces
+    }
+}
+
+/// Resolves a local bucket boundary to the first matching moment after `after`.
+fn local_to_utc_after(local: NaiveDateTime, tz: &Tz, after: DateTime<Utc>) -> DateTime<Utc> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(time) => time.with_timezone(&Utc),
+        // Clocks went back, so the boundary happens twice
+        LocalResult::Ambiguous(earliest, latest) => {
+            let earliest = earliest.with_timezone(&Utc);
+            if earliest > after {
+                earliest
+            } else {
+                latest.with_timezone(&Utc)
+            }
+        }
+        // Clocks jumped over the boundary, so the bucket ends at the jump
+        LocalResult::None => skipped_local_to_utc(local, tz),
+    }
+}
+
+/// Resolves a local bucket start to the last matching moment at or before `at`.
+fn local_to_utc_at_or_before(local: NaiveDateTime, tz: &Tz, at: DateTime<Utc>) -> DateTime<Utc> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(time) => time.with_timezone(&Utc),
+        LocalResult::Ambiguous(earliest, latest) => {
+            let latest = latest.with_timezone(&Utc);
+            if latest <= at {
+                latest
+            } else {
+                earliest.with_timezone(&Utc)
+            }
+        }
+        LocalResult::None => skipped_local_to_utc(local, tz),
+    }
+}
+
+/// Local times clocks jumped over resolve to the moment of the jump
+fn skipped_local_to_utc(local: NaiveDateTime, tz: &Tz) -> DateTime<Utc> {
+    let offset_before_jump = tz
+        .offset_from_utc_datetime(&(local - Duration::days(1)))
+        .fix();
+    let utc = local - Duration::seconds(offset_before_jump.local_minus_utc().into());
+    Utc.from_utc_datetime(&utc)
+}
+
+/// The moment in `(from, until)` at which `tz`'s UTC offset changes, if it does. Offsets change at
+/// most once within a week, the longest interval.
+fn offset_change_between(
+    tz: &Tz,
+    from: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let offset_at = |time: DateTime<Utc>| tz.offset_from_utc_datetime(&time.naive_utc()).fix();
+    let from_offset = offset_at(from);
+
+    // Binary search between the last known moment with the old offset and the first with the new
+    let mut before = from;
+    let mut after = until - Duration::milliseconds(1);
+    if after <= before || offset_at(after) == from_offset {
+        return None;
+    }
+    while after - before > Duration::milliseconds(1) {
+        let middle = before + (after - before) / 2;
+        if offset_at(middle) == from_offset {
+            before = middle;
+        } else {
+            after = middle;
+        }
+    }
+
+    Some(after)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    const NEW_YORK: Tz = Tz::America__New_York;
+
+    fn utc(timestamp: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(timestamp)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn split(interval: &str, start: &str, end: &str) -> Vec<(String, f64)> {
+        let bucket_interval: BucketInterval = interval.parse().unwrap();
+        bucket_interval
+            .split(utc(start), utc(end), &NEW_YORK)
+            .into_iter()
+            .map(|(key, seconds)| (key.to_rfc3339(), seconds))
+            .collect()
+    }
+
+    /// Seconds per bucket, merging pieces of the same bucket like the aggregations do
+    fn bucket_totals(interval: &str, start: &str, end: &str) -> Vec<(String, f64)> {
+        let bucket_interval: BucketInterval = interval.parse().unwrap();
+        let mut totals = BTreeMap::new();
+        for (key, seconds) in bucket_interval.split(utc(start), utc(end), &NEW_YORK) {
+            *totals.entry(key).or_insert(0.0) += seconds;
+        }
+
+        totals
+            .into_iter()
+            .map(|(key, seconds)| (key.to_rfc3339(), seconds))
+            .collect()
+    }
+
+    fn expected(buckets: &[(&str, f64)]) -> Vec<(String, f64)> {
+        buckets
+            .iter()
+            .map(|(key, seconds)| (key.to_string(), *seconds))
+            .collect()
+    }
+
+    // Clocks in New York went from 02:00 EST to 03:00 EDT at 2024-03-10T07:00Z, and from 02:00
+    // EDT back to 01:00 EST at 2024-11-03T06:00Z
+
+    #[test]
+    fn spring_forward_15m() {
+        assert_eq!(
+            split("15m", "2024-03-10T06:30:00Z", "2024-03-10T07:30:00Z"),
+            expected(&[
+                ("2024-03-10T01:30:00-05:00", 900.0),
+                ("2024-03-10T01:45:00-05:00", 900.0),
+                ("2024-03-10T03:00:00-04:00", 900.0),
+                ("2024-03-10T03:15:00-04:00", 900.0),
+            ])
+        );
+    }
+
+    #[test]
+    fn spring_forward_hour() {
+        assert_eq!(
+            split("hour", "2024-03-10T06:00:00Z", "2024-03-10T08:00:00Z"),
+            expected(&[
+                ("2024-03-10T01:00:00-05:00", 3600.0),
+                ("2024-03-10T03:00:00-04:00", 3600.0),
+            ])
+        );
+    }
+
+    #[test]
+    fn spring_forward_day() {
+        assert_eq!(
+            bucket_totals("day", "2024-03-10T05:00:00Z", "2024-03-11T04:00:00Z"),
+            expected(&[("2024-03-10T00:00:00-05:00", 23.0 * 3600.0)])
+        );
+    }
+
+    #[test]
+    fn fall_back_15m() {
+        assert_eq!(
+            split("15m", "2024-11-03T05:30:00Z", "2024-11-03T06:30:00Z"),
+            expected(&[
+                ("2024-11-03T01:30:00-04:00", 900.0),
+                ("2024-11-03T01:45:00-04:00", 900.0),
+                ("2024-11-03T01:00:00-05:00", 900.0),
+                ("2024-11-03T01:15:00-05:00", 900.0),
+            ])
+        );
+    }
+
+    #[test]
+    fn fall_back_hour() {
+        assert_eq!(
+            split("hour", "2024-11-03T04:30:00Z", "2024-11-03T07:30:00Z"),
+            expected(&[
+                ("2024-11-03T00:00:00-04:00", 1800.0),
+                ("2024-11-03T01:00:00-04:00", 3600.0),
+                ("2024-11-03T01:00:00-05:00", 3600.0),
+                ("2024-11-03T02:00:00-05:00", 1800.0),
+            ])
+        );
+    }
+
+    #[test]
+    fn fall_back_day() {
+        assert_eq!(
+            bucket_totals("day", "2024-11-03T04:00:00Z", "2024-11-04T05:00:00Z"),
+            expected(&[("2024-11-03T00:00:00-04:00", 25.0 * 3600.0)])
+        );
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        assert_eq!(
+            split("week", "2024-10-02T16:00:00Z", "2024-10-02T17:00:00Z"),
+            expected(&[("2024-09-30T00:00:00-04:00", 3600.0)])
+        );
+    }
+
+    #[test]
+    fn intervals_are_validated() {
+        assert_eq!(
+            "15m".parse::<BucketInterval>().unwrap(),
+            BucketInterval(Duration::minutes(15))
+        );
+        assert_eq!(
+            "day".parse::<BucketInterval>().unwrap(),
+            BucketInterval(Duration::days(1))
+        );
+        for invalid in ["", "15", "15s", "0m", "2w", "m", "-5m", "fortnight"] {
+            assert!(
+                invalid.parse::<BucketInterval>().is_err(),
+                "{:?} was accepted",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn bucket_counts_are_capped() {
+        let time_range = TimeRange::from_params(
+            Some("America/New_York"),
+            Some("2024-01-01"),
+            Some("2024-12-31"),
+        )
+        .unwrap();
+
+        assert!(BucketInterval(Duration::minutes(1))
+            .check_bucket_count(&time_range)
+            .is_err());
+        assert!(BucketInterval(Duration::hours(1))
+            .check_bucket_count(&time_range)
+            .is_ok());
+    }
+
+    #[test]
+    fn time_ranges_are_validated() {
+        let time_range =
+            TimeRange::from_params(Some("Europe/Paris"), Some("2024-07-01"), Some("2024-07-02"))
+                .unwrap();
+        assert_eq!(time_range.start, utc("2024-06-30T22:00:00Z"));
+        assert_eq!(time_range.end, utc("2024-07-01T22:00:00Z"));
+
+        assert!(TimeRange::from_params(Some("Mars/Olympus_Mons"), None, None).is_err());
+        assert!(TimeRange::from_params(None, Some("2024-07-02"), Some("2024-07-01")).is_err());
+        assert!(TimeRange::from_params(None, Some("2023-01-01"), Some("2024-07-01")).is_err());
+        assert!(TimeRange::from_params(None, Some("yesterday"), None).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Context, Error};
+use chrono::Utc;
+use pgvector::Vector;
+use sqlx::PgPool;
+
+use crate::{
+    app_state::AppState,
+    db::{
+        browse_event::get_latest_page_title,
+        cluster::check_page_assigned_in_run,
+        page::get_page_from_id,
+        page_processing_job::{
+            claim_next_page_processing_job, complete_page_processing_job,
+            dead_letter_page_processing_job, requeue_running_page_processing_jobs,
+            retry_page_processing_job,
+        },
+        page_snapshot::get_page_snapshot,
+        preprocessed_page_embedding::{
+            delete_page_embedding_chunks, get_preprocessed_page_embedding,
+            insert_page_embedding_chunk, upsert_preprocessed_page_embedding,
+        },
+    },
+    models::{page_processing_job::PageProcessingJobRow, PageRow, PageSnapshotRow},
+    services::{
+        clustering::{
+            cluster_algorithm::ClusterAlgorithm,
+            cluster_algorithms::{clustering_run_name, get_all_cluster_algorithms},
+        },
+        preprocessing::{
+            chunking::EmbeddingOutput, page_context::PageContext, pipeline::PreprocessingPipeline,
+        },
+    },
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_ATTEMPTS: i32 = 5;
+const BASE_RETRY_DELAY_SECONDS: i64 = 10;
+const MAX_RETRY_DELAY_SECONDS: i64 = 60 * 60;
+
+pub async fn spawn_workers(state: AppState, num_workers: usize) -> Result<(), Error> {
+    let requeued = requeue_running_page_processing_jobs(&state.db).await?;
+    if requeued > 0 {
+        println!("Requeued {} interrupted page processing jobs", requeued);
+    }
+
+    for worker_id in 0..num_workers {
+        tokio::spawn(run_worker(state.clone(), worker_id));
+    }
+
+    Ok(())
+}
+
+async fn run_worker(state: AppState, worker_id: usize) {
+    loop {
+        match claim_next_page_processing_job(&state.db).await {
+            Ok(Some(job)) => handle_job(&state, job).await,
+            Ok(None) => tokio::time::sleep(POLL_INTERVAL).await,
+            Err(e) => {
+                eprintln!("Worker {} failed to claim a job: {:?}", worker_id, e);
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+async fn handle_job(state: &AppState, job: PageProcessingJobRow) {
+    let result = process_page_snapshot(
+        &state.db,
+        &state.preprocessing_pipelines,
+        job.page_snapshot_id,
+    )
+    .await;
+
+    let update_result = match result {
+        Ok(()) => complete_page_processing_job(&state.db, job.id).await,
+        Err(e) if job.attempts >= MAX_ATTEMPTS => {
+            eprintln!("Giving up on page {}: {:?}", job.page_id, e);
+            dead_letter_page_processing_job(&state.db, job.id, &e.to_string()).await
+        }
+        Err(e) => {
+            eprintln!("Failed to process page {}, retrying: {:?}", job.page_id, e);
+            let run_after = Utc::now() + retry_delay(job.attempts);
+            retry_page_processing_job(&state.db, job.id, run_after, &e.to_string()).await
+        }
+    };
+
+    if let Err(e) = update_result {
+        eprintln!("Failed to update job {}: {:?}", job.id, e);
+    }
+}
+
+/// Exponential backoff, doubling with every attempt
+fn retry_delay(attempts: i32) -> chrono::Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 20) as u32;
+    let delay_seconds = BASE_RETRY_DELAY_SECONDS.saturating_mul(2_i64.pow(exponent));
+
+    chrono::Duration::seconds(delay_seconds.min(MAX_RETRY_DELAY_SECONDS))
+}
+
+/// Embeds a snapshot of a page with every pipeline and assigns the page with every online
+/// clustering algorithm.
+///
+/// Jobs can be retried after failing partway through, so embeddings that already exist are reused
+/// and clustering runs the page already belongs to are skipped. Pages stay where their first
+/// snapshot was assigned, later snapshots only get embedded; batch reclustering and backfill are
+/// what cluster pages by their latest snapshot.
+pub async fn process_page_snapshot(
+    db: &PgPool,
+    preprocessing_pipelines: &Arc<Vec<PreprocessingPipeline>>,
+    page_snapshot_id: i32,
+) -> Result<(), Error> {
+    let page_snapshot = get_page_snapshot(db, page_snapshot_id)
+        .await?
+        .with_context(|| format!("Page snapshot {} doesn't exist", page_snapshot_id))?;
+    let page_row = get_page_from_id(db, page_snapshot.page_id)
+        .await?
+        .with_context(|| format!("Page {} doesn't exist", page_snapshot.page_id))?;
+    let page_context = load_page_context(db, &page_row, &page_snapshot).await?;
+
+    let cluster_algorithms = get_all_cluster_algorithms();
+    for (pipeline_index, preprocessing_pipeline) in preprocessing_pipelines.iter().enumerate() {
+        let embedding = get_or_embed_page_snapshot(
+            db,
+            preprocessing_pipelines,
+            pipeline_index,
+            &page_context,
+            &page_snapshot,
+        )
+        .await?;
+
+        assign_page_to_online_runs(
+            db,
+            &page_row,
+            &embedding,
+            &preprocessing_pipeline.name,
+            &cluster_algorithms,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn load_page_context(
+    db: &PgPool,
+    page_row: &PageRow,
+    page_snapshot: &PageSnapshotRow,
+) -> Result<PageContext, Error> {
+    let page_title = get_latest_page_title(db, page_row.id).await?;
+
+    Ok(PageContext::new(
+        page_row.url.clone(),
+        page_title,
+        page_snapshot.contents.clone(),
+    ))
+}
+
+/// Reuses the snapshot's stored embedding for the pipeline, or runs the pipeline and stores it
+pub async fn get_or_embed_page_snapshot(
+    db: &PgPool,
+    preprocessing_pipelines: &Arc<Vec<PreprocessingPipeline>>,
+    pipeline_index: usize,
+    page_context: &PageContext,
+    page_snapshot: &PageSnapshotRow,
+) -> Result<Vector, Error> {
+    let embedding_run = &preprocessing_pipelines[pipeline_index].name;
+    if let Some(embedding_row) =
+        get_preprocessed_page_embedding(db, page_snapshot.id, embedding_run).await?
+    {
+        return Ok(embedding_row.embedding);
+    }
+
+    let embedding_output = run_pipeline(
+        Arc::clone(preprocessing_pipelines),
+        pipeline_index,
+        page_context.clone(),
+    )
+    .await?;
+    store_embedding(db, page_snapshot, embedding_run, &embedding_output).await?;
+
+    Ok(embedding_output.embedding)
+}
+
+/// Skips the clustering runs the page already belongs to
+pub async fn assign_page_to_online_runs(
+    db: &PgPool,
+    page_row: &PageRow,
+    embedding: &Vector,
+    embedding_run: &str,
+    cluster_algorithms: &[Box<dyn ClusterAlgorithm>],
+) -> Result<(), Error> {
+    for cluster_algorithm in cluster_algorithms {
+        if !cluster_algorithm.is_online() {
+            continue;
+        }
+
+        let clustering_run = clustering_run_name(embedding_run, cluster_algorithm.as_ref());
+        if check_page_assigned_in_run(db, page_row.id, &clustering_run).await? {
+            continue;
+        }
+
+        cluster_algorithm
+            .assign_page(db, page_row, embedding, &clustering_run)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Stores the snapshot's embedding along with any chunk embeddings, all or nothing
+async fn store_embedding(
+    db: &PgPool,
+    page_snapshot: &PageSnapshotRow,
+    embedding_run: &str,
+    embedding_output: &EmbeddingOutput,
+) -> Result<(), Error> {
+    let mut tx = db.begin().await?;
+    upsert_preprocessed_page_embedding(
+        &mut *tx,
+        page_snapshot.page_id,
+        page_snapshot.id,
+        embedding_run,
+        &embedding_output.embedding,
+    )
+    .await?;
+    delete_page_embedding_chunks(&mut *tx, page_snapshot.id, embedding_run).await?;
+    for (chunk_index, chunk) in embedding_output.chunks.iter().enumerate() {
+        insert_page_embedding_chunk(
+            &mut *tx,
+            page_snapshot.page_id,
+            page_snapshot.id,
+            embedding_run,
+            i32::try_from(chunk_index)?,
+            &chunk.contents,
+            &chunk.embedding,
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Embedding is CPU-bound, so keep it off the async runtime's worker threads
+async fn run_pipeline(
+    preprocessing_pipelines: Arc<Vec<PreprocessingPipeline>>,
+    pipeline_index: usize,
+    page_context: PageContext,
+) -> Result<EmbeddingOutput, Error> {
+    tokio::task::spawn_blocking(move || preprocessing_pipelines[pipeline_index].run(&page_context))
+        .await?
+}
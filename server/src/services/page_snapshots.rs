@@ -0,0 +1,84 @@
+use anyhow::Error;
+use chrono::{DateTime, Duration, Utc};
+
+use crate::services::{preprocessing::main_content::MainContentExtractor, utils::html_to_markdown};
+
+/// A page whose text changes on every visit, like a feed, gets at most one new snapshot this often
+const MIN_SNAPSHOT_INTERVAL_MINUTES: i64 = 30;
+
+/// Reduces a page's HTML to the text its snapshots are keyed by. Captured HTML differs on nearly
+/// every visit (ads, nonces, tracking attributes, counters in the navigation), so only the main
+/// content's text, with whitespace collapsed, decides whether the page changed.
+pub struct SnapshotTextExtractor {
+    main_content: MainContentExtractor,
+}
+
+impl SnapshotTextExtractor {
+    pub fn new() -> Result<Self, Error> {
+        Ok(SnapshotTextExtractor {
+            main_content: MainContentExtractor::new()?,
+        })
+    }
+
+    pub fn snapshot_text(&self, page_html: &str) -> Result<String, Error> {
+        let markdown = html_to_markdown(&self.main_content.extract(page_html))?;
+        Ok(markdown.split_whitespace().collect::<Vec<_>>().join(" "))
+    }
+}
+
+/// Whether a page whose text changed gets a new snapshot now, or stays on its current one for now
+pub fn is_due_for_snapshot(current_snapshot_created_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+    now - current_snapshot_created_at >= Duration::minutes(MIN_SNAPSHOT_INTERVAL_MINUTES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARTICLE_WITH_BOILERPLATE: &str =
+        include_str!("../../tests/fixtures/article_with_boilerplate.html");
+
+    fn snapshot_text(page_html: &str) -> String {
+        SnapshotTextExtractor::new()
+            .unwrap()
+            .snapshot_text(page_html)
+            .unwrap()
+    }
+
+    #[test]
+    fn markup_and_boilerplate_changes_keep_the_same_text() {
+        let revisited = ARTICLE_WITH_BOILERPLATE
+            .replace("About us", "About us (3 new)")
+            .replace("We use cookies", "We still use cookies")
+            .replace(
+                "<article class=\"post\">",
+                "<article class=\"post\" data-nonce=\"8f2a\">",
+            )
+            .replace("\n          ", "\n    ");
+
+        assert_eq!(
+            snapshot_text(ARTICLE_WITH_BOILERPLATE),
+            snapshot_text(&revisited)
+        );
+    }
+
+    #[test]
+    fn changes_to_the_article_change_the_text() {
+        let edited = ARTICLE_WITH_BOILERPLATE.replace("once a day", "twice a day");
+
+        assert_ne!(
+            snapshot_text(ARTICLE_WITH_BOILERPLATE),
+            snapshot_text(&edited)
+        );
+    }
+
+    #[test]
+    fn snapshots_are_rate_limited_per_page() {
+        let now = Utc::now();
+
+        assert!(!is_due_for_snapshot(now - Duration::minutes(5), now));
+        assert!(!is_due_for_snapshot(now - Duration::minutes(29), now));
+        assert!(is_due_for_snapshot(now - Duration::minutes(30), now));
+        assert!(is_due_for_snapshot(now - Duration::days(2), now));
+    }
+}
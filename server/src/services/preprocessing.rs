@@ -1,3 +1,9 @@
+pub mod chunking;
+pub mod main_content;
+pub mod markuplm;
+pub mod page_context;
 pub mod pipeline;
+pub mod pipeline_config;
 pub mod pipeline_step;
 pub mod pipelines;
+pub mod step_registry;
@@ -0,0 +1,250 @@
+use anyhow::{bail, Error};
+use pgvector::Vector;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokenizers::Tokenizer;
+
+/// How chunk vectors are combined into the page's embedding
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkPooling {
+    Mean,
+    Max,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkingConfig {
+    /// Tokens per chunk, not counting the special tokens the model adds
+    pub max_tokens: usize,
+    /// Tokens shared between consecutive chunks, so passages cut at a boundary still appear whole
+    pub overlap_tokens: usize,
+    pub pooling: ChunkPooling,
+    /// Keep every chunk's embedding, so search can point at the matching passage
+    pub store_chunks: bool,
+}
+
+impl ChunkingConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.max_tokens == 0 {
+            bail!("Chunks need at least one token");
+        }
+        if self.overlap_tokens >= self.max_tokens {
+            bail!(
+                "Chunk overlap ({}) must be smaller than the chunk size ({})",
+                self.overlap_tokens,
+                self.max_tokens
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The settings that change the pooled embedding. Storing chunks doesn't, so it's left out.
+    pub fn parameters(&self) -> serde_json::Value {
+        json!({
+            "max_tokens": self.max_tokens,
+            "overlap_tokens": self.overlap_tokens,
+            "pooling": self.pooling,
+        })
+    }
+}
+
+pub struct EmbeddedChunk {
+    pub contents: String,
+    pub embedding: Vector,
+}
+
+/// A page's embedding, along with the chunks it was pooled from when they're kept
+pub struct EmbeddingOutput {
+    pub embedding: Vector,
+    pub chunks: Vec<EmbeddedChunk>,
+}
+
+/// Splits `text` into overlapping windows of at most `max_tokens` tokens, returned as text slices
+/// so they can be embedded as usual. Text that fits in one window, empty text included, is a
+/// single chunk.
+pub fn chunk_text(
+    tokenizer: &Tokenizer,
+    text: &str,
+    config: &ChunkingConfig,
+) -> Result<Vec<String>, Error> {
+    config.validate()?;
+
+    let encoding = tokenizer
+        .encode_char_offsets(text, false)
+        .map_err(Error::msg)?;
+    let offsets = encoding.get_offsets();
+    if offsets.len() <= config.max_tokens {
+        return Ok(vec![text.to_string()]);
+    }
+
+    let chars: Vec<char> = text.chars().collect();
+    let stride = config.max_tokens - config.overlap_tokens;
+    let mut chunks = Vec::new();
+    let mut first_token = 0;
+
+    loop {
+        let last_token = (first_token + config.max_tokens).min(offsets.len()) - 1;
+        let (chunk_start, _) = offsets[first_token];
+        let (_, chunk_end) = offsets[last_token];
+        chunks.push(
+            chars[chunk_start..chunk_end.min(chars.len())]
+                .iter()
+                .collect(),
+        );
+
+        if last_token + 1 >= offsets.len() {
+            break;
+        }
+        first_token += stride;
+    }
+
+    Ok(chunks)
+}
+
+/// Pools chunk embeddings into one unit vector, so pages compare the same however many chunks
+/// they have
+pub fn pool(chunk_embeddings: &[Vec<f32>], pooling: ChunkPooling) -> Result<Vec<f32>, Error> {
+    let Some(first) = chunk_embeddings.first() else {
+        bail!("No chunk embeddings to pool");
+    };
+
+    let mut pooled = first.clone();
+    for chunk_embedding in &chunk_embeddings[1..] {
+        for (pooled_value, value) in pooled.iter_mut().zip(chunk_embedding) {
+            match pooling {
+                ChunkPooling::Mean => *pooled_value += value,
+                ChunkPooling::Max => *pooled_value = pooled_value.max(*value),
+            }
+        }
+    }
+
+    // The mean points the same way as the sum, so normalizing covers averaging too
+    let norm = pooled.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        for pooled_value in &mut pooled {
+            *pooled_value /= norm;
+        }
+    }
+
+    Ok(pooled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokenizers::{models::wordlevel::WordLevel, pre_tokenizers::whitespace::Whitespace};
+
+    /// Every whitespace separated word is one token
+    fn word_tokenizer() -> Tokenizer {
+        let model = WordLevel::builder()
+            .vocab(HashMap::from([("[UNK]".to_string(), 0)]))
+            .unk_token("[UNK]".to_string())
+            .build()
+            .unwrap();
+        let mut tokenizer = Tokenizer::new(model);
+        tokenizer.with_pre_tokenizer(Whitespace {});
+        tokenizer
+    }
+
+    fn chunking(max_tokens: usize, overlap_tokens: usize) -> ChunkingConfig {
+        ChunkingConfig {
+            max_tokens,
+            overlap_tokens,
+            pooling: ChunkPooling::Mean,
+            store_chunks: false,
+        }
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-6, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn chunks_overlap_by_the_configured_number_of_tokens() {
+        let chunks = chunk_text(&word_tokenizer(), "a b c d e f g", &chunking(3, 1)).unwrap();
+
+        assert_eq!(chunks, vec!["a b c", "c d e", "e f g"]);
+    }
+
+    #[test]
+    fn the_last_chunk_can_be_shorter() {
+        let chunks = chunk_text(&word_tokenizer(), "a b c d e f", &chunking(4, 1)).unwrap();
+
+        assert_eq!(chunks, vec!["a b c d", "d e f"]);
+    }
+
+    #[test]
+    fn short_and_empty_text_is_one_chunk() {
+        let tokenizer = word_tokenizer();
+
+        assert_eq!(
+            chunk_text(&tokenizer, "a b", &chunking(3, 1)).unwrap(),
+            vec!["a b"]
+        );
+        assert_eq!(
+            chunk_text(&tokenizer, "", &chunking(3, 1)).unwrap(),
+            vec![""]
+        );
+    }
+
+    #[test]
+    fn overlap_at_least_the_chunk_size_is_rejected() {
+        let tokenizer = word_tokenizer();
+
+        assert!(chunk_text(&tokenizer, "a b c d e f g", &chunking(3, 3)).is_err());
+        assert!(chunk_text(&tokenizer, "a b c d e f g", &chunking(3, 4)).is_err());
+        assert!(chunk_text(&tokenizer, "a b c d e f g", &chunking(0, 0)).is_err());
+    }
+
+    #[test]
+    fn chunks_split_multi_byte_text_on_character_boundaries() {
+        let chunks = chunk_text(
+            &word_tokenizer(),
+            "crème brûlée 東京 タワー 🍰 café",
+            &chunking(2, 0),
+        )
+        .unwrap();
+
+        assert_eq!(chunks, vec!["crème brûlée", "東京 タワー", "🍰 café"]);
+    }
+
+    #[test]
+    fn mean_pooling_averages_and_normalizes() {
+        let pooled = pool(&[vec![1.0, 0.0], vec![0.0, 1.0]], ChunkPooling::Mean).unwrap();
+
+        let component = 1.0 / 2.0_f32.sqrt();
+        assert_close(&pooled, &[component, component]);
+    }
+
+    #[test]
+    fn max_pooling_takes_the_largest_values_and_normalizes() {
+        let chunk_embeddings = [vec![1.0, -1.0, 0.0], vec![-1.0, 0.0, 1.0]];
+
+        let component = 1.0 / 2.0_f32.sqrt();
+        assert_close(
+            &pool(&chunk_embeddings, ChunkPooling::Max).unwrap(),
+            &[component, 0.0, component],
+        );
+        assert_close(
+            &pool(&chunk_embeddings, ChunkPooling::Mean).unwrap(),
+            &[0.0, -component, component],
+        );
+    }
+
+    #[test]
+    fn pooling_one_chunk_keeps_its_direction() {
+        let pooled = pool(&[vec![3.0, 4.0]], ChunkPooling::Mean).unwrap();
+
+        assert_close(&pooled, &[0.6, 0.8]);
+    }
+
+    #[test]
+    fn pooling_nothing_fails() {
+        assert!(pool(&[], ChunkPooling::Mean).is_err());
+    }
+}
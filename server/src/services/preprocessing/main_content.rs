@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Error};
+use regex::Regex;
+use scraper::{ElementRef, Html, Node, Selector};
+
+const BOILERPLATE_TAGS: &[&str] = &[
+    "nav", "aside", "footer", "header", "form", "script", "style", "noscript", "iframe", "svg",
+    "button",
+];
+const BOILERPLATE_ROLES: &[&str] = &[
+    "navigation",
+    "banner",
+    "contentinfo",
+    "complementary",
+    "search",
+    "dialog",
+];
+const VOID_TAGS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+const MIN_PARAGRAPH_LENGTH: usize = 25;
+const CLASS_WEIGHT: f32 = 25.0;
+
+/// Readability-style main content extraction.
+///
+/// Boilerplate (nav, aside, footer, header, form and elements whose role, class or id looks like
+/// navigation, banners or sidebars) is dropped. Every remaining paragraph scores its parent, and
+/// half as much its grandparent, by length and number of commas. The highest scoring element,
+/// discounted by how much of its text is links, is taken to be the main content.
+pub struct MainContentExtractor {
+    paragraph_selector: Selector,
+    link_selector: Selector,
+    body_selector: Selector,
+    negative_hints: Regex,
+    positive_hints: Regex,
+}
+
+impl MainContentExtractor {
+    pub fn new() -> Result<Self, Error> {
+        Ok(MainContentExtractor {
+            paragraph_selector: Selector::parse("p, pre, td, blockquote")
+                .map_err(|e| anyhow!("Invalid selector: {}", e))?,
+            link_selector: Selector::parse("a").map_err(|e| anyhow!("Invalid selector: {}", e))?,
+            body_selector: Selector::parse("body")
+                .map_err(|e| anyhow!("Invalid selector: {}", e))?,
+            negative_hints: Regex::new(
+                r"(?i)comment|cookie|consent|banner|footer|sidebar|side-bar|nav|menu|breadcrumb|share|social|promo|related|advert|sponsor|popup|modal|newsletter|subscribe",
+            )?,
+            positive_hints: Regex::new(
+                r"(?i)article|content|main|post|entry|story|text|body|blog",
+            )?,
+        })
+    }
+
+    /// Returns the main content as HTML, with boilerplate removed
+    pub fn extract(&self, page_html: &str) -> String {
+        let document = Html::parse_document(page_html);
+
+        let mut scores = HashMap::new();
+        for paragraph in document.select(&self.paragraph_selector) {
+            if self.in_boilerplate(paragraph) {
+                continue;
+            }
+
+            let text = self.visible_text(paragraph);
+            let text_length = text.trim().chars().count();
+            if text_length < MIN_PARAGRAPH_LENGTH {
+                continue;
+            }
+
+            let score =
+                1.0 + text.matches(',').count() as f32 + (text_length as f32 / 100.0).min(3.0);
+            let ancestors = paragraph.ancestors().filter_map(ElementRef::wrap).take(2);
+            for (level, ancestor) in ancestors.enumerate() {
+                let ancestor_score = scores
+                    .entry(ancestor.id())
+                    .or_insert_with(|| self.class_weight(ancestor));
+                *ancestor_score += if level == 0 { score } else { score / 2.0 };
+            }
+        }
+
+        let main_content = scores
+            .into_iter()
+            .filter_map(|(id, score)| {
+                let candidate = document.tree.get(id).and_then(ElementRef::wrap)?;
+                Some((candidate, score * (1.0 - self.link_density(candidate))))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(candidate, _)| candidate)
+            .or_else(|| document.select(&self.body_selector).next())
+            .unwrap_or_else(|| document.root_element());
+
+        let mut main_content_html = String::new();
+        self.write_clean_html(main_content, &mut main_content_html);
+        main_content_html
+    }
+
+    fn is_boilerplate(&self, element: ElementRef) -> bool {
+        let name = element.value().name();
+        if BOILERPLATE_TAGS.contains(&name) {
+            return true;
+        }
+
+        if element
+            .value()
+            .attr("role")
+            .is_some_and(|role| BOILERPLATE_ROLES.contains(&role))
+        {
+            return true;
+        }
+
+        if element.value().attr("aria-hidden") == Some("true") {
+            return true;
+        }
+
+        // Class names on the page's outer elements describe the whole page, not a section of it
+        if name == "html" || name == "body" || name == "main" || name == "article" {
+            return false;
+        }
+
+        let hints = self.hints(element);
+        self.negative_hints.is_match(&hints) && !self.positive_hints.is_match(&hints)
+    }
+
+    fn in_boilerplate(&self, element: ElementRef) -> bool {
+        self.is_boilerplate(element)
+            || element
+                .ancestors()
+                .filter_map(ElementRef::wrap)
+                .any(|ancestor| self.is_boilerplate(ancestor))
+    }
+
+    fn hints(&self, element: ElementRef) -> String {
+        format!(
+            "{} {}",
+            element.value().attr("class").unwrap_or_default(),
+            element.value().id().unwrap_or_default()
+        )
+    }
+
+    fn class_weight(&self, element: ElementRef) -> f32 {
+        let hints = self.hints(element);
+        let mut weight = 0.0;
+        if self.negative_hints.is_match(&hints) {
+            weight -= CLASS_WEIGHT;
+        }
+        if self.positive_hints.is_match(&hints) {
+            weight += CLASS_WEIGHT;
+        }
+
+        weight
+    }
+
+    fn link_density(&self, element: ElementRef) -> f32 {
+        let text_length = self.visible_text(element).chars().count();
+        if text_length == 0 {
+            return 0.0;
+        }
+
+        let link_text_length: usize = element
+            .select(&self.link_selector)
+            .map(|link| self.visible_text(link).chars().count())
+            .sum();
+
+        link_text_length as f32 / text_length as f32
+    }
+
+    fn visible_text(&self, element: ElementRef) -> String {
+        let mut text = String::new();
+        self.push_visible_text(element, &mut text);
+        text
+    }
+
+    fn push_visible_text(&self, element: ElementRef, text: &mut String) {
+        for child in element.children() {
+            match child.value() {
+                Node::Text(child_text) => text.push_str(child_text),
+                Node::Element(_) => {
+                    if let Some(child) = ElementRef::wrap(child) {
+                        if !self.is_boilerplate(child) {
+                            self.push_visible_text(child, text);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Serializes the children of `element`, skipping boilerplate
+    fn write_clean_html(&self, element: ElementRef, html: &mut String) {
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => html.push_str(&escape_html(text, false)),
+                Node::Element(child_element) => {
+                    let Some(child) = ElementRef::wrap(child) else {
+                        continue;
+                    };
+                    if self.is_boilerplate(child) {
+                        continue;
+                    }
+
+                    let name = child_element.name();
+                    html.push('<');
+                    html.push_str(name);
+                    for (attribute, value) in child_element.attrs() {
+                        html.push_str(&format!(" {}=\"{}\"", attribute, escape_html(value, true)));
+                    }
+                    html.push('>');
+
+                    if VOID_TAGS.contains(&name) {
+                        continue;
+                    }
+
+                    self.write_clean_html(child, html);
+                    html.push_str(&format!("</{}>", name));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn escape_html(text: &str, in_attribute: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if in_attribute => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARTICLE_WITH_BOILERPLATE: &str =
+        include_str!("../../../tests/fixtures/article_with_boilerplate.html");
+    const ARTICLE_IN_DIVS: &str = include_str!("../../../tests/fixtures/article_in_divs.html");
+
+    fn assert_kept(main_content: &str, phrases: &[&str]) {
+        for phrase in phrases {
+            assert!(
+                main_content.contains(phrase),
+                "{:?} was dropped from {}",
+                phrase,
+                main_content
+            );
+        }
+    }
+
+    fn assert_dropped(main_content: &str, phrases: &[&str]) {
+        for phrase in phrases {
+            assert!(
+                !main_content.contains(phrase),
+                "{:?} was kept in {}",
+                phrase,
+                main_content
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_the_article_and_drops_semantic_boilerplate() {
+        let main_content = MainContentExtractor::new()
+            .unwrap()
+            .extract(ARTICLE_WITH_BOILERPLATE);
+
+        assert_kept(
+            &main_content,
+            &[
+                "Sourdough starters, explained",
+                "living culture of wild yeast",
+                "discarding part of it",
+                "it is ready to leaven a loaf of bread",
+            ],
+        );
+        assert_dropped(
+            &main_content,
+            &[
+                // header
+                "Recipes, techniques and stories",
+                // nav
+                "About us",
+                // share links inside the article
+                "Share this article",
+                // newsletter form inside the article
+                "Get a new recipe in your inbox",
+                "Subscribe",
+                // aside
+                "Related reading",
+                // footer
+                "all rights reserved",
+                // cookie banner
+                "We use cookies",
+                "Accept all",
+                // head
+                "window.analytics",
+                "font-family",
+            ],
+        );
+    }
+
+    #[test]
+    fn keeps_the_article_and_drops_boilerplate_marked_by_class_and_id() {
+        let main_content = MainContentExtractor::new()
+            .unwrap()
+            .extract(ARTICLE_IN_DIVS);
+
+        assert_kept(
+            &main_content,
+            &[
+                "Choosing a mechanical keyboard switch",
+                "Linear switches travel straight down",
+                "Tactile switches have a small bump",
+                "Clicky switches add an audible click",
+            ],
+        );
+        assert_dropped(
+            &main_content,
+            &[
+                // menu
+                "Reviews",
+                // cookie consent popup
+                "stores cookies on your device",
+                // sidebar
+                "Popular this week",
+                "Follow us",
+                // footer and its search form
+                "advertise with us",
+                "type=\"search\"",
+            ],
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_body_without_paragraphs() {
+        let main_content = MainContentExtractor::new().unwrap().extract(
+            "<html><body><nav>Home</nav><div>Short text</div><footer>Footer</footer></body></html>",
+        );
+
+        assert_eq!(main_content, "<div>Short text</div>");
+    }
+}
@@ -0,0 +1,602 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{bail, Context, Error};
+use ndarray::{Array2, Array3, Axis, Ix3};
+use ort::{GraphOptimizationLevel, Session, Value};
+use scraper::{ElementRef, Html, Node};
+use serde::Deserialize;
+use tokenizers::Tokenizer;
+
+use crate::services::preprocessing::chunking::{
+    pool, ChunkingConfig, EmbeddedChunk, EmbeddingOutput,
+};
+
+const MODEL_FILE: &str = "model.onnx";
+const TOKENIZER_FILE: &str = "tokenizer.json";
+const TOKENIZER_CONFIG_FILE: &str = "tokenizer_config.json";
+const MODEL_CONFIG_FILE: &str = "config.json";
+
+const CLS_TOKEN: &str = "<s>";
+const SEP_TOKEN: &str = "</s>";
+const PAD_TOKEN: &str = "<pad>";
+
+// RoBERTa-style position ids start after the padding index, so two positions are never used
+const POSITION_OFFSET: usize = 2;
+
+const SKIPPED_TAGS: &[&str] = &["script", "style", "noscript", "template"];
+
+/// The parts of `tokenizer_config.json` describing how xpaths are encoded
+#[derive(Deserialize)]
+struct XPathConfig {
+    tags_dict: HashMap<String, i64>,
+    max_depth: usize,
+    max_width: i64,
+    pad_width: i64,
+}
+
+#[derive(Deserialize)]
+struct ModelConfig {
+    hidden_size: usize,
+    max_position_embeddings: usize,
+    #[serde(rename = "_name_or_path")]
+    name_or_path: Option<String>,
+}
+
+/// A token along with the xpath of the text node it came from, as tag ids and sibling subscripts
+struct XPathToken {
+    id: u32,
+    xpath_tags: Vec<i64>,
+    xpath_subs: Vec<i64>,
+}
+
+/// MarkupLM run locally through ONNX Runtime. Every token is embedded along with the xpath of the
+/// text node it belongs to, so the embedding reflects where text sits in the page, not only what
+/// it says.
+///
+/// The model directory needs `model.onnx` (an export of the base model whose first output is the
+/// last hidden state), along with the `tokenizer.json`, `tokenizer_config.json` and `config.json`
+/// saved with it. Nothing is downloaded.
+pub struct MarkupLMModel {
+    session: Session,
+    output_name: String,
+    need_token_type_ids: bool,
+    tokenizer: Tokenizer,
+    xpath_config: XPathConfig,
+    cls_token_id: u32,
+    sep_token_id: u32,
+    pad_token_id: u32,
+    model_id: String,
+    dimension: usize,
+    max_tokens: usize,
+}
+
+impl MarkupLMModel {
+    pub fn load(model_dir: &Path) -> Result<Self, Error> {
+        let model_path = model_dir.join(MODEL_FILE);
+        if !model_path.is_file() {
+            bail!(
+                "No MarkupLM model at {}, export one to ONNX there first",
+                model_path.display()
+            );
+        }
+
+        let session = Session::builder()?
+            .with_optimization_level(GraphOptimizationLevel::Level3)?
+            .commit_from_file(&model_path)?;
+        let output_name = session
+            .outputs
+            .first()
+            .context("MarkupLM model has no outputs")?
+            .name
+            .clone();
+        let need_token_type_ids = session
+            .inputs
+            .iter()
+            .any(|input| input.name == "token_type_ids");
+
+        let tokenizer = Tokenizer::from_file(model_dir.join(TOKENIZER_FILE)).map_err(Error::msg)?;
+        let token_id = |token: &str| {
+            tokenizer
+                .token_to_id(token)
+                .with_context(|| format!("MarkupLM tokenizer has no {} token", token))
+        };
+        let cls_token_id = token_id(CLS_TOKEN)?;
+        let sep_token_id = token_id(SEP_TOKEN)?;
+        let pad_token_id = token_id(PAD_TOKEN)?;
+
+        let xpath_config: XPathConfig = read_json(&model_dir.join(TOKENIZER_CONFIG_FILE))?;
+        let model_config: ModelConfig = read_json(&model_dir.join(MODEL_CONFIG_FILE))?;
+
+        Ok(MarkupLMModel {
+            session,
+            output_name,
+            need_token_type_ids,
+            tokenizer,
+            xpath_config,
+            cls_token_id,
+            sep_token_id,
+            pad_token_id,
+            model_id: model_config
+                .name_or_path
+                .unwrap_or_else(|| model_dir.display().to_string()),
+            dimension: model_config.hidden_size,
+            // Leave room for the positions RoBERTa skips and for [CLS] and [SEP]
+            max_tokens: model_config
+                .max_position_embeddings
+                .saturating_sub(POSITION_OFFSET + 2),
+        })
+    }
+
+    pub fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    /// Largest window, in tokens, the model can embed at once
+    pub fn max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+
+    /// Embeds the page's HTML in overlapping windows of xpath tokens, pooled like text chunks
+    pub fn embed_html(
+        &self,
+        page_html: &str,
+        chunking: &ChunkingConfig,
+    ) -> Result<EmbeddingOutput, Error> {
+        chunking.validate()?;
+        if chunking.max_tokens > self.max_tokens {
+            bail!(
+                "MarkupLM windows can have at most {} tokens, not {}",
+                self.max_tokens,
+                chunking.max_tokens
+            );
+        }
+
+        let tokens = self.xpath_tokens(page_html)?;
+
+        let stride = chunking.max_tokens - chunking.overlap_tokens;
+        let mut windows = Vec::new();
+        let mut first_token = 0;
+        loop {
+            let last_token = (first_token + chunking.max_tokens).min(tokens.len());
+            windows.push(&tokens[first_token..last_token]);
+            if last_token >= tokens.len() {
+                break;
+            }
+            first_token += stride;
+        }
+
+        let window_embeddings = self.embed_windows(&windows)?;
+        let embedding = pool(&window_embeddings, chunking.pooling)?.into();
+
+        let chunks = if chunking.store_chunks {
+            windows
+                .iter()
+                .zip(window_embeddings)
+                .map(|(window, window_embedding)| {
+                    let ids: Vec<u32> = window.iter().map(|token| token.id).collect();
+                    Ok(EmbeddedChunk {
+                        contents: self.tokenizer.decode(&ids, true).map_err(Error::msg)?,
+                        embedding: window_embedding.into(),
+                    })
+                })
+                .collect::<Result<_, Error>>()?
+        } else {
+            vec![]
+        };
+
+        Ok(EmbeddingOutput { embedding, chunks })
+    }
+
+    /// Tokenizes every visible text node, in document order, tagging its tokens with its xpath
+    fn xpath_tokens(&self, page_html: &str) -> Result<Vec<XPathToken>, Error> {
+        let document = Html::parse_document(page_html);
+        let mut xpath_units = HashMap::new();
+        let mut tokens = Vec::new();
+
+        for node in document.tree.root().descendants() {
+            let Node::Text(text) = node.value() else {
+                continue;
+            };
+            let text = text.trim();
+            if text.is_empty() {
+                continue;
+            }
+
+            let ancestors: Vec<ElementRef> =
+                node.ancestors().filter_map(ElementRef::wrap).collect();
+            if ancestors
+                .iter()
+                .any(|ancestor| SKIPPED_TAGS.contains(&ancestor.value().name()))
+            {
+                continue;
+            }
+
+            // Keep the innermost elements when the xpath is deeper than the model supports
+            let depth = ancestors.len().min(self.xpath_config.max_depth);
+            let (mut xpath_tags, mut xpath_subs): (Vec<i64>, Vec<i64>) = ancestors[..depth]
+                .iter()
+                .rev()
+                .map(|ancestor| {
+                    *xpath_units
+                        .entry(ancestor.id())
+                        .or_insert_with(|| self.xpath_unit(*ancestor))
+                })
+                .unzip();
+            xpath_tags.resize(self.xpath_config.max_depth, self.pad_tag_id());
+            xpath_subs.resize(self.xpath_config.max_depth, self.xpath_config.pad_width);
+
+            // Each node is a separate word, so it's encoded with the leading space RoBERTa expects
+            let encoding = self
+                .tokenizer
+                .encode(format!(" {}", text), false)
+                .map_err(Error::msg)?;
+            for &id in encoding.get_ids() {
+                tokens.push(XPathToken {
+                    id,
+                    xpath_tags: xpath_tags.clone(),
+                    xpath_subs: xpath_subs.clone(),
+                });
+            }
+        }
+
+        Ok(tokens)
+    }
+
+    /// The element's tag id, and its 1-based position among siblings with the same tag, or 0 when
+    /// it's the only one
+    fn xpath_unit(&self, element: ElementRef) -> (i64, i64) {
+        let name = element.value().name();
+        let tag_id = self
+            .xpath_config
+            .tags_dict
+            .get(name)
+            .copied()
+            .unwrap_or_else(|| self.unknown_tag_id());
+
+        let same_tag = |sibling: &ElementRef| sibling.value().name() == name;
+        let preceding = element
+            .prev_siblings()
+            .filter_map(ElementRef::wrap)
+            .filter(same_tag)
+            .count();
+        let following = element
+            .next_siblings()
+            .filter_map(ElementRef::wrap)
+            .filter(same_tag)
+            .count();
+
+        let subscript = if preceding + following == 0 {
+            0
+        } else {
+            (preceding as i64 + 1).min(self.xpath_config.max_width)
+        };
+
+        (tag_id, subscript)
+    }
+
+    fn unknown_tag_id(&self) -> i64 {
+        self.xpath_config.tags_dict.len() as i64
+    }
+
+    fn pad_tag_id(&self) -> i64 {
+        self.unknown_tag_id() + 1
+    }
+
+    /// Runs all windows as one batch and mean pools each over its attended tokens, normalized so
+    /// windows weigh the same when pooled
+    fn embed_windows(&self, windows: &[&[XPathToken]]) -> Result<Vec<Vec<f32>>, Error> {
+        let max_depth = self.xpath_config.max_depth;
+        let batch_size = windows.len();
+        let sequence_length = windows.iter().map(|window| window.len()).max().unwrap_or(0) + 2;
+
+        let mut input_ids =
+            Array2::from_elem((batch_size, sequence_length), self.pad_token_id as i64);
+        let mut attention_mask = Array2::<i64>::zeros((batch_size, sequence_length));
+        let mut xpath_tags_seq =
+            Array3::from_elem((batch_size, sequence_length, max_depth), self.pad_tag_id());
+        let mut xpath_subs_seq = Array3::from_elem(
+            (batch_size, sequence_length, max_depth),
+            self.xpath_config.pad_width,
+        );
+
+        for (row, window) in windows.iter().enumerate() {
+            input_ids[[row, 0]] = self.cls_token_id as i64;
+            for (position, token) in window.iter().enumerate() {
+                input_ids[[row, position + 1]] = token.id as i64;
+                for depth in 0..max_depth {
+                    xpath_tags_seq[[row, position + 1, depth]] = token.xpath_tags[depth];
+                    xpath_subs_seq[[row, position + 1, depth]] = token.xpath_subs[depth];
+                }
+            }
+            input_ids[[row, window.len() + 1]] = self.sep_token_id as i64;
+            attention_mask
+                .row_mut(row)
+                .slice_mut(ndarray::s![..window.len() + 2])
+                .fill(1);
+        }
+
+        let mut session_inputs = ort::inputs![
+            "input_ids" => Value::from_array(input_ids)?,
+            "attention_mask" => Value::from_array(attention_mask.clone())?,
+            "xpath_tags_seq" => Value::from_array(xpath_tags_seq)?,
+            "xpath_subs_seq" => Value::from_array(xpath_subs_seq)?,
+        ]?;
+        if self.need_token_type_ids {
+            let token_type_ids = Array2::<i64>::zeros((batch_size, sequence_length));
+            session_inputs.push((
+                "token_type_ids".into(),
+                Value::from_array(token_type_ids)?.into(),
+            ));
+        }
+
+        let outputs = self.session.run(session_inputs)?;
+        let hidden_states = outputs[self.output_name.as_str()]
+            .try_extract_tensor::<f32>()?
+            .into_dimensionality::<Ix3>()?;
+
+        let embeddings = hidden_states
+            .axis_iter(Axis(0))
+            .zip(attention_mask.axis_iter(Axis(0)))
+            .map(|(token_states, token_mask)| {
+                let mut embedding = vec![0.0_f32; self.dimension];
+                for (token_state, &mask) in token_states.axis_iter(Axis(0)).zip(token_mask) {
+                    if mask == 0 {
+                        continue;
+                    }
+                    for (value, state) in embedding.iter_mut().zip(token_state) {
+                        *value += state;
+                    }
+                }
+
+                let norm = embedding
+                    .iter()
+                    .map(|value| value * value)
+                    .sum::<f32>()
+                    .sqrt()
+                    .max(f32::EPSILON);
+                // The mean over tokens points the same way as their sum, so only the norm matters
+                embedding
+                    .into_iter()
+                    .map(|value| value / norm)
+                    .collect::<Vec<f32>>()
+            })
+            .collect();
+
+        Ok(embeddings)
+    }
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T, Error> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("Couldn't parse {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::preprocessing::chunking::ChunkPooling;
+
+    // Generated by tests/fixtures/markuplm/generate.py: hidden size 8, 20 positions, depth 4
+    const TINY_MODEL_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/markuplm");
+
+    const HTML: i64 = 0;
+    const BODY: i64 = 2;
+    const DIV: i64 = 3;
+    const P: i64 = 4;
+    const UL: i64 = 7;
+    const LI: i64 = 8;
+    const SPAN: i64 = 9;
+    const UNKNOWN: i64 = 10;
+    const PAD: i64 = 11;
+    const PAD_WIDTH: i64 = 7;
+
+    fn tiny_model() -> MarkupLMModel {
+        MarkupLMModel::load(Path::new(TINY_MODEL_DIR)).unwrap()
+    }
+
+    fn chunking(max_tokens: usize, overlap_tokens: usize) -> ChunkingConfig {
+        ChunkingConfig {
+            max_tokens,
+            overlap_tokens,
+            pooling: ChunkPooling::Mean,
+            store_chunks: true,
+        }
+    }
+
+    /// Each distinct xpath, in document order, along with the text of its tokens
+    fn xpaths(model: &MarkupLMModel, page_html: &str) -> Vec<(Vec<i64>, Vec<i64>, String)> {
+        let mut xpaths: Vec<(Vec<i64>, Vec<i64>, Vec<u32>)> = vec![];
+        for token in model.xpath_tokens(page_html).unwrap() {
+            match xpaths.last_mut() {
+                Some((tags, subs, ids))
+                    if *tags == token.xpath_tags && *subs == token.xpath_subs =>
+                {
+                    ids.push(token.id)
+                }
+                _ => xpaths.push((token.xpath_tags, token.xpath_subs, vec![token.id])),
+            }
+        }
+
+        xpaths
+            .into_iter()
+            .map(|(tags, subs, ids)| (tags, subs, model.tokenizer.decode(&ids, true).unwrap()))
+            .collect()
+    }
+
+    fn norm(v: &[f32]) -> f32 {
+        v.iter().map(|value| value * value).sum::<f32>().sqrt()
+    }
+
+    #[test]
+    fn loads_the_model_with_its_tokenizer_and_configs() {
+        let model = tiny_model();
+
+        assert_eq!(model.model_id(), "tiny-random-markuplm");
+        assert_eq!(model.dimension(), 8);
+        assert_eq!(model.max_tokens(), 16);
+        assert_eq!(model.output_name, "last_hidden_state");
+        assert!(model.need_token_type_ids);
+        assert_eq!(
+            (model.cls_token_id, model.pad_token_id, model.sep_token_id),
+            (0, 1, 2)
+        );
+    }
+
+    #[test]
+    fn load_fails_without_an_exported_model() {
+        let error = MarkupLMModel::load(Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures"
+        )))
+        .err()
+        .unwrap();
+
+        assert!(error.to_string().contains("No MarkupLM model"), "{}", error);
+    }
+
+    #[test]
+    fn encodes_xpaths_as_tags_and_sibling_subscripts() {
+        let model = tiny_model();
+
+        assert_eq!(
+            xpaths(
+                &model,
+                "<html><body><div><p>first</p><p>second</p><span>third</span></div>\
+                 <script>skipped()</script></body></html>",
+            ),
+            vec![
+                (
+                    vec![HTML, BODY, DIV, P],
+                    vec![0, 0, 0, 1],
+                    " first".to_string()
+                ),
+                (
+                    vec![HTML, BODY, DIV, P],
+                    vec![0, 0, 0, 2],
+                    " second".to_string()
+                ),
+                (
+                    vec![HTML, BODY, DIV, SPAN],
+                    vec![0, 0, 0, 0],
+                    " third".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn pads_short_xpaths_and_caps_subscripts_at_the_max_width() {
+        let model = tiny_model();
+        let items: String = (1..=8).map(|i| format!("<li>{}</li>", i)).collect();
+
+        // Every item is two tokens, a space and its digit
+        let subscripts: Vec<i64> = model
+            .xpath_tokens(&format!("<ul>{}</ul>", items))
+            .unwrap()
+            .into_iter()
+            .step_by(2)
+            .map(|token| {
+                assert_eq!(token.xpath_tags, vec![HTML, BODY, UL, LI]);
+                token.xpath_subs[3]
+            })
+            .collect();
+        assert_eq!(subscripts, vec![1, 2, 3, 4, 5, 6, 6, 6]);
+
+        assert_eq!(
+            xpaths(&model, "<html><body>shallow</body></html>"),
+            vec![(
+                vec![HTML, BODY, PAD, PAD],
+                vec![0, 0, PAD_WIDTH, PAD_WIDTH],
+                " shallow".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn keeps_the_innermost_tags_of_deep_xpaths() {
+        let model = tiny_model();
+
+        assert_eq!(
+            xpaths(
+                &model,
+                "<html><body><div><section><article><p>deep</p></article></section></div></body></html>",
+            ),
+            vec![(
+                vec![DIV, UNKNOWN, UNKNOWN, P],
+                vec![0, 0, 0, 0],
+                " deep".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn embeds_a_page_into_one_normalized_vector() {
+        let model = tiny_model();
+
+        let output = model
+            .embed_html("<html><body><p>hello</p></body></html>", &chunking(16, 4))
+            .unwrap();
+
+        let embedding = output.embedding.to_vec();
+        assert_eq!(embedding.len(), model.dimension());
+        assert!((norm(&embedding) - 1.0).abs() < 1e-5);
+        assert_eq!(output.chunks.len(), 1);
+    }
+
+    #[test]
+    fn embeds_pages_longer_than_the_model_in_overlapping_windows() {
+        let model = tiny_model();
+        // 6 + 7 + 6 byte-level tokens
+        let page_html = "<html><body><p>first</p><p>second</p><p>third</p></body></html>";
+
+        let output = model
+            .embed_html(page_html, &chunking(model.max_tokens(), 4))
+            .unwrap();
+
+        let chunk_contents: Vec<&str> = output
+            .chunks
+            .iter()
+            .map(|chunk| chunk.contents.as_str())
+            .collect();
+        assert_eq!(chunk_contents, vec![" first second th", "d third"]);
+        for chunk in &output.chunks {
+            assert_eq!(chunk.embedding.as_slice().len(), model.dimension());
+        }
+
+        let chunk_embeddings: Vec<Vec<f32>> = output
+            .chunks
+            .iter()
+            .map(|chunk| chunk.embedding.to_vec())
+            .collect();
+        let pooled = pool(&chunk_embeddings, ChunkPooling::Mean).unwrap();
+        for (value, expected) in output.embedding.as_slice().iter().zip(&pooled) {
+            assert!((value - expected).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn rejects_windows_longer_than_the_model() {
+        let model = tiny_model();
+
+        assert!(model
+            .embed_html("<p>hello</p>", &chunking(model.max_tokens() + 1, 4))
+            .is_err());
+        assert!(model.embed_html("<p>hello</p>", &chunking(4, 4)).is_err());
+    }
+
+    #[test]
+    fn embeds_pages_without_text() {
+        let model = tiny_model();
+
+        let output = model
+            .embed_html("<html><body></body></html>", &chunking(16, 4))
+            .unwrap();
+
+        assert_eq!(output.embedding.as_slice().len(), model.dimension());
+    }
+}
@@ -0,0 +1,22 @@
+/// Everything known about a page while it's preprocessed. Steps read whichever parts they need and
+/// produce the next `text`.
+#[derive(Clone, Debug)]
+pub struct PageContext {
+    pub url: String,
+    /// The most recently logged title, missing when events were only kept with their domain
+    pub title: Option<String>,
+    pub html: String,
+    /// Output of the previous step, starting as the page's HTML
+    pub text: String,
+}
+
+impl PageContext {
+    pub fn new(url: String, title: Option<String>, html: String) -> Self {
+        PageContext {
+            url,
+            title,
+            text: html.clone(),
+            html,
+        }
+    }
+}
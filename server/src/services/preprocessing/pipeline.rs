@@ -1,15 +1,20 @@
 use anyhow::Error;
+use serde_json::json;
 
-use crate::services::preprocessing::pipeline_step::{EmbeddingStep, PreprocessingStep};
+use crate::services::preprocessing::{
+    chunking::EmbeddingOutput,
+    page_context::PageContext,
+    pipeline_step::{EmbeddingStep, PreprocessingStep},
+};
 
 pub struct PreprocessingPipeline {
-    pub name: &'static str,
+    pub name: String,
     pub steps: Vec<Box<dyn PreprocessingStep>>,
     pub embedding_step: Box<dyn EmbeddingStep>,
 }
 
 impl PreprocessingPipeline {
-    pub fn new(name: &'static str, embedding_step: Box<dyn EmbeddingStep>) -> Self {
+    pub fn new(name: String, embedding_step: Box<dyn EmbeddingStep>) -> Self {
         PreprocessingPipeline {
             name,
             steps: vec![],
@@ -17,18 +22,30 @@ impl PreprocessingPipeline {
         }
     }
 
-    pub fn add_step<T: PreprocessingStep + 'static>(mut self, step: T) -> Self {
-        self.steps.push(Box::new(step));
+    pub fn add_step(mut self, step: Box<dyn PreprocessingStep>) -> Self {
+        self.steps.push(step);
         self
     }
 
-    pub fn run(&self, page_content: &str) -> Result<pgvector::Vector, Error> {
-        let mut intermediate_result = page_content.to_string();
+    /// Every step and the embedding settings, so runs notice when the pipeline behind them changed
+    pub fn parameters(&self) -> serde_json::Value {
+        json!({
+            "steps": self
+                .steps
+                .iter()
+                .map(|step| step.parameters())
+                .collect::<Vec<_>>(),
+            "embedding": self.embedding_step.parameters(),
+        })
+    }
+
+    pub fn run(&self, page: &PageContext) -> Result<EmbeddingOutput, Error> {
+        let mut page = page.clone();
 
         for step in &self.steps {
-            intermediate_result = step.process(&intermediate_result)?;
+            page.text = step.process(&page)?;
         }
 
-        self.embedding_step.embed(&intermediate_result)
+        self.embedding_step.embed(&page.text)
     }
 }
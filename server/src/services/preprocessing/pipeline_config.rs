@@ -0,0 +1,125 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Error};
+use serde::Deserialize;
+
+use crate::services::preprocessing::chunking::{ChunkPooling, ChunkingConfig};
+
+/// fastembed's own default, so models already cached there keep working
+const DEFAULT_CACHE_DIR: &str = ".fastembed_cache";
+
+/// Pipelines as defined in the pipelines config file, e.g.
+///
+/// ```toml
+/// cache_dir = ".fastembed_cache"
+/// offline = true
+///
+/// [[pipelines]]
+/// name = "keyword-minilm"
+/// steps = ["html_to_markdown", { step = "extract_keywords", num_keywords = 15 }]
+/// embedding = { type = "fastembed", model = "AllMiniLML6V2" }
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PipelinesConfig {
+    /// Where fastembed models are downloaded to and loaded from
+    #[serde(default = "default_cache_dir")]
+    pub cache_dir: PathBuf,
+    /// Only load models that are already in the cache, never download them
+    #[serde(default)]
+    pub offline: bool,
+    pub pipelines: Vec<PipelineConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub name: String,
+    #[serde(default)]
+    pub steps: Vec<StepConfig>,
+    pub embedding: EmbeddingConfig,
+}
+
+/// A step from the step registry, by name alone or as a table with its arguments
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum StepConfig {
+    Name(String),
+    WithArgs {
+        step: String,
+        #[serde(flatten)]
+        args: toml::Table,
+    },
+}
+
+impl StepConfig {
+    pub fn name(&self) -> &str {
+        match self {
+            StepConfig::Name(name) => name,
+            StepConfig::WithArgs { step, .. } => step,
+        }
+    }
+
+    pub fn args(&self) -> Option<&toml::Table> {
+        match self {
+            StepConfig::Name(_) => None,
+            StepConfig::WithArgs { args, .. } => Some(args),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum EmbeddingConfig {
+    /// Any text embedding model fastembed supports, by its `EmbeddingModel` variant name or model
+    /// code, e.g. `AllMiniLML6V2` or `Qdrant/all-MiniLM-L6-v2-onnx`
+    Fastembed {
+        model: String,
+        #[serde(default)]
+        chunking: Option<ChunkingSettings>,
+    },
+    /// MarkupLM exported to ONNX in a local directory
+    Markuplm { model_dir: PathBuf },
+    /// Hashed bag of words, for running pipelines without downloading a model
+    Hash { dimension: usize },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ChunkingSettings {
+    pub max_tokens: usize,
+    pub overlap_tokens: usize,
+    #[serde(default = "default_pooling")]
+    pub pooling: ChunkPooling,
+    #[serde(default)]
+    pub store_chunks: bool,
+}
+
+impl From<&ChunkingSettings> for ChunkingConfig {
+    fn from(settings: &ChunkingSettings) -> Self {
+        ChunkingConfig {
+            max_tokens: settings.max_tokens,
+            overlap_tokens: settings.overlap_tokens,
+            pooling: settings.pooling,
+            store_chunks: settings.store_chunks,
+        }
+    }
+}
+
+fn default_cache_dir() -> PathBuf {
+    PathBuf::from(DEFAULT_CACHE_DIR)
+}
+
+fn default_pooling() -> ChunkPooling {
+    ChunkPooling::Mean
+}
+
+pub fn load_pipelines_config(path: &Path) -> Result<PipelinesConfig, Error> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Couldn't read the pipelines config at {}", path.display()))?;
+    toml::from_str(&contents)
+        .with_context(|| format!("Invalid pipelines config at {}", path.display()))
+}
@@ -1,57 +1,428 @@
-use anyhow::{Context, Error};
-use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Error};
+use fastembed::{InitOptions, TextEmbedding};
 use pgvector::Vector;
+use serde_json::json;
+use tokenizers::Tokenizer;
+use url::Url;
+
+use crate::services::{
+    preprocessing::{
+        chunking::{
+            chunk_text, pool, ChunkPooling, ChunkingConfig, EmbeddedChunk, EmbeddingOutput,
+        },
+        main_content::MainContentExtractor,
+        markuplm::MarkupLMModel,
+        page_context::PageContext,
+    },
+    utils::{extract_keywords, html_to_markdown},
+};
 
-use crate::services::utils::{extract_keywords, html_to_markdown};
+/// Produces the page's next `text`, with the rest of its context available as extra signal
+pub trait PreprocessingStep: Send + Sync {
+    fn process(&self, page: &PageContext) -> Result<String, Error>;
 
-pub trait PreprocessingStep: Send {
-    fn process(&self, input: &str) -> Result<String, Error>;
+    /// The step's name and arguments, defaults included, stored with each embedding run
+    fn parameters(&self) -> serde_json::Value;
 }
 
-pub trait EmbeddingStep: Send {
-    fn embed(&self, input: &str) -> Result<Vector, Error>;
+pub trait EmbeddingStep: Send + Sync {
+    fn embed(&self, input: &str) -> Result<EmbeddingOutput, Error>;
+
+    /// Identifies the model in run records, so embeddings from different models aren't mixed
+    fn model_id(&self) -> &str;
+
+    fn dimension(&self) -> usize;
+
+    /// The model and whatever else changes its embeddings, like chunking, stored with each
+    /// embedding run
+    fn parameters(&self) -> serde_json::Value;
 }
 
 pub struct HtmlToMarkdownStep;
 
 impl PreprocessingStep for HtmlToMarkdownStep {
-    fn process(&self, page_html: &str) -> Result<String, Error> {
-        html_to_markdown(page_html)
+    fn process(&self, page: &PageContext) -> Result<String, Error> {
+        html_to_markdown(&page.text)
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({ "step": "html_to_markdown" })
+    }
+}
+
+/// Reduces a page's HTML to its main content, so navigation and other boilerplate shared across a
+/// site doesn't dominate the embedding
+pub struct MainContentStep {
+    extractor: MainContentExtractor,
+}
+
+impl MainContentStep {
+    pub fn new() -> Result<Self, Error> {
+        Ok(MainContentStep {
+            extractor: MainContentExtractor::new()?,
+        })
+    }
+}
+
+impl PreprocessingStep for MainContentStep {
+    fn process(&self, page: &PageContext) -> Result<String, Error> {
+        Ok(self.extractor.extract(&page.text))
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({ "step": "main_content" })
     }
 }
 
-pub struct ExtractKeywordsStringStep;
+pub struct ExtractKeywordsStringStep {
+    num_keywords: usize,
+}
+
+impl ExtractKeywordsStringStep {
+    pub fn new(num_keywords: usize) -> Self {
+        ExtractKeywordsStringStep { num_keywords }
+    }
+}
 
 impl PreprocessingStep for ExtractKeywordsStringStep {
-    fn process(&self, text: &str) -> Result<String, Error> {
-        let keywords = extract_keywords(text, 15);
+    fn process(&self, page: &PageContext) -> Result<String, Error> {
+        let keywords = extract_keywords(&page.text, self.num_keywords);
         Ok(keywords.join(" "))
     }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({ "step": "extract_keywords", "num_keywords": self.num_keywords })
+    }
 }
 
-pub struct MiniLMEmbeddingStep {
+/// Puts the page's title ahead of its text. Titles are often the clearest statement of what a page
+/// is about, especially for SPAs that render little text on the server.
+pub struct TitlePrefixStep;
+
+impl PreprocessingStep for TitlePrefixStep {
+    fn process(&self, page: &PageContext) -> Result<String, Error> {
+        match page.title.as_deref().map(str::trim) {
+            Some(title) if !title.is_empty() && title != page.url => {
+                Ok(format!("{}\n\n{}", title, page.text))
+            }
+            _ => Ok(page.text.clone()),
+        }
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({ "step": "title_prefix" })
+    }
+}
+
+// Longer tokens are almost always ids, hashes or slugs glued together without separators
+const MAX_URL_TOKEN_LENGTH: usize = 30;
+
+/// Puts the words in the page's URL path ahead of its text, e.g. `/docs/rust-async/streams` adds
+/// "docs rust async streams". Ids, hashes and file extensions are left out.
+pub struct UrlPathPrefixStep;
+
+impl UrlPathPrefixStep {
+    fn path_tokens(url: &str) -> Vec<String> {
+        let Ok(url) = Url::parse(url) else {
+            return vec![];
+        };
+
+        url.path_segments()
+            .into_iter()
+            .flatten()
+            .flat_map(|segment| {
+                let segment = match segment.rsplit_once('.') {
+                    Some((stem, extension)) if extension.len() <= 4 => stem,
+                    _ => segment,
+                };
+                segment
+                    .split(|c: char| !c.is_alphanumeric())
+                    .map(str::to_lowercase)
+                    .collect::<Vec<_>>()
+            })
+            .filter(|token| {
+                !token.is_empty()
+                    && token.chars().count() <= MAX_URL_TOKEN_LENGTH
+                    && token.chars().any(char::is_alphabetic)
+                    && !is_id_like(token)
+            })
+            .collect()
+    }
+}
+
+/// Mixed letters and digits, like `a1b2c3d4e5` or `3f9c2e`, rather than words like `html5`
+fn is_id_like(token: &str) -> bool {
+    let digits = token.chars().filter(char::is_ascii_digit).count();
+    digits >= 3 && digits * 3 >= token.chars().count()
+}
+
+impl PreprocessingStep for UrlPathPrefixStep {
+    fn process(&self, page: &PageContext) -> Result<String, Error> {
+        let tokens = Self::path_tokens(&page.url);
+        if tokens.is_empty() {
+            return Ok(page.text.clone());
+        }
+
+        Ok(format!("{}\n\n{}", tokens.join(" "), page.text))
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({ "step": "url_path_prefix" })
+    }
+}
+
+// Every fastembed text model takes at least 256 tokens, including [CLS] and [SEP]
+const DEFAULT_CHUNKING: ChunkingConfig = ChunkingConfig {
+    max_tokens: 254,
+    overlap_tokens: 32,
+    pooling: ChunkPooling::Mean,
+    store_chunks: false,
+};
+
+/// Embeds text with a fastembed model, downloading it into the cache directory unless running
+/// offline.
+///
+/// Long inputs are embedded in overlapping token windows and the chunk vectors pooled, rather than
+/// letting the model truncate everything past its token limit.
+pub struct FastembedEmbeddingStep {
     embedding_model: TextEmbedding,
+    tokenizer: Tokenizer,
+    chunking: ChunkingConfig,
+    model_id: String,
+    dimension: usize,
 }
 
-impl MiniLMEmbeddingStep {
-    pub fn new() -> Result<Self, Error> {
+impl FastembedEmbeddingStep {
+    /// `model` is an `EmbeddingModel` variant name, like `AllMiniLML6V2`, or a model code
+    pub fn new(
+        model: &str,
+        cache_dir: &Path,
+        offline: bool,
+        chunking: Option<ChunkingConfig>,
+    ) -> Result<Self, Error> {
+        let chunking = chunking.unwrap_or(DEFAULT_CHUNKING);
+        chunking.validate()?;
+
+        let model_info = TextEmbedding::list_supported_models()
+            .into_iter()
+            .find(|model_info| {
+                format!("{:?}", model_info.model) == model || model_info.model_code == model
+            })
+            .with_context(|| format!("fastembed doesn't support an embedding model {}", model))?;
+
+        if offline && !is_model_cached(cache_dir, &model_info.model_code) {
+            bail!(
+                "Embedding model {} isn't in the cache at {} and downloads are disabled. Run once with \
+                 `offline = false` and network access, or copy the model into the cache.",
+                model_info.model_code,
+                cache_dir.display()
+            );
+        }
+
         let embedding_model = TextEmbedding::try_new(
-            InitOptions::new(EmbeddingModel::AllMiniLML6V2).with_show_download_progress(true),
-        )?;
+            InitOptions::new(model_info.model.clone())
+                .with_cache_dir(cache_dir.to_path_buf())
+                .with_show_download_progress(!offline),
+        )
+        .with_context(|| {
+            format!(
+                "Couldn't load embedding model {} into {}",
+                model_info.model_code,
+                cache_dir.display()
+            )
+        })?;
+
+        // The model's own tokenizer truncates, which would hide everything past the first window
+        let mut tokenizer = embedding_model.tokenizer.clone();
+        tokenizer.with_truncation(None).map_err(Error::msg)?;
+        tokenizer.with_padding(None);
+
+        Ok(FastembedEmbeddingStep {
+            embedding_model,
+            tokenizer,
+            chunking,
+            model_id: model_info.model_code,
+            dimension: model_info.dim,
+        })
+    }
+}
+
+/// Models are cached the way the Hugging Face hub lays them out, one directory per repo
+fn is_model_cached(cache_dir: &Path, model_code: &str) -> bool {
+    let snapshots_dir = cache_dir
+        .join(format!("models--{}", model_code.replace('/', "--")))
+        .join("snapshots");
+
+    fs::read_dir(snapshots_dir)
+        .map(|mut snapshots| snapshots.next().is_some())
+        .unwrap_or(false)
+}
+
+impl EmbeddingStep for FastembedEmbeddingStep {
+    fn embed(&self, text: &str) -> Result<EmbeddingOutput, Error> {
+        let chunks = chunk_text(&self.tokenizer, text, &self.chunking)?;
+        let chunk_embeddings = self.embedding_model.embed(chunks.clone(), None)?;
+        let embedding = Vector::from(pool(&chunk_embeddings, self.chunking.pooling)?);
+
+        let chunks = if self.chunking.store_chunks {
+            chunks
+                .into_iter()
+                .zip(chunk_embeddings)
+                .map(|(contents, chunk_embedding)| EmbeddedChunk {
+                    contents,
+                    embedding: Vector::from(chunk_embedding),
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+
+        Ok(EmbeddingOutput { embedding, chunks })
+    }
 
-        Ok(MiniLMEmbeddingStep { embedding_model })
+    fn model_id(&self) -> &str {
+        &self.model_id
     }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "fastembed",
+            "model": self.model_id,
+            "chunking": self.chunking.parameters(),
+        })
+    }
+}
+
+// FNV-1a, which unlike std's hashers is guaranteed to give the same hashes across builds
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Hashes every lowercased word into one of `dimension` buckets with a hash-derived sign. It's
+/// deterministic and needs no model, so whole pipelines can run in CI, and pages sharing words
+/// still end up close together.
+pub struct HashEmbeddingStep {
+    dimension: usize,
+    model_id: String,
 }
 
-impl EmbeddingStep for MiniLMEmbeddingStep {
-    fn embed(&self, text: &str) -> Result<Vector, Error> {
-        let pages_to_embed = vec![text];
-        let page_embedding_vec = self
-            .embedding_model
-            .embed(pages_to_embed, None)?
-            .pop()
-            .context("List of embeddings is empty")?;
+impl HashEmbeddingStep {
+    pub fn new(dimension: usize) -> Result<Self, Error> {
+        if dimension == 0 {
+            bail!("Hash embeddings need at least one dimension");
+        }
+
+        Ok(HashEmbeddingStep {
+            dimension,
+            model_id: format!("hash-fnv1a-{}", dimension),
+        })
+    }
+}
+
+impl EmbeddingStep for HashEmbeddingStep {
+    fn embed(&self, text: &str) -> Result<EmbeddingOutput, Error> {
+        let mut embedding = vec![0.0_f32; self.dimension];
+        for word in text.split(|c: char| !c.is_alphanumeric()) {
+            if word.is_empty() {
+                continue;
+            }
+
+            let hash = word
+                .to_lowercase()
+                .bytes()
+                .fold(FNV_OFFSET_BASIS, |hash, byte| {
+                    (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+                });
+            let bucket = (hash % self.dimension as u64) as usize;
+            embedding[bucket] += if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        }
+
+        let norm = embedding
+            .iter()
+            .map(|value| value * value)
+            .sum::<f32>()
+            .sqrt();
+        if norm > 0.0 {
+            for value in &mut embedding {
+                *value /= norm;
+            }
+        }
 
-        Ok(pgvector::Vector::from(page_embedding_vec))
+        Ok(EmbeddingOutput {
+            embedding: Vector::from(embedding),
+            chunks: vec![],
+        })
+    }
+
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+    fn parameters(&self) -> serde_json::Value {
+        json!({ "type": "hash", "dimension": self.dimension })
+    }
+}
+
+const MARKUPLM_DEFAULT_OVERLAP_TOKENS: usize = 64;
+
+/// Embeds a page's HTML with MarkupLM, loaded from a local model directory. Unlike the text
+/// models, it expects HTML as input, so only steps that keep the markup can come before it.
+pub struct MarkupLMEmbeddingStep {
+    model: MarkupLMModel,
+    chunking: ChunkingConfig,
+}
+
+impl MarkupLMEmbeddingStep {
+    pub fn new(model_dir: &Path) -> Result<Self, Error> {
+        let model = MarkupLMModel::load(model_dir)?;
+        let chunking = ChunkingConfig {
+            max_tokens: model.max_tokens(),
+            overlap_tokens: MARKUPLM_DEFAULT_OVERLAP_TOKENS,
+            pooling: ChunkPooling::Mean,
+            store_chunks: false,
+        };
+
+        Self::with_chunking(model, chunking)
+    }
+
+    pub fn with_chunking(model: MarkupLMModel, chunking: ChunkingConfig) -> Result<Self, Error> {
+        chunking.validate()?;
+        if chunking.max_tokens > model.max_tokens() {
+            bail!(
+                "MarkupLM chunks can have at most {} tokens, not {}",
+                model.max_tokens(),
+                chunking.max_tokens
+            );
+        }
+
+        Ok(MarkupLMEmbeddingStep { model, chunking })
+    }
+}
+
+impl EmbeddingStep for MarkupLMEmbeddingStep {
+    fn embed(&self, page_html: &str) -> Result<EmbeddingOutput, Error> {
+        self.model.embed_html(page_html, &self.chunking)
+    }
+
+    fn model_id(&self) -> &str {
+        self.model.model_id()
+    }
+
+    fn dimension(&self) -> usize {
+        self.model.dimension()
+    }
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "markuplm",
+            "model": self.model.model_id(),
+            "chunking": self.chunking.parameters(),
+        })
     }
 }
@@ -1,27 +1,319 @@
-use anyhow::Error;
+use std::collections::HashSet;
+
+use anyhow::{bail, Context, Error};
 
 use crate::services::preprocessing::pipeline::PreprocessingPipeline;
+use crate::services::preprocessing::pipeline_config::{
+    EmbeddingConfig, PipelineConfig, PipelinesConfig,
+};
 use crate::services::preprocessing::pipeline_step::{
-    ExtractKeywordsStringStep, HtmlToMarkdownStep, MiniLMEmbeddingStep,
+    EmbeddingStep, FastembedEmbeddingStep, HashEmbeddingStep, MarkupLMEmbeddingStep,
 };
+use crate::services::preprocessing::step_registry::{default_step_registry, StepRegistry};
+
+/// Builds the pipelines in the config. The whole config is validated before any model is loaded,
+/// and every model is loaded up front, so mistakes fail startup rather than the first page.
+pub fn get_all_preprocessing_pipelines(
+    config: &PipelinesConfig,
+) -> Result<Vec<PreprocessingPipeline>, Error> {
+    let registry = default_step_registry();
+    validate_pipelines_config(config, &registry)?;
+
+    config
+        .pipelines
+        .iter()
+        .map(|pipeline_config| {
+            build_pipeline(pipeline_config, config, &registry)
+                .with_context(|| format!("Couldn't build pipeline {}", pipeline_config.name))
+        })
+        .collect()
+}
+
+/// Builds only the named pipeline, so commands working on one pipeline don't load every model
+pub fn get_preprocessing_pipeline(
+    config: &PipelinesConfig,
+    name: &str,
+) -> Result<PreprocessingPipeline, Error> {
+    let registry = default_step_registry();
+    validate_pipelines_config(config, &registry)?;
+
+    let pipeline_config = config
+        .pipelines
+        .iter()
+        .find(|pipeline_config| pipeline_config.name == name)
+        .with_context(|| format!("Pipeline {} isn't configured", name))?;
+
+    build_pipeline(pipeline_config, config, &registry)
+        .with_context(|| format!("Couldn't build pipeline {}", name))
+}
+
+fn validate_pipelines_config(
+    config: &PipelinesConfig,
+    registry: &StepRegistry,
+) -> Result<(), Error> {
+    if config.pipelines.is_empty() {
+        bail!("No pipelines are configured");
+    }
+
+    let mut names = HashSet::new();
+    for pipeline_config in &config.pipelines {
+        let name = &pipeline_config.name;
+        if !is_valid_pipeline_name(name) {
+            bail!(
+                "Invalid pipeline name {:?}, use lowercase letters, digits, - and _",
+                name
+            );
+        }
+        if !names.insert(name) {
+            bail!("Pipeline {} is configured more than once", name);
+        }
+
+        for step_config in &pipeline_config.steps {
+            registry
+                .validate(step_config)
+                .with_context(|| format!("Invalid step in pipeline {}", name))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Names end up in run names, urls and CLI arguments
+fn is_valid_pipeline_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+fn build_pipeline(
+    pipeline_config: &PipelineConfig,
+    config: &PipelinesConfig,
+    registry: &StepRegistry,
+) -> Result<PreprocessingPipeline, Error> {
+    let embedding_step: Box<dyn EmbeddingStep> = match &pipeline_config.embedding {
+        EmbeddingConfig::Fastembed { model, chunking } => Box::new(FastembedEmbeddingStep::new(
+            model,
+            &config.cache_dir,
+            config.offline,
+            chunking.as_ref().map(Into::into),
+        )?),
+        EmbeddingConfig::Markuplm { model_dir } => Box::new(MarkupLMEmbeddingStep::new(model_dir)?),
+        EmbeddingConfig::Hash { dimension } => Box::new(HashEmbeddingStep::new(*dimension)?),
+    };
+
+    let mut pipeline = PreprocessingPipeline::new(pipeline_config.name.clone(), embedding_step);
+    for step_config in &pipeline_config.steps {
+        pipeline = pipeline.add_step(registry.build(step_config)?);
+    }
+
+    Ok(pipeline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::preprocessing::page_context::PageContext;
+    use std::path::Path;
+
+    const ARTICLE_WITH_BOILERPLATE: &str =
+        include_str!("../../../tests/fixtures/article_with_boilerplate.html");
+
+    // The same article as the fixture, without anything around it
+    const BARE_ARTICLE: &str = "<h1>Sourdough starters, explained</h1>\
+        <p>A sourdough starter is a living culture of wild yeast and lactic acid bacteria, kept \
+        alive by regular feedings of flour and water.</p>\
+        <p>Most bakers feed their starter once a day, discarding part of it, so that the yeast \
+        always has fresh flour to eat and the acidity stays in check.</p>\
+        <p>When the starter doubles within a few hours of a feeding, bubbles all the way through, \
+        and smells pleasantly sour, it is ready to leaven a loaf of bread.</p>";
+
+    const HASH_PIPELINES: &str = r#"
+        [[pipelines]]
+        name = "direct-hash"
+        steps = ["html_to_markdown"]
+        embedding = { type = "hash", dimension = 16 }
+
+        [[pipelines]]
+        name = "main-content-hash"
+        steps = ["main_content", "html_to_markdown"]
+        embedding = { type = "hash", dimension = 16 }
+
+        [[pipelines]]
+        name = "title-keywords-hash"
+        steps = ["html_to_markdown", { step = "extract_keywords", num_keywords = 5 }, "title_prefix"]
+        embedding = { type = "hash", dimension = 32 }
+    "#;
+
+    fn pipelines_config(toml: &str) -> PipelinesConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn page(html: &str) -> PageContext {
+        PageContext::new(
+            "https://example.com/sourdough".to_string(),
+            Some("Sourdough starters".to_string()),
+            html.to_string(),
+        )
+    }
+
+    fn embed(pipeline: &PreprocessingPipeline, html: &str) -> Vec<f32> {
+        pipeline.run(&page(html)).unwrap().embedding.to_vec()
+    }
+
+    #[test]
+    fn runs_hash_pipelines_from_the_config_end_to_end() {
+        let pipelines = get_all_preprocessing_pipelines(&pipelines_config(HASH_PIPELINES)).unwrap();
+
+        let names: Vec<&str> = pipelines
+            .iter()
+            .map(|pipeline| pipeline.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["direct-hash", "main-content-hash", "title-keywords-hash"]
+        );
+
+        for pipeline in &pipelines {
+            let output = pipeline.run(&page(ARTICLE_WITH_BOILERPLATE)).unwrap();
+            let embedding = output.embedding.to_vec();
+
+            assert_eq!(embedding.len(), pipeline.embedding_step.dimension());
+            let norm = embedding
+                .iter()
+                .map(|value| value * value)
+                .sum::<f32>()
+                .sqrt();
+            assert!(
+                (norm - 1.0).abs() < 1e-5,
+                "{} isn't normalized",
+                pipeline.name
+            );
+            assert!(output.chunks.is_empty());
+        }
+        assert_eq!(pipelines[0].embedding_step.model_id(), "hash-fnv1a-16");
+    }
+
+    #[test]
+    fn hash_pipelines_embed_deterministically() {
+        let config = pipelines_config(HASH_PIPELINES);
+        let first = get_preprocessing_pipeline(&config, "direct-hash").unwrap();
+        let second = get_preprocessing_pipeline(&config, "direct-hash").unwrap();
+
+        assert_eq!(
+            embed(&first, ARTICLE_WITH_BOILERPLATE),
+            embed(&second, ARTICLE_WITH_BOILERPLATE)
+        );
+    }
+
+    #[test]
+    fn main_content_pipelines_only_embed_the_article() {
+        let config = pipelines_config(HASH_PIPELINES);
+        let direct = get_preprocessing_pipeline(&config, "direct-hash").unwrap();
+        let main_content = get_preprocessing_pipeline(&config, "main-content-hash").unwrap();
+
+        // Only the article is left to embed, so the boilerplate around it makes no difference
+        assert_eq!(
+            embed(&main_content, ARTICLE_WITH_BOILERPLATE),
+            embed(&direct, BARE_ARTICLE)
+        );
+        assert_ne!(
+            embed(&direct, ARTICLE_WITH_BOILERPLATE),
+            embed(&direct, BARE_ARTICLE)
+        );
+    }
+
+    #[test]
+    fn only_builds_the_requested_pipeline() {
+        let config = pipelines_config(HASH_PIPELINES);
+
+        assert!(get_preprocessing_pipeline(&config, "main-content-hash").is_ok());
+        let error = get_preprocessing_pipeline(&config, "missing")
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "Pipeline missing isn't configured");
+    }
+
+    #[test]
+    fn rejects_invalid_configs_before_building_anything() {
+        let error = |toml: &str| {
+            format!(
+                "{:#}",
+                get_all_preprocessing_pipelines(&pipelines_config(toml))
+                    .err()
+                    .unwrap()
+            )
+        };
+
+        assert!(error("pipelines = []").contains("No pipelines are configured"));
+        assert!(error(
+            r#"
+            [[pipelines]]
+            name = "hash"
+            embedding = { type = "hash", dimension = 16 }
 
-pub const DIRECT_MINILM_PIPELINE: &str = "direct-minilm";
-pub const KEYWORD_MINILM_PIPELINE: &str = "keyword-minilm";
-pub const MARKUPLM_PIPELINE: &str = "markuplm";
+            [[pipelines]]
+            name = "hash"
+            embedding = { type = "hash", dimension = 8 }
+            "#
+        )
+        .contains("configured more than once"));
+        assert!(error(
+            r#"
+            [[pipelines]]
+            name = "Hash Pipeline"
+            embedding = { type = "hash", dimension = 16 }
+            "#
+        )
+        .contains("Invalid pipeline name"));
+        assert!(error(
+            r#"
+            [[pipelines]]
+            name = "hash"
+            steps = ["summarize"]
+            embedding = { type = "hash", dimension = 16 }
+            "#
+        )
+        .contains("Invalid step in pipeline hash"));
+        assert!(error(
+            r#"
+            [[pipelines]]
+            name = "hash"
+            embedding = { type = "hash", dimension = 0 }
+            "#
+        )
+        .contains("at least one dimension"));
+    }
 
-pub fn get_all_preprocessing_pipelines() -> Result<Vec<PreprocessingPipeline>, Error> {
-    let mut pipelines = Vec::new();
+    #[test]
+    fn offline_pipelines_fail_at_startup_without_a_cached_model() {
+        let mut config = pipelines_config(
+            r#"
+            offline = true
 
-    let embedding_step = Box::new(MiniLMEmbeddingStep::new()?);
-    let pipeline = PreprocessingPipeline::new(DIRECT_MINILM_PIPELINE, embedding_step)
-        .add_step(HtmlToMarkdownStep);
-    pipelines.push(pipeline);
+            [[pipelines]]
+            name = "direct-minilm"
+            steps = ["html_to_markdown"]
+            embedding = { type = "fastembed", model = "AllMiniLML6V2" }
+            "#,
+        );
+        config.cache_dir =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/empty_model_cache");
 
-    let embedding_step = Box::new(MiniLMEmbeddingStep::new()?);
-    let pipeline = PreprocessingPipeline::new(KEYWORD_MINILM_PIPELINE, embedding_step)
-        .add_step(HtmlToMarkdownStep)
-        .add_step(ExtractKeywordsStringStep);
-    pipelines.push(pipeline);
+        let error = format!(
+            "{:#}",
+            get_all_preprocessing_pipelines(&config).err().unwrap()
+        );
 
-    Ok(pipelines)
+        assert!(
+            error.contains("Couldn't build pipeline direct-minilm"),
+            "{}",
+            error
+        );
+        assert!(
+            error.contains("isn't in the cache") && error.contains("downloads are disabled"),
+            "{}",
+            error
+        );
+    }
 }
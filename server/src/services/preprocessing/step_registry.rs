@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Error};
+
+use crate::services::preprocessing::{
+    pipeline_config::StepConfig,
+    pipeline_step::{
+        ExtractKeywordsStringStep, HtmlToMarkdownStep, MainContentStep, PreprocessingStep,
+        TitlePrefixStep, UrlPathPrefixStep,
+    },
+};
+
+const DEFAULT_NUM_KEYWORDS: usize = 15;
+
+type BuildStep = fn(&StepArgs) -> Result<Box<dyn PreprocessingStep>, Error>;
+
+/// Builds a step from the arguments given in the config, which are checked against `args` first
+pub struct StepFactory {
+    args: &'static [&'static str],
+    build: BuildStep,
+}
+
+/// The arguments a step was configured with, e.g. `num_keywords` in
+/// `{ step = "extract_keywords", num_keywords = 20 }`
+pub struct StepArgs<'a> {
+    step: &'a str,
+    args: Option<&'a toml::Table>,
+}
+
+impl StepArgs<'_> {
+    pub fn usize_or(&self, arg: &str, default: usize) -> Result<usize, Error> {
+        let Some(value) = self.args.and_then(|args| args.get(arg)) else {
+            return Ok(default);
+        };
+
+        value
+            .as_integer()
+            .and_then(|value| usize::try_from(value).ok())
+            .with_context(|| {
+                format!(
+                    "{} of step {} must be a non-negative integer, not {}",
+                    arg, self.step, value
+                )
+            })
+    }
+}
+
+/// Preprocessing steps by the names pipelines refer to them by in the config
+pub struct StepRegistry {
+    factories: HashMap<&'static str, StepFactory>,
+}
+
+impl StepRegistry {
+    pub fn new() -> Self {
+        StepRegistry {
+            factories: HashMap::new(),
+        }
+    }
+
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        args: &'static [&'static str],
+        build: BuildStep,
+    ) {
+        self.factories.insert(name, StepFactory { args, build });
+    }
+
+    /// Checks that the step exists and takes the given arguments, without building it
+    pub fn validate(&self, step_config: &StepConfig) -> Result<&StepFactory, Error> {
+        let name = step_config.name();
+        let Some(factory) = self.factories.get(name) else {
+            let mut known_steps: Vec<_> = self.factories.keys().copied().collect();
+            known_steps.sort();
+            bail!(
+                "Unknown step {}, expected one of {}",
+                name,
+                known_steps.join(", ")
+            );
+        };
+
+        if let Some(arg) = step_config
+            .args()
+            .into_iter()
+            .flat_map(|args| args.keys())
+            .find(|arg| !factory.args.contains(&arg.as_str()))
+        {
+            if factory.args.is_empty() {
+                bail!("Step {} doesn't take any arguments, got {}", name, arg);
+            }
+            bail!(
+                "Step {} doesn't take {}, only {}",
+                name,
+                arg,
+                factory.args.join(", ")
+            );
+        }
+
+        Ok(factory)
+    }
+
+    pub fn build(&self, step_config: &StepConfig) -> Result<Box<dyn PreprocessingStep>, Error> {
+        let factory = self.validate(step_config)?;
+        (factory.build)(&StepArgs {
+            step: step_config.name(),
+            args: step_config.args(),
+        })
+    }
+}
+
+/// Every step pipelines can use
+pub fn default_step_registry() -> StepRegistry {
+    let mut registry = StepRegistry::new();
+
+    registry.register("html_to_markdown", &[], |_| {
+        Ok(Box::new(HtmlToMarkdownStep))
+    });
+    registry.register("extract_keywords", &["num_keywords"], |args| {
+        let num_keywords = args.usize_or("num_keywords", DEFAULT_NUM_KEYWORDS)?;
+        if num_keywords == 0 {
+            bail!("extract_keywords needs num_keywords of at least 1");
+        }
+        Ok(Box::new(ExtractKeywordsStringStep::new(num_keywords)))
+    });
+    registry.register("main_content", &[], |_| {
+        Ok(Box::new(MainContentStep::new()?))
+    });
+    registry.register("title_prefix", &[], |_| Ok(Box::new(TitlePrefixStep)));
+    registry.register("url_path_prefix", &[], |_| Ok(Box::new(UrlPathPrefixStep)));
+
+    registry
+}
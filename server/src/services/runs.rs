@@ -0,0 +1,241 @@
+use anyhow::{bail, Context, Error};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{
+    db::run::{
+        create_clustering_run_index, get_clustering_run, get_embedding_run, insert_clustering_run,
+        insert_embedding_run,
+    },
+    models::run::{NewClusteringRun, NewEmbeddingRun, RunStatus},
+    services::{
+        clustering::cluster_algorithms::{clustering_run_name, get_all_cluster_algorithms},
+        preprocessing::pipeline::PreprocessingPipeline,
+    },
+};
+
+/// The git commit the server was built from, recorded on every run so results can be traced back
+/// to the code that produced them. Set by `build.rs`.
+pub const CODE_VERSION: &str = env!("GIT_SHA");
+
+/// Registers an embedding run for every pipeline, and a clustering run for every pipeline and
+/// online algorithm, so runs and their vector indexes exist before anything is written to them.
+pub async fn register_runs(
+    db: &PgPool,
+    preprocessing_pipelines: &[PreprocessingPipeline],
+) -> Result<(), Error> {
+    for preprocessing_pipeline in preprocessing_pipelines {
+        register_embedding_run(db, preprocessing_pipeline).await?;
+        register_online_clustering_runs(db, preprocessing_pipeline).await?;
+    }
+
+    Ok(())
+}
+
+/// Embeddings from different models, or from the same model fed differently preprocessed pages,
+/// can't be compared, so a pipeline whose model, steps or embedding settings changed has to be
+/// renamed to start a new run.
+async fn register_embedding_run(
+    db: &PgPool,
+    preprocessing_pipeline: &PreprocessingPipeline,
+) -> Result<(), Error> {
+    let model_id = preprocessing_pipeline.embedding_step.model_id();
+    let embedding_dimension = i32::try_from(preprocessing_pipeline.embedding_step.dimension())?;
+    let parameters = preprocessing_pipeline.parameters();
+
+    let new_embedding_run = NewEmbeddingRun {
+        name: &preprocessing_pipeline.name,
+        pipeline_name: &preprocessing_pipeline.name,
+        model_id,
+        embedding_dimension,
+        parameters: parameters.clone(),
+    };
+    insert_embedding_run(db, &new_embedding_run, CODE_VERSION).await?;
+
+    let embedding_run = get_embedding_run(db, &preprocessing_pipeline.name)
+        .await?
+        .with_context(|| format!("Embedding run {} wasn't registered", new_embedding_run.name))?;
+
+    // Runs that predate run records don't know their model
+    let model_changed = embedding_run
+        .model_id
+        .as_deref()
+        .is_some_and(|existing_model_id| existing_model_id != model_id);
+    let dimension_changed = embedding_run
+        .embedding_dimension
+        .is_some_and(|existing_dimension| existing_dimension != embedding_dimension);
+    if model_changed || dimension_changed {
+        bail!(
+            "Embedding run {} was created with {:?} ({:?} dimensions), but the pipeline now uses {} ({} dimensions). Rename the pipeline to start a new run.",
+            embedding_run.name,
+            embedding_run.model_id,
+            embedding_run.embedding_dimension,
+            model_id,
+            embedding_dimension
+        );
+    }
+
+    // Runs registered before parameters were recorded have legacy ones, so they never match
+    if embedding_run.parameters != parameters {
+        bail!(
+            "Embedding run {} was created by the pipeline {}, but it's now {}. Rename the pipeline to start a new run.",
+            embedding_run.name,
+            embedding_run.parameters,
+            parameters
+        );
+    }
+
+    Ok(())
+}
+
+async fn register_online_clustering_runs(
+    db: &PgPool,
+    preprocessing_pipeline: &PreprocessingPipeline,
+) -> Result<(), Error> {
+    let embedding_dimension = i32::try_from(preprocessing_pipeline.embedding_step.dimension())?;
+    for cluster_algorithm in get_all_cluster_algorithms() {
+        if !cluster_algorithm.is_online() {
+            continue;
+        }
+
+        let name = clustering_run_name(&preprocessing_pipeline.name, cluster_algorithm.as_ref());
+        create_clustering_run_index(
+            db,
+            &name,
+            embedding_dimension,
+            cluster_algorithm.similarity_metric(),
+        )
+        .await?;
+
+        let parameters = cluster_algorithm.parameters();
+        let new_clustering_run = NewClusteringRun {
+            name: &name,
+            embedding_run: &preprocessing_pipeline.name,
+            algorithm: cluster_algorithm.name(),
+            parameters: parameters.clone(),
+            status: RunStatus::Running,
+        };
+        if insert_clustering_run(db, &new_clustering_run, CODE_VERSION).await? {
+            continue;
+        }
+
+        // Runs that predate run records don't know their parameters
+        let existing_parameters = get_clustering_run(db, &name)
+            .await?
+            .map(|clustering_run| clustering_run.parameters)
+            .filter(|existing_parameters| *existing_parameters != json!({}));
+        if let Some(existing_parameters) = existing_parameters {
+            if existing_parameters != parameters {
+                eprintln!(
+                    "Clustering run {} was created with {}, but now runs with {}",
+                    name, existing_parameters, parameters
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::preprocessing::{
+        pipeline_config::PipelinesConfig, pipelines::get_preprocessing_pipeline,
+    };
+
+    fn hash_pipeline(steps: &str) -> PreprocessingPipeline {
+        let config: PipelinesConfig = toml::from_str(&format!(
+            r#"
+            [[pipelines]]
+            name = "hash"
+            steps = {}
+            embedding = {{ type = "hash", dimension = 8 }}
+            "#,
+            steps
+        ))
+        .unwrap();
+
+        get_preprocessing_pipeline(&config, "hash").unwrap()
+    }
+
+    #[sqlx::test]
+    async fn reregistering_an_unchanged_pipeline_keeps_its_runs(db: PgPool) -> Result<(), Error> {
+        let pipeline = hash_pipeline(r#"["main_content", "html_to_markdown"]"#);
+
+        register_runs(&db, std::slice::from_ref(&pipeline)).await?;
+        register_runs(&db, std::slice::from_ref(&pipeline)).await?;
+
+        let embedding_run = get_embedding_run(&db, "hash").await?.unwrap();
+        assert_eq!(embedding_run.parameters, pipeline.parameters());
+        assert_eq!(embedding_run.model_id.as_deref(), Some("hash-fnv1a-8"));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn defaulted_step_arguments_are_the_same_pipeline(db: PgPool) -> Result<(), Error> {
+        register_runs(&db, &[hash_pipeline(r#"["extract_keywords"]"#)]).await?;
+
+        register_runs(
+            &db,
+            &[hash_pipeline(
+                r#"[{ step = "extract_keywords", num_keywords = 15 }]"#,
+            )],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn refuses_to_start_when_the_pipeline_changed(db: PgPool) -> Result<(), Error> {
+        register_runs(&db, &[hash_pipeline(r#"["html_to_markdown"]"#)]).await?;
+
+        for changed_steps in [
+            r#"["main_content", "html_to_markdown"]"#,
+            r#"["html_to_markdown", "title_prefix"]"#,
+            r#"[{ step = "extract_keywords", num_keywords = 5 }]"#,
+        ] {
+            let error = register_runs(&db, &[hash_pipeline(changed_steps)])
+                .await
+                .unwrap_err();
+            assert!(
+                error.to_string().contains("Rename the pipeline"),
+                "{}",
+                error
+            );
+        }
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn refuses_to_start_on_runs_from_before_parameters_were_recorded(
+        db: PgPool,
+    ) -> Result<(), Error> {
+        // As recorded by the migration that marks them
+        let new_embedding_run = NewEmbeddingRun {
+            name: "hash",
+            pipeline_name: "hash",
+            model_id: "hash-fnv1a-8",
+            embedding_dimension: 8,
+            parameters: json!({ "legacy": true }),
+        };
+        insert_embedding_run(&db, &new_embedding_run, "test").await?;
+
+        let error = register_runs(&db, &[hash_pipeline(r#"["html_to_markdown"]"#)])
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("Rename the pipeline"),
+            "{}",
+            error
+        );
+
+        let embedding_run = get_embedding_run(&db, "hash").await?.unwrap();
+        assert_eq!(embedding_run.parameters, json!({ "legacy": true }));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{Context, Error};
+use chrono::{DateTime, Days, Duration, FixedOffset, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        browse_event::{BrowseEventRow, BrowseEventType},
+        cluster::PageClusterRow,
+        DwellTimeBucket, PageDwellTimeRow,
+    },
+    services::event_buckets::BucketInterval,
+};
+
+// Fallback for when idle events never arrive, e.g. the browser crashed or the extension was off
+pub const MAX_IDLE_GAP_MINUTES: i64 = 15;
+
+/// A stretch of time during which a single page had the user's attention.
+#[derive(Serialize, Debug, Clone)]
+pub struct AttentionInterval {
+    pub tab_id: i32,
+    pub page_id: Option<i32>,
+    pub page_url: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub duration_seconds: f64,
+}
+
+impl AttentionInterval {
+    fn new(
+        tab_id: i32,
+        page_id: Option<i32>,
+        page_url: String,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Self {
+        let duration_seconds = (end - start).num_milliseconds() as f64 / 1000.0;
+        AttentionInterval {
+            tab_id,
+            page_id,
+            page_url,
+            start,
+            end,
+            duration_seconds,
+        }
+    }
+
+    fn clip(&self, range_start: DateTime<Utc>, range_end: DateTime<Utc>) -> Option<Self> {
+        let start = self.start.max(range_start);
+        let end = self.end.min(range_end);
+
+        if end <= start {
+            return None;
+        }
+
+        Some(AttentionInterval::new(
+            self.tab_id,
+            self.page_id,
+            self.page_url.clone(),
+            start,
+            end,
+        ))
+    }
+}
+
+struct OpenInterval {
+    tab_id: i32,
+    page_id: Option<i32>,
+    page_url: String,
+    start: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+}
+
+impl OpenInterval {
+    fn from_event(event: &BrowseEventRow) -> Self {
+        OpenInterval {
+            tab_id: event.tab_id,
+            page_id: event.page_id,
+            page_url: event.page_url.clone(),
+            start: event.timestamp,
+            last_seen: event.timestamp,
+        }
+    }
+
+    fn close(self, end: DateTime<Utc>) -> AttentionInterval {
+        AttentionInterval::new(self.tab_id, self.page_id, self.page_url, self.start, end)
+    }
+}
+
+/// Which tab has the user's focus, as far as the events so far tell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    /// Nothing told yet, e.g. at the start of a time range
+    Unknown,
+    Tab(i32),
+    /// The browser lost focus, so nothing has it until a tab is activated or focused again
+    Lost,
+}
+
+fn close_open_interval(
+    open_interval: &mut Option<OpenInterval>,
+    intervals: &mut Vec<AttentionInterval>,
+    end: DateTime<Utc>,
+) {
+    if let Some(current) = open_interval.take() {
+        if end > current.start {
+            intervals.push(current.close(end));
+        }
+    }
+}
+
+/// Turns a timestamp-ordered stream of browse events into attention intervals.
+///
+/// Only one page has the user's attention at a time. `activate` and `focus_gained` always move
+/// attention to the event's tab, while `update` only does so for the focused tab, or any tab while
+/// focus isn't known yet. Losing window focus, going idle, closing the focused tab or closing the
+/// browser all end the current interval and leave no tab focused, so background tabs that finish
+/// loading meanwhile don't count as attention. Going longer than `max_idle_gap` without any
+/// activity on the focused page also ends the interval.
+pub fn sessionize(
+    events: &[BrowseEventRow],
+    max_idle_gap: Duration,
+    until: DateTime<Utc>,
+) -> Vec<AttentionInterval> {
+    let mut intervals = Vec::new();
+    let mut open_interval: Option<OpenInterval> = None;
+    let mut focus = Focus::Unknown;
+
+    for event in events {
+        let idle_deadline = open_interval
+            .as_ref()
+            .map(|current| current.last_seen + max_idle_gap)
+            .filter(|idle_deadline| event.timestamp > *idle_deadline);
+        if let Some(idle_deadline) = idle_deadline {
+            close_open_interval(&mut open_interval, &mut intervals, idle_deadline);
+        }
+
+        match event.event_type {
+            BrowseEventType::Activate | BrowseEventType::Update | BrowseEventType::FocusGained => {
+                // Background tabs can finish loading without being looked at
+                if event.event_type == BrowseEventType::Update
+                    && focus != Focus::Unknown
+                    && focus != Focus::Tab(event.tab_id)
+                {
+                    continue;
+                }
+                focus = Focus::Tab(event.tab_id);
+
+                let continues_open_interval = open_interval.as_ref().is_some_and(|current| {
+                    current.tab_id == event.tab_id && current.page_url == event.page_url
+                });
+
+                if continues_open_interval {
+                    if let Some(current) = &mut open_interval {
+                        current.last_seen = event.timestamp;
+                    }
+                } else {
+                    close_open_interval(&mut open_interval, &mut intervals, event.timestamp);
+                    open_interval = Some(OpenInterval::from_event(event));
+                }
+            }
+            BrowseEventType::TabClosed => {
+                if focus == Focus::Tab(event.tab_id) {
+                    close_open_interval(&mut open_interval, &mut intervals, event.timestamp);
+                    focus = Focus::Lost;
+                }
+            }
+            BrowseEventType::FocusLost | BrowseEventType::Idle | BrowseEventType::BrowserClosed => {
+                close_open_interval(&mut open_interval, &mut intervals, event.timestamp);
+                focus = Focus::Lost;
+            }
+        }
+    }
+
+    let last_end = open_interval
+        .as_ref()
+        .map(|current| (current.last_seen + max_idle_gap).min(until));
+    if let Some(last_end) = last_end {
+        close_open_interval(&mut open_interval, &mut intervals, last_end);
+    }
+
+    intervals
+}
+
+pub fn local_midnight(date: NaiveDate, tz: &Tz) -> Result<DateTime<Utc>, Error> {
+    let midnight = date.and_hms_opt(0, 0, 0).context("Invalid midnight")?;
+    let local_midnight = tz
+        .from_local_datetime(&midnight)
+        .earliest()
+        .with_context(|| format!("{} has no midnight on {}", tz, date))?;
+
+    Ok(local_midnight.with_timezone(&Utc))
+}
+
+/// The range covering yesterday and today in `tz`.
+pub fn recent_days_range(tz: &Tz) -> Result<(DateTime<Utc>, DateTime<Utc>), Error> {
+    let today = Utc::now().with_timezone(tz).date_naive();
+    let yesterday = today - Days::new(1);
+    let tomorrow = today + Days::new(1);
+
+    Ok((
+        local_midnight(yesterday, tz)?,
+        local_midnight(tomorrow, tz)?,
+    ))
+}
+
+/// Sessionizes `events` and clips the resulting intervals to `[range_start, range_end)`.
+pub fn attention_intervals_in_range(
+    events: &[BrowseEventRow],
+    range_start: DateTime<Utc>,
+    range_end: DateTime<Utc>,
+) -> Vec<AttentionInterval> {
+    let max_idle_gap = Duration::minutes(MAX_IDLE_GAP_MINUTES);
+    let until = range_end.min(Utc::now());
+
+    sessionize(events, max_idle_gap, until)
+        .iter()
+        .filter_map(|interval| interval.clip(range_start, range_end))
+        .collect()
+}
+
+/// Sums attention time per local bucket and cluster. Pages without a cluster in the run are skipped.
+pub fn aggregate_dwell_time_buckets(
+    intervals: &[AttentionInterval],
+    page_clusters: &[PageClusterRow],
+    bucket_interval: &BucketInterval,
+    tz: &Tz,
+) -> Vec<DwellTimeBucket> {
+    let clusters_by_page: HashMap<i32, &PageClusterRow> = page_clusters
+        .iter()
+        .map(|page_cluster| (page_cluster.page_id, page_cluster))
+        .collect();
+
+    let mut buckets: BTreeMap<(DateTime<FixedOffset>, Uuid), (&str, f64)> = BTreeMap::new();
+    for interval in intervals {
+        let Some(page_cluster) = interval
+            .page_id
+            .and_then(|page_id| clusters_by_page.get(&page_id))
+        else {
+            continue;
+        };
+
+        for (timestamp_bucket, seconds) in bucket_interval.split(interval.start, interval.end, tz) {
+            buckets
+                .entry((timestamp_bucket, page_cluster.cluster_id))
+                .or_insert((page_cluster.cluster_name.as_str(), 0.0))
+                .1 += seconds;
+        }
+    }
+
+    buckets
+        .into_iter()
+        .map(
+            |((timestamp_bucket, cluster_id), (cluster_name, seconds))| DwellTimeBucket {
+                timestamp_bucket,
+                cluster_id,
+                cluster_name: cluster_name.to_string(),
+                seconds,
+            },
+        )
+        .collect()
+}
+
+/// Sums attention time per page, longest first.
+pub fn aggregate_page_dwell_times(intervals: &[AttentionInterval]) -> Vec<PageDwellTimeRow> {
+    let mut seconds_by_url: HashMap<&str, f64> = HashMap::new();
+    for interval in intervals {
+        *seconds_by_url
+            .entry(interval.page_url.as_str())
+            .or_insert(0.0) += interval.duration_seconds;
+    }
+
+    let mut page_dwell_times: Vec<PageDwellTimeRow> = seconds_by_url
+        .into_iter()
+        .map(|(page_url, seconds)| PageDwellTimeRow {
+            page_url: page_url.to_string(),
+            seconds,
+        })
+        .collect();
+    page_dwell_times.sort_by(|a, b| b.seconds.total_cmp(&a.seconds));
+
+    page_dwell_times
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 10, 1, 12, 0, 0).unwrap() + Duration::minutes(minutes)
+    }
+
+    fn event(
+        minutes: i64,
+        tab_id: i32,
+        page_url: &str,
+        event_type: BrowseEventType,
+    ) -> BrowseEventRow {
+        BrowseEventRow {
+            id: 0,
+            timestamp: at(minutes),
+            tab_id,
+            page_id: None,
+            page_url: page_url.to_string(),
+            page_title: page_url.to_string(),
+            event_type,
+            client_event_id: None,
+        }
+    }
+
+    fn spans(intervals: &[AttentionInterval]) -> Vec<(i32, &str, DateTime<Utc>, DateTime<Utc>)> {
+        intervals
+            .iter()
+            .map(|interval| {
+                (
+                    interval.tab_id,
+                    interval.page_url.as_str(),
+                    interval.start,
+                    interval.end,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn activations_move_attention_between_tabs() {
+        let events = [
+            event(0, 1, "a", BrowseEventType::Activate),
+            event(5, 2, "b", BrowseEventType::Activate),
+            event(8, 2, "c", BrowseEventType::Update),
+            event(10, 2, "c", BrowseEventType::FocusLost),
+        ];
+
+        let intervals = sessionize(&events, Duration::minutes(15), at(60));
+
+        assert_eq!(
+            spans(&intervals),
+            [
+                (1, "a", at(0), at(5)),
+                (2, "b", at(5), at(8)),
+                (2, "c", at(8), at(10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn background_tab_updates_while_unfocused_are_not_attention() {
+        let events = [
+            event(0, 1, "a", BrowseEventType::Activate),
+            event(5, 1, "a", BrowseEventType::FocusLost),
+            // A background tab finishes loading while the user is in another app
+            event(6, 2, "b", BrowseEventType::Update),
+            event(30, 1, "a", BrowseEventType::FocusGained),
+            event(35, 1, "a", BrowseEventType::Idle),
+        ];
+
+        let intervals = sessionize(&events, Duration::minutes(15), at(60));
+
+        assert_eq!(
+            spans(&intervals),
+            [(1, "a", at(0), at(5)), (1, "a", at(30), at(35))]
+        );
+    }
+
+    #[test]
+    fn background_tab_updates_while_focused_elsewhere_are_not_attention() {
+        let events = [
+            event(0, 1, "a", BrowseEventType::Activate),
+            event(2, 2, "b", BrowseEventType::Update),
+            event(4, 1, "a2", BrowseEventType::Update),
+            event(6, 1, "a2", BrowseEventType::TabClosed),
+            event(7, 2, "b", BrowseEventType::Update),
+        ];
+
+        let intervals = sessionize(&events, Duration::minutes(15), at(60));
+
+        assert_eq!(
+            spans(&intervals),
+            [(1, "a", at(0), at(4)), (1, "a2", at(4), at(6))]
+        );
+    }
+
+    #[test]
+    fn updates_open_intervals_before_focus_is_known() {
+        let events = [event(0, 1, "a", BrowseEventType::Update)];
+
+        let intervals = sessionize(&events, Duration::minutes(15), at(60));
+
+        assert_eq!(spans(&intervals), [(1, "a", at(0), at(15))]);
+    }
+}
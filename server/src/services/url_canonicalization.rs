@@ -0,0 +1,416 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{Context, Error};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+use url::{form_urlencoded, Url};
+
+use crate::{
+    db::{
+        browse_event::{get_browse_event_urls, rename_browse_events_url},
+        page::{get_all_page_urls, merge_duplicate_page, set_page_url},
+        url_canonicalization::{
+            get_applied_url_canonicalization_config, set_applied_url_canonicalization_config,
+        },
+    },
+    services::url_rules::domain_glob_regex,
+};
+
+/// Params that only record how a visit was referred, dropped from every url. A trailing `*`
+/// matches any param starting with the rest.
+const TRACKING_PARAMS: &[&str] = &[
+    "utm_*", "fbclid", "gclid", "dclid", "gbraid", "wbraid", "msclkid", "mc_cid", "mc_eid",
+    "igshid", "yclid", "_ga", "_gl", "_hsenc", "_hsmi",
+];
+
+/// Params servers put session ids in, dropped from every url
+const SESSION_PARAMS: &[&str] = &["sessionid", "session_id", "jsessionid", "phpsessid"];
+
+/// Which query params matter, as defined in the url canonicalization config file, e.g.
+///
+/// ```toml
+/// drop_params = ["ref"]
+///
+/// [[domains]]
+/// domain = "*.youtube.com"
+/// keep_params = ["v", "list"]
+/// ```
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct UrlCanonicalizationConfig {
+    /// Dropped from every url, on top of the built in tracking and session params
+    #[serde(default)]
+    pub drop_params: Vec<String>,
+    #[serde(default)]
+    pub domains: Vec<DomainParamsConfig>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DomainParamsConfig {
+    /// Domain glob, as in url rules
+    pub domain: String,
+    /// The only params that identify a page on the domain, everything else is dropped
+    #[serde(default)]
+    pub keep_params: Option<Vec<String>>,
+    /// Dropped on the domain, on top of the global ones
+    #[serde(default)]
+    pub drop_params: Vec<String>,
+}
+
+impl UrlCanonicalizationConfig {
+    /// Recorded once stored urls are canonicalized with the config, to tell when it changed
+    pub fn parameters(&self) -> serde_json::Value {
+        json!(self)
+    }
+}
+
+pub fn load_url_canonicalization_config(path: &Path) -> Result<UrlCanonicalizationConfig, Error> {
+    let contents = fs::read_to_string(path).with_context(|| {
+        format!(
+            "Couldn't read the url canonicalization config at {}",
+            path.display()
+        )
+    })?;
+    toml::from_str(&contents)
+        .with_context(|| format!("Invalid url canonicalization config at {}", path.display()))
+}
+
+struct DomainParams {
+    domain: Regex,
+    keep_params: Option<Vec<String>>,
+    drop_params: Vec<String>,
+}
+
+/// Reduces the different urls a page is visited under to one, so they share a page, embeddings
+/// and clusters. Fragments, trailing slashes, tracking and session params are always dropped,
+/// other params depend on the config.
+pub struct UrlCanonicalizer {
+    drop_params: Vec<String>,
+    domains: Vec<DomainParams>,
+}
+
+impl UrlCanonicalizer {
+    pub fn new(config: &UrlCanonicalizationConfig) -> Result<Self, Error> {
+        let drop_params = TRACKING_PARAMS
+            .iter()
+            .chain(SESSION_PARAMS)
+            .map(|param| param.to_string())
+            .chain(config.drop_params.iter().map(|param| param.to_lowercase()))
+            .collect();
+        let domains = config
+            .domains
+            .iter()
+            .map(|domain_config| {
+                let domain = domain_glob_regex(&domain_config.domain).with_context(|| {
+                    format!(
+                        "Invalid domain {} in url canonicalization config",
+                        domain_config.domain
+                    )
+                })?;
+                Ok(DomainParams {
+                    domain,
+                    keep_params: domain_config
+                        .keep_params
+                        .as_ref()
+                        .map(|params| params.iter().map(|param| param.to_lowercase()).collect()),
+                    drop_params: domain_config
+                        .drop_params
+                        .iter()
+                        .map(|param| param.to_lowercase())
+                        .collect(),
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(UrlCanonicalizer {
+            drop_params,
+            domains,
+        })
+    }
+
+    /// Urls that aren't http(s) are returned as they are
+    pub fn canonicalize(&self, url: &str) -> String {
+        let Ok(mut parsed_url) = Url::parse(url) else {
+            return url.to_string();
+        };
+        if !matches!(parsed_url.scheme(), "http" | "https") {
+            return url.to_string();
+        }
+
+        parsed_url.set_fragment(None);
+
+        let path = parsed_url.path();
+        if path.len() > 1 && path.ends_with('/') {
+            let trimmed_path = path.trim_end_matches('/').to_string();
+            parsed_url.set_path(&trimmed_path);
+        }
+
+        // Params are filtered on the raw query so the ones that are kept aren't re-encoded
+        if let Some(query) = parsed_url.query() {
+            // The first matching domain decides
+            let domain_params = parsed_url.host_str().and_then(|host| {
+                self.domains
+                    .iter()
+                    .find(|domain_params| domain_params.domain.is_match(host))
+            });
+            let kept_query = query
+                .split('&')
+                .filter(|param| !param.is_empty())
+                .filter(|param| self.keeps_param(param, domain_params))
+                .collect::<Vec<_>>()
+                .join("&");
+            parsed_url.set_query((!kept_query.is_empty()).then_some(kept_query.as_str()));
+        }
+
+        parsed_url.to_string()
+    }
+
+    fn keeps_param(&self, param: &str, domain_params: Option<&DomainParams>) -> bool {
+        let name = param.split('=').next().unwrap_or_default();
+        let name = form_urlencoded::parse(name.as_bytes())
+            .next()
+            .map(|(name, _)| name.to_lowercase())
+            .unwrap_or_default();
+
+        if matches_any(&self.drop_params, &name) {
+            return false;
+        }
+        let Some(domain_params) = domain_params else {
+            return true;
+        };
+
+        domain_params
+            .keep_params
+            .as_ref()
+            .is_none_or(|keep_params| matches_any(keep_params, &name))
+            && !matches_any(&domain_params.drop_params, &name)
+    }
+}
+
+fn matches_any(params: &[String], name: &str) -> bool {
+    params.iter().any(|param| match param.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == param,
+    })
+}
+
+#[derive(Default, Debug)]
+pub struct CanonicalizeSummary {
+    pub merged_pages: u64,
+    pub renamed_pages: u64,
+    pub renamed_event_urls: u64,
+}
+
+/// Applies the canonicalizer to everything that's already stored, merging pages that turn out to
+/// be the same one. A page already stored under the canonical url is kept, otherwise the oldest.
+pub async fn canonicalize_stored_urls(
+    db: &PgPool,
+    url_canonicalizer: &UrlCanonicalizer,
+) -> Result<CanonicalizeSummary, Error> {
+    let mut summary = CanonicalizeSummary::default();
+
+    let mut pages_by_canonical_url: HashMap<String, Vec<_>> = HashMap::new();
+    for page in get_all_page_urls(db).await? {
+        pages_by_canonical_url
+            .entry(url_canonicalizer.canonicalize(&page.url))
+            .or_default()
+            .push(page);
+    }
+
+    for (canonical_url, mut pages) in pages_by_canonical_url {
+        pages.sort_by_key(|page| (page.url != canonical_url, page.id));
+        let (page, duplicates) = pages.split_first().context("Pages are grouped by url")?;
+        if duplicates.is_empty() && page.url == canonical_url {
+            continue;
+        }
+
+        let mut tx = db.begin().await?;
+        for duplicate in duplicates {
+            merge_duplicate_page(&mut *tx, duplicate.id, page.id).await?;
+            summary.merged_pages += 1;
+        }
+        if page.url != canonical_url {
+            set_page_url(&mut *tx, page.id, &canonical_url).await?;
+            summary.renamed_pages += 1;
+        }
+        tx.commit().await?;
+    }
+
+    for url in get_browse_event_urls(db).await? {
+        let canonical_url = url_canonicalizer.canonicalize(&url);
+        if canonical_url != url {
+            summary.renamed_event_urls +=
+                rename_browse_events_url(db, &url, &canonical_url).await?;
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Canonicalizes everything that's already stored with the config and records it as applied
+pub async fn apply_url_canonicalization_config(
+    db: &PgPool,
+    config: &UrlCanonicalizationConfig,
+) -> Result<CanonicalizeSummary, Error> {
+    let url_canonicalizer = UrlCanonicalizer::new(config)?;
+    let summary = canonicalize_stored_urls(db, &url_canonicalizer).await?;
+    set_applied_url_canonicalization_config(db, &config.parameters()).await?;
+
+    Ok(summary)
+}
+
+/// New events are stored under urls canonicalized with the current config, so pages stored before
+/// it changed would otherwise never be merged with them. Does nothing if stored urls were already
+/// canonicalized with this config.
+pub async fn apply_url_canonicalization_config_if_changed(
+    db: &PgPool,
+    config: &UrlCanonicalizationConfig,
+) -> Result<Option<CanonicalizeSummary>, Error> {
+    let applied_config = get_applied_url_canonicalization_config(db).await?;
+    if applied_config.as_ref() == Some(&config.parameters()) {
+        return Ok(None);
+    }
+
+    Ok(Some(apply_url_canonicalization_config(db, config).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::page::{get_page_from_url, insert_page};
+
+    const CONFIG: &str = r#"
+        drop_params = ["ref"]
+
+        [[domains]]
+        domain = "www.youtube.com"
+        keep_params = ["v", "list"]
+
+        [[domains]]
+        domain = "*.amazon.*"
+        drop_params = ["qid", "pd_rd_*"]
+    "#;
+
+    fn canonicalizer(config: &str) -> UrlCanonicalizer {
+        UrlCanonicalizer::new(&toml::from_str(config).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn drops_fragments_and_trailing_slashes() {
+        let url_canonicalizer = canonicalizer(CONFIG);
+
+        assert_eq!(
+            url_canonicalizer.canonicalize("https://docs.rs/regex/latest/regex/#syntax"),
+            "https://docs.rs/regex/latest/regex"
+        );
+        assert_eq!(
+            url_canonicalizer.canonicalize("https://example.com/a/b//?q=1#top"),
+            "https://example.com/a/b?q=1"
+        );
+        // The root path always has its slash
+        assert_eq!(
+            url_canonicalizer.canonicalize("https://example.com/#top"),
+            "https://example.com/"
+        );
+    }
+
+    #[test]
+    fn drops_tracking_and_session_params_everywhere() {
+        let url_canonicalizer = canonicalizer("");
+
+        assert_eq!(
+            url_canonicalizer.canonicalize(
+                "https://example.com/post?utm_source=news&id=3&UTM_Medium=email&fbclid=abc&PHPSESSID=1"
+            ),
+            "https://example.com/post?id=3"
+        );
+        assert_eq!(
+            url_canonicalizer.canonicalize("https://example.com/post?utm_campaign=launch&gclid=x"),
+            "https://example.com/post"
+        );
+    }
+
+    #[test]
+    fn keeps_other_params_as_they_were() {
+        let url_canonicalizer = canonicalizer("");
+
+        assert_eq!(
+            url_canonicalizer.canonicalize("https://example.com/search?q=rust%20lang&page=2&&"),
+            "https://example.com/search?q=rust%20lang&page=2"
+        );
+    }
+
+    #[test]
+    fn keep_params_are_the_only_params_kept_on_their_domain() {
+        let url_canonicalizer = canonicalizer(CONFIG);
+
+        assert_eq!(
+            url_canonicalizer
+                .canonicalize("https://www.youtube.com/watch?v=abc&t=42s&list=PL1&ab_channel=x"),
+            "https://www.youtube.com/watch?v=abc&list=PL1"
+        );
+        // Other domains keep them
+        assert_eq!(
+            url_canonicalizer.canonicalize("https://vimeo.com/watch?v=abc&t=42s"),
+            "https://vimeo.com/watch?v=abc&t=42s"
+        );
+    }
+
+    #[test]
+    fn drop_params_are_dropped_globally_or_on_their_domain() {
+        let url_canonicalizer = canonicalizer(CONFIG);
+
+        assert_eq!(
+            url_canonicalizer
+                .canonicalize("https://www.amazon.de/dp/B01?qid=17&pd_rd_w=x&ref=nav&th=1"),
+            "https://www.amazon.de/dp/B01?th=1"
+        );
+        assert_eq!(
+            url_canonicalizer.canonicalize("https://example.com/item?qid=17&ref=nav"),
+            "https://example.com/item?qid=17"
+        );
+    }
+
+    #[test]
+    fn leaves_urls_that_arent_http_alone() {
+        let url_canonicalizer = canonicalizer(CONFIG);
+
+        for url in ["chrome://settings/#utm_source", "about:blank", "not a url/"] {
+            assert_eq!(url_canonicalizer.canonicalize(url), url);
+        }
+    }
+
+    #[sqlx::test]
+    async fn canonicalizes_stored_urls_only_when_the_config_changed(
+        db: PgPool,
+    ) -> Result<(), Error> {
+        let config: UrlCanonicalizationConfig = toml::from_str("")?;
+        insert_page(&db, "https://www.youtube.com/watch?v=abc&t=10s").await?;
+        insert_page(&db, "https://www.youtube.com/watch?v=abc&t=42s").await?;
+
+        let summary = apply_url_canonicalization_config_if_changed(&db, &config).await?;
+        assert_eq!(summary.map(|summary| summary.merged_pages), Some(0));
+        assert!(apply_url_canonicalization_config_if_changed(&db, &config)
+            .await?
+            .is_none());
+
+        let changed_config: UrlCanonicalizationConfig = toml::from_str(CONFIG)?;
+        let summary = apply_url_canonicalization_config_if_changed(&db, &changed_config).await?;
+        assert_eq!(summary.map(|summary| summary.merged_pages), Some(1));
+        assert!(
+            get_page_from_url(&db, "https://www.youtube.com/watch?v=abc")
+                .await?
+                .is_some()
+        );
+        assert!(
+            apply_url_canonicalization_config_if_changed(&db, &changed_config)
+                .await?
+                .is_none()
+        );
+
+        Ok(())
+    }
+}
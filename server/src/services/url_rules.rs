@@ -0,0 +1,356 @@
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Context, Error};
+use regex::{Regex, RegexBuilder};
+use sqlx::PgPool;
+use url::Url;
+
+use crate::{
+    db::{
+        browse_event::{
+            delete_browse_events_with_url, get_browse_event_urls, redact_browse_events_with_url,
+        },
+        page::{clear_page_contents, delete_page, get_all_page_urls},
+        url_rule::get_url_rules,
+    },
+    models::{
+        browse_event::BrowseEventFromChromeExtension,
+        url_rule::{UrlMatchType, UrlRuleAction, UrlRuleRow},
+    },
+};
+
+/// Rules are read on every ingested event, so they're compiled once and reloaded on edits
+pub type SharedUrlRules = Arc<RwLock<UrlRules>>;
+
+enum UrlMatcher {
+    Domain(Regex),
+    Pattern(Regex),
+    PathPrefix(String),
+}
+
+impl UrlMatcher {
+    fn new(match_type: UrlMatchType, pattern: &str) -> Result<Self, Error> {
+        let matcher = match match_type {
+            UrlMatchType::DomainGlob => UrlMatcher::Domain(domain_glob_regex(pattern)?),
+            UrlMatchType::Regex => UrlMatcher::Pattern(Regex::new(pattern)?),
+            UrlMatchType::PathPrefix => UrlMatcher::PathPrefix(pattern.to_lowercase()),
+        };
+
+        Ok(matcher)
+    }
+
+    fn matches(&self, url: &str, parsed_url: Option<&Url>) -> bool {
+        match self {
+            UrlMatcher::Domain(domain_regex) => parsed_url
+                .and_then(Url::host_str)
+                .is_some_and(|host| domain_regex.is_match(host)),
+            UrlMatcher::Pattern(pattern) => pattern.is_match(url),
+            UrlMatcher::PathPrefix(prefix) => parsed_url.is_some_and(|parsed_url| {
+                let host = parsed_url.host_str().unwrap_or_default();
+                format!("{}{}", host, parsed_url.path())
+                    .to_lowercase()
+                    .starts_with(prefix)
+            }),
+        }
+    }
+}
+
+/// Matches a whole host, with `*` matching any run of characters, e.g. `*.google.com`
+pub fn domain_glob_regex(glob: &str) -> Result<Regex, Error> {
+    let pattern = glob
+        .split('*')
+        .map(regex::escape)
+        .collect::<Vec<_>>()
+        .join(".*");
+
+    Ok(RegexBuilder::new(&format!("^{}$", pattern))
+        .case_insensitive(true)
+        .build()?)
+}
+
+#[derive(Default)]
+pub struct UrlRules {
+    rules: Vec<(UrlMatcher, UrlRuleAction)>,
+}
+
+impl UrlRules {
+    pub fn compile(url_rule_rows: &[UrlRuleRow]) -> Result<Self, Error> {
+        let rules = url_rule_rows
+            .iter()
+            .map(|row| {
+                let matcher = UrlMatcher::new(row.match_type, &row.pattern)
+                    .with_context(|| format!("Invalid pattern in url rule {}", row.id))?;
+                Ok((matcher, row.action))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(UrlRules { rules })
+    }
+
+    /// The strictest action of all rules matching `url`
+    pub fn action_for(&self, url: &str) -> Option<UrlRuleAction> {
+        let parsed_url = Url::parse(url).ok();
+
+        self.rules
+            .iter()
+            .filter(|(matcher, _)| matcher.matches(url, parsed_url.as_ref()))
+            .map(|(_, action)| *action)
+            .max()
+    }
+}
+
+pub fn validate_url_rule_pattern(match_type: UrlMatchType, pattern: &str) -> Result<(), Error> {
+    UrlMatcher::new(match_type, pattern).map(|_| ())
+}
+
+pub async fn load_url_rules(db: &PgPool) -> Result<UrlRules, Error> {
+    let url_rule_rows = get_url_rules(db).await?;
+    UrlRules::compile(&url_rule_rows)
+}
+
+pub async fn reload_url_rules(db: &PgPool, url_rules: &SharedUrlRules) -> Result<(), Error> {
+    let reloaded_url_rules = load_url_rules(db).await?;
+    *url_rules
+        .write()
+        .map_err(|_| anyhow!("Url rules lock was poisoned"))? = reloaded_url_rules;
+
+    Ok(())
+}
+
+fn domain_only_url(url: &str) -> Option<String> {
+    let parsed_url = Url::parse(url).ok()?;
+    Some(format!(
+        "{}://{}/",
+        parsed_url.scheme(),
+        parsed_url.host_str()?
+    ))
+}
+
+/// Applies the strictest matching rule to an incoming event, returning `None` when it should be
+/// dropped.
+pub fn apply_url_rules(
+    url_rules: &UrlRules,
+    mut browse_event: BrowseEventFromChromeExtension,
+) -> Option<BrowseEventFromChromeExtension> {
+    match url_rules.action_for(&browse_event.page_url) {
+        None => Some(browse_event),
+        Some(UrlRuleAction::DropEvent) => None,
+        Some(UrlRuleAction::DropContent) => {
+            browse_event.page_content = None;
+            Some(browse_event)
+        }
+        Some(UrlRuleAction::DomainOnly) => {
+            // Urls we can't reduce to a domain are dropped rather than stored in full
+            let domain_url = domain_only_url(&browse_event.page_url)?;
+            browse_event.page_title = domain_url.clone();
+            browse_event.page_url = domain_url;
+            browse_event.page_content = None;
+            Some(browse_event)
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct PurgeSummary {
+    pub deleted_events: u64,
+    pub redacted_events: u64,
+    pub deleted_pages: u64,
+    pub cleared_pages: u64,
+}
+
+/// Applies the current rules to everything that's already stored
+pub async fn purge_url_rule_matches(
+    db: &PgPool,
+    url_rules: &UrlRules,
+) -> Result<PurgeSummary, Error> {
+    let mut summary = PurgeSummary::default();
+
+    for url in get_browse_event_urls(db).await? {
+        match url_rules.action_for(&url) {
+            Some(UrlRuleAction::DropEvent) => {
+                summary.deleted_events += delete_browse_events_with_url(db, &url).await?;
+            }
+            Some(UrlRuleAction::DomainOnly) => match domain_only_url(&url) {
+                Some(domain_url) => {
+                    summary.redacted_events +=
+                        redact_browse_events_with_url(db, &url, &domain_url).await?;
+                }
+                None => {
+                    summary.deleted_events += delete_browse_events_with_url(db, &url).await?;
+                }
+            },
+            Some(UrlRuleAction::DropContent) | None => {}
+        }
+    }
+
+    for page in get_all_page_urls(db).await? {
+        match url_rules.action_for(&page.url) {
+            Some(UrlRuleAction::DropEvent) | Some(UrlRuleAction::DomainOnly) => {
+                delete_page(db, page.id).await?;
+                summary.deleted_pages += 1;
+            }
+            Some(UrlRuleAction::DropContent) => {
+                clear_page_contents(db, page.id).await?;
+                summary.cleared_pages += 1;
+            }
+            None => {}
+        }
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn compile_rules(rules: &[(UrlMatchType, &str, UrlRuleAction)]) -> UrlRules {
+        let url_rule_rows: Vec<_> = rules
+            .iter()
+            .enumerate()
+            .map(|(id, (match_type, pattern, action))| UrlRuleRow {
+                id: id as i32,
+                match_type: *match_type,
+                pattern: pattern.to_string(),
+                action: *action,
+                created_at: Utc::now(),
+            })
+            .collect();
+        UrlRules::compile(&url_rule_rows).unwrap()
+    }
+
+    #[test]
+    fn domain_globs_match_whole_hosts() {
+        let url_rules = compile_rules(&[(
+            UrlMatchType::DomainGlob,
+            "*.google.com",
+            UrlRuleAction::DropEvent,
+        )]);
+
+        assert_eq!(
+            url_rules.action_for("https://mail.google.com/mail/u/0"),
+            Some(UrlRuleAction::DropEvent)
+        );
+        assert_eq!(
+            url_rules.action_for("https://MAIL.Google.com/"),
+            Some(UrlRuleAction::DropEvent)
+        );
+        assert_eq!(url_rules.action_for("https://google.com/search"), None);
+        assert_eq!(url_rules.action_for("https://notgoogle.com/"), None);
+        assert_eq!(url_rules.action_for("https://google.com.evil.com/"), None);
+
+        let url_rules = compile_rules(&[(
+            UrlMatchType::DomainGlob,
+            "google.com",
+            UrlRuleAction::DropEvent,
+        )]);
+
+        assert_eq!(
+            url_rules.action_for("https://google.com/search"),
+            Some(UrlRuleAction::DropEvent)
+        );
+        assert_eq!(url_rules.action_for("https://mail.google.com/"), None);
+    }
+
+    #[test]
+    fn regexes_match_anywhere_in_the_url() {
+        let url_rules = compile_rules(&[(
+            UrlMatchType::Regex,
+            r"[?&]token=",
+            UrlRuleAction::DomainOnly,
+        )]);
+
+        assert_eq!(
+            url_rules.action_for("https://example.com/reset?user=1&token=abc"),
+            Some(UrlRuleAction::DomainOnly)
+        );
+        assert_eq!(
+            url_rules.action_for("https://example.com/reset?user=1"),
+            None
+        );
+    }
+
+    #[test]
+    fn path_prefixes_match_case_insensitively() {
+        let url_rules = compile_rules(&[(
+            UrlMatchType::PathPrefix,
+            "docs.google.com/Document",
+            UrlRuleAction::DropContent,
+        )]);
+
+        assert_eq!(
+            url_rules.action_for("https://docs.google.com/document/d/123/edit"),
+            Some(UrlRuleAction::DropContent)
+        );
+        assert_eq!(
+            url_rules.action_for("https://DOCS.google.com/DOCUMENT/d/123"),
+            Some(UrlRuleAction::DropContent)
+        );
+        assert_eq!(
+            url_rules.action_for("https://docs.google.com/spreadsheets/d/123"),
+            None
+        );
+    }
+
+    #[test]
+    fn urls_without_hosts_only_match_regexes() {
+        let url_rules = compile_rules(&[
+            (UrlMatchType::DomainGlob, "*", UrlRuleAction::DropContent),
+            (UrlMatchType::PathPrefix, "", UrlRuleAction::DropContent),
+            (
+                UrlMatchType::Regex,
+                "^(about|data):",
+                UrlRuleAction::DropEvent,
+            ),
+        ]);
+
+        assert_eq!(
+            url_rules.action_for("about:blank"),
+            Some(UrlRuleAction::DropEvent)
+        );
+        assert_eq!(
+            url_rules.action_for("data:text/plain,hello"),
+            Some(UrlRuleAction::DropEvent)
+        );
+        assert_eq!(url_rules.action_for("not a url"), None);
+        // Other schemes can still have hosts
+        assert_eq!(
+            url_rules.action_for("chrome://settings"),
+            Some(UrlRuleAction::DropContent)
+        );
+    }
+
+    #[test]
+    fn strictest_matching_action_wins() {
+        let url_rules = compile_rules(&[
+            (
+                UrlMatchType::DomainGlob,
+                "*.bank.com",
+                UrlRuleAction::DropContent,
+            ),
+            (
+                UrlMatchType::PathPrefix,
+                "online.bank.com/statements",
+                UrlRuleAction::DropEvent,
+            ),
+            (UrlMatchType::Regex, "bank", UrlRuleAction::DomainOnly),
+        ]);
+
+        assert_eq!(
+            url_rules.action_for("https://online.bank.com/statements/2024"),
+            Some(UrlRuleAction::DropEvent)
+        );
+        assert_eq!(
+            url_rules.action_for("https://online.bank.com/accounts"),
+            Some(UrlRuleAction::DomainOnly)
+        );
+        assert_eq!(url_rules.action_for("https://example.com/"), None);
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        assert!(validate_url_rule_pattern(UrlMatchType::Regex, "(unclosed").is_err());
+        assert!(validate_url_rule_pattern(UrlMatchType::DomainGlob, "*.example.com").is_ok());
+    }
+}
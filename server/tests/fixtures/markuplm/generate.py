@@ -0,0 +1,438 @@
+"""Writes a tiny, randomly initialised MarkupLM for tests.
+
+The model has one encoder layer and a hidden size of 8, but otherwise follows MarkupLM: word,
+RoBERTa-style position, token type and xpath embeddings, then self-attention. It takes the same
+inputs and gives the same first output as an `optimum-cli export onnx` of microsoft/markuplm-base,
+so `MarkupLMModel` loads it the same way. The tokenizer is byte-level BPE without merges, so every
+byte is a token and short pages already span several windows.
+
+Only the standard library is needed, the ONNX protobuf is encoded by hand. Rerunning it writes
+the same files.
+"""
+
+import json
+import math
+import random
+import struct
+from pathlib import Path
+
+OUTPUT_DIR = Path(__file__).parent
+
+NAME = "tiny-random-markuplm"
+HIDDEN_SIZE = 8
+NUM_HEADS = 2
+INTERMEDIATE_SIZE = 16
+MAX_POSITION_EMBEDDINGS = 20
+PAD_TOKEN_ID = 1
+TYPE_VOCAB_SIZE = 1
+LAYER_NORM_EPS = 1e-5
+
+TAGS = ["html", "head", "body", "div", "p", "a", "h1", "ul", "li", "span"]
+MAX_DEPTH = 4
+MAX_WIDTH = 6
+PAD_WIDTH = MAX_WIDTH + 1
+XPATH_UNIT_HIDDEN_SIZE = 4
+# Known tags, then the unknown and padding tag ids
+MAX_XPATH_TAG_UNIT_EMBEDDINGS = len(TAGS) + 2
+MAX_XPATH_SUBS_UNIT_EMBEDDINGS = PAD_WIDTH + 1
+
+SPECIAL_TOKENS = ["<s>", "<pad>", "</s>", "<unk>"]
+
+OPSET_VERSION = 14
+FLOAT = 1
+INT64 = 7
+
+
+def bytes_to_unicode():
+    """GPT-2's mapping from bytes to printable characters, used by byte-level BPE"""
+    printable = (
+        list(range(ord("!"), ord("~") + 1))
+        + list(range(ord("¡"), ord("¬") + 1))
+        + list(range(ord("®"), ord("ÿ") + 1))
+    )
+    characters = printable[:]
+    n = 0
+    for byte in range(256):
+        if byte not in printable:
+            printable.append(byte)
+            characters.append(256 + n)
+            n += 1
+    return dict(zip(printable, map(chr, characters)))
+
+
+def vocab():
+    tokens = SPECIAL_TOKENS + sorted(bytes_to_unicode().values())
+    return {token: i for i, token in enumerate(tokens)}
+
+
+# Protobuf encoding, just enough for onnx.proto
+
+
+def varint(value):
+    value &= (1 << 64) - 1
+    encoded = bytearray()
+    while True:
+        byte = value & 0x7F
+        value >>= 7
+        if value:
+            encoded.append(byte | 0x80)
+        else:
+            encoded.append(byte)
+            return bytes(encoded)
+
+
+def field_varint(number, value):
+    return varint(number << 3) + varint(value)
+
+
+def field_bytes(number, value):
+    if isinstance(value, str):
+        value = value.encode()
+    return varint(number << 3 | 2) + varint(len(value)) + value
+
+
+def field_float(number, value):
+    return varint(number << 3 | 5) + struct.pack("<f", value)
+
+
+def tensor(name, dims, data_type, values):
+    packed = struct.pack(
+        "<%d%s" % (len(values), "f" if data_type == FLOAT else "q"), *values
+    )
+    return (
+        b"".join(field_varint(1, dim) for dim in dims)
+        + field_varint(2, data_type)
+        + field_bytes(8, name)
+        + field_bytes(9, packed)
+    )
+
+
+def attribute(name, value):
+    encoded = field_bytes(1, name)
+    if isinstance(value, float):
+        return encoded + field_float(2, value) + field_varint(20, 1)
+    if isinstance(value, int):
+        return encoded + field_varint(3, value) + field_varint(20, 2)
+    if isinstance(value, list):
+        return (
+            encoded
+            + b"".join(field_varint(8, item) for item in value)
+            + field_varint(20, 7)
+        )
+    raise TypeError(value)
+
+
+def value_info(name, data_type, dims):
+    shape = b"".join(
+        field_bytes(1, field_bytes(2, dim) if isinstance(dim, str) else field_varint(1, dim))
+        for dim in dims
+    )
+    tensor_type = field_varint(1, data_type) + field_bytes(2, shape)
+    return field_bytes(1, name) + field_bytes(2, field_bytes(1, tensor_type))
+
+
+class Graph:
+    def __init__(self, seed):
+        self.random = random.Random(seed)
+        self.nodes = []
+        self.initializers = []
+        self.count = 0
+
+    def fresh(self, prefix):
+        self.count += 1
+        return "%s_%d" % (prefix, self.count)
+
+    def constant(self, dims, values, data_type=FLOAT, prefix="const"):
+        name = self.fresh(prefix)
+        self.initializers.append(tensor(name, dims, data_type, values))
+        return name
+
+    def weight(self, prefix, dims, mean=0.0, std=0.5):
+        size = math.prod(dims)
+        return self.constant(
+            dims, [self.random.gauss(mean, std) for _ in range(size)], prefix=prefix
+        )
+
+    def op(self, op_type, inputs, output=None, **attributes):
+        output = output or self.fresh(op_type.lower())
+        node = (
+            b"".join(field_bytes(1, name) for name in inputs)
+            + field_bytes(2, output)
+            + field_bytes(3, self.fresh("node"))
+            + field_bytes(4, op_type)
+            + b"".join(
+                field_bytes(5, attribute(key, value))
+                for key, value in attributes.items()
+            )
+        )
+        self.nodes.append(node)
+        return output
+
+    def linear(self, prefix, x, in_features, out_features):
+        projected = self.op("MatMul", [x, self.weight(prefix + "_weight", [in_features, out_features])])
+        return self.op("Add", [projected, self.weight(prefix + "_bias", [out_features])])
+
+    def layer_norm(self, prefix, x, output=None):
+        mean = self.op("ReduceMean", [x], axes=[-1])
+        centered = self.op("Sub", [x, mean])
+        variance = self.op("ReduceMean", [self.op("Mul", [centered, centered])], axes=[-1])
+        std = self.op("Sqrt", [self.op("Add", [variance, self.constant([], [LAYER_NORM_EPS])])])
+        normalized = self.op("Div", [centered, std])
+        scaled = self.op(
+            "Mul", [normalized, self.weight(prefix + "_gamma", [HIDDEN_SIZE], mean=1.0, std=0.1)]
+        )
+        return self.op(
+            "Add", [scaled, self.weight(prefix + "_beta", [HIDDEN_SIZE], std=0.1)], output
+        )
+
+
+def build_model():
+    graph = Graph(seed=0)
+    vocab_size = len(vocab())
+
+    # RoBERTa counts positions over non-padding tokens, starting after the padding index
+    not_padding = graph.op(
+        "Cast",
+        [graph.op("Not", [graph.op("Equal", ["input_ids", graph.constant([], [PAD_TOKEN_ID], INT64)])])],
+        to=INT64,
+    )
+    positions = graph.op("CumSum", [not_padding, graph.constant([], [1], INT64)])
+    position_ids = graph.op(
+        "Add",
+        [graph.op("Mul", [positions, not_padding]), graph.constant([], [PAD_TOKEN_ID], INT64)],
+    )
+
+    word_embeddings = graph.op(
+        "Gather", [graph.weight("word_embeddings", [vocab_size, HIDDEN_SIZE]), "input_ids"]
+    )
+    position_embeddings = graph.op(
+        "Gather",
+        [graph.weight("position_embeddings", [MAX_POSITION_EMBEDDINGS, HIDDEN_SIZE]), position_ids],
+    )
+    token_type_embeddings = graph.op(
+        "Gather",
+        [graph.weight("token_type_embeddings", [TYPE_VOCAB_SIZE, HIDDEN_SIZE]), "token_type_ids"],
+    )
+
+    # Every xpath depth has its own tag and subscript embeddings, concatenated and projected
+    xpath_units = {}
+    for inputs, table_size in [
+        ("xpath_tags_seq", MAX_XPATH_TAG_UNIT_EMBEDDINGS),
+        ("xpath_subs_seq", MAX_XPATH_SUBS_UNIT_EMBEDDINGS),
+    ]:
+        depth_embeddings = []
+        for depth in range(MAX_DEPTH):
+            unit_ids = graph.op(
+                "Gather", [inputs, graph.constant([], [depth], INT64)], axis=2
+            )
+            table = graph.weight(
+                "%s_embeddings_%d" % (inputs, depth), [table_size, XPATH_UNIT_HIDDEN_SIZE]
+            )
+            depth_embeddings.append(graph.op("Gather", [table, unit_ids]))
+        xpath_units[inputs] = graph.op("Concat", depth_embeddings, axis=-1)
+    xpath_unit_sum = graph.op("Add", [xpath_units["xpath_tags_seq"], xpath_units["xpath_subs_seq"]])
+    xpath_inner = graph.op(
+        "Relu",
+        [graph.linear("xpath_unitseq2_inner", xpath_unit_sum, MAX_DEPTH * XPATH_UNIT_HIDDEN_SIZE, 4 * HIDDEN_SIZE)],
+    )
+    xpath_embeddings = graph.linear("inner2emb", xpath_inner, 4 * HIDDEN_SIZE, HIDDEN_SIZE)
+
+    embeddings = graph.op("Add", [word_embeddings, position_embeddings])
+    embeddings = graph.op("Add", [embeddings, token_type_embeddings])
+    embeddings = graph.op("Add", [embeddings, xpath_embeddings])
+    hidden_states = graph.layer_norm("embeddings_layer_norm", embeddings)
+
+    # Padding is masked out of attention with a large negative bias
+    attention_bias = graph.op(
+        "Mul",
+        [
+            graph.op(
+                "Sub",
+                [graph.constant([], [1.0]), graph.op("Cast", ["attention_mask"], to=FLOAT)],
+            ),
+            graph.constant([], [-10000.0]),
+        ],
+    )
+    attention_bias = graph.op(
+        "Unsqueeze", [attention_bias, graph.constant([2], [1, 2], INT64)]
+    )
+
+    head_size = HIDDEN_SIZE // NUM_HEADS
+    heads_shape = graph.constant([4], [0, 0, NUM_HEADS, head_size], INT64)
+
+    def split_heads(prefix, perm):
+        projected = graph.linear(prefix, hidden_states, HIDDEN_SIZE, HIDDEN_SIZE)
+        return graph.op("Transpose", [graph.op("Reshape", [projected, heads_shape])], perm=perm)
+
+    query = split_heads("query", [0, 2, 1, 3])
+    key = split_heads("key", [0, 2, 3, 1])
+    value = split_heads("value", [0, 2, 1, 3])
+    scores = graph.op(
+        "Mul", [graph.op("MatMul", [query, key]), graph.constant([], [1.0 / math.sqrt(head_size)])]
+    )
+    probabilities = graph.op("Softmax", [graph.op("Add", [scores, attention_bias])], axis=-1)
+    context = graph.op(
+        "Transpose", [graph.op("MatMul", [probabilities, value])], perm=[0, 2, 1, 3]
+    )
+    context = graph.op("Reshape", [context, graph.constant([3], [0, 0, HIDDEN_SIZE], INT64)])
+    attention_output = graph.linear("attention_output", context, HIDDEN_SIZE, HIDDEN_SIZE)
+    hidden_states = graph.layer_norm(
+        "attention_layer_norm", graph.op("Add", [attention_output, hidden_states])
+    )
+
+    intermediate = graph.linear("intermediate", hidden_states, HIDDEN_SIZE, INTERMEDIATE_SIZE)
+    gelu = graph.op(
+        "Mul",
+        [
+            graph.op("Mul", [intermediate, graph.constant([], [0.5])]),
+            graph.op(
+                "Add",
+                [
+                    graph.constant([], [1.0]),
+                    graph.op(
+                        "Erf", [graph.op("Div", [intermediate, graph.constant([], [math.sqrt(2.0)])])]
+                    ),
+                ],
+            ),
+        ],
+    )
+    output = graph.linear("output", gelu, INTERMEDIATE_SIZE, HIDDEN_SIZE)
+    graph.layer_norm(
+        "output_layer_norm",
+        graph.op("Add", [output, hidden_states]),
+        output="last_hidden_state",
+    )
+
+    sequence = ["batch_size", "sequence_length"]
+    xpath_sequence = sequence + [MAX_DEPTH]
+    inputs = [
+        value_info("input_ids", INT64, sequence),
+        value_info("attention_mask", INT64, sequence),
+        value_info("token_type_ids", INT64, sequence),
+        value_info("xpath_tags_seq", INT64, xpath_sequence),
+        value_info("xpath_subs_seq", INT64, xpath_sequence),
+    ]
+    outputs = [value_info("last_hidden_state", FLOAT, sequence + [HIDDEN_SIZE])]
+
+    graph_proto = (
+        b"".join(field_bytes(1, node) for node in graph.nodes)
+        + field_bytes(2, NAME)
+        + b"".join(field_bytes(5, initializer) for initializer in graph.initializers)
+        + b"".join(field_bytes(11, input) for input in inputs)
+        + b"".join(field_bytes(12, output) for output in outputs)
+    )
+    opset = field_bytes(1, "") + field_varint(2, OPSET_VERSION)
+    return (
+        field_varint(1, 7)
+        + field_bytes(2, "generate.py")
+        + field_bytes(7, graph_proto)
+        + field_bytes(8, opset)
+    )
+
+
+def tokenizer():
+    special_tokens = [
+        {
+            "id": i,
+            "content": token,
+            "single_word": False,
+            "lstrip": False,
+            "rstrip": False,
+            "normalized": False,
+            "special": True,
+        }
+        for i, token in enumerate(SPECIAL_TOKENS)
+    ]
+    return {
+        "version": "1.0",
+        "truncation": None,
+        "padding": None,
+        "added_tokens": special_tokens,
+        "normalizer": None,
+        "pre_tokenizer": {
+            "type": "ByteLevel",
+            "add_prefix_space": False,
+            "trim_offsets": True,
+            "use_regex": True,
+        },
+        "post_processor": {
+            "type": "RobertaProcessing",
+            "sep": ["</s>", 2],
+            "cls": ["<s>", 0],
+            "trim_offsets": True,
+            "add_prefix_space": False,
+        },
+        "decoder": {
+            "type": "ByteLevel",
+            "add_prefix_space": True,
+            "trim_offsets": True,
+            "use_regex": True,
+        },
+        "model": {
+            "type": "BPE",
+            "dropout": None,
+            "unk_token": None,
+            "continuing_subword_prefix": "",
+            "end_of_word_suffix": "",
+            "fuse_unk": False,
+            "byte_fallback": False,
+            "vocab": vocab(),
+            "merges": [],
+        },
+    }
+
+
+def tokenizer_config():
+    return {
+        "tokenizer_class": "MarkupLMTokenizer",
+        "bos_token": "<s>",
+        "eos_token": "</s>",
+        "sep_token": "</s>",
+        "cls_token": "<s>",
+        "unk_token": "<unk>",
+        "pad_token": "<pad>",
+        "model_max_length": MAX_POSITION_EMBEDDINGS - PAD_TOKEN_ID - 1,
+        "tags_dict": {tag: i for i, tag in enumerate(TAGS)},
+        "max_depth": MAX_DEPTH,
+        "max_width": MAX_WIDTH,
+        "pad_width": PAD_WIDTH,
+    }
+
+
+def model_config():
+    return {
+        "_name_or_path": NAME,
+        "architectures": ["MarkupLMModel"],
+        "model_type": "markuplm",
+        "vocab_size": len(vocab()),
+        "hidden_size": HIDDEN_SIZE,
+        "num_hidden_layers": 1,
+        "num_attention_heads": NUM_HEADS,
+        "intermediate_size": INTERMEDIATE_SIZE,
+        "hidden_act": "gelu",
+        "max_position_embeddings": MAX_POSITION_EMBEDDINGS,
+        "type_vocab_size": TYPE_VOCAB_SIZE,
+        "layer_norm_eps": LAYER_NORM_EPS,
+        "pad_token_id": PAD_TOKEN_ID,
+        "bos_token_id": 0,
+        "eos_token_id": 2,
+        "max_depth": MAX_DEPTH,
+        "xpath_unit_hidden_size": XPATH_UNIT_HIDDEN_SIZE,
+        "max_xpath_tag_unit_embeddings": MAX_XPATH_TAG_UNIT_EMBEDDINGS,
+        "max_xpath_subs_unit_embeddings": MAX_XPATH_SUBS_UNIT_EMBEDDINGS,
+        "tag_pad_id": len(TAGS) + 1,
+        "subs_pad_id": PAD_WIDTH,
+    }
+
+
+def write_json(name, contents):
+    with open(OUTPUT_DIR / name, "w") as f:
+        json.dump(contents, f, indent=2, ensure_ascii=False)
+        f.write("\n")
+
+
+if __name__ == "__main__":
+    (OUTPUT_DIR / "model.onnx").write_bytes(build_model())
+    write_json("tokenizer.json", tokenizer())
+    write_json("tokenizer_config.json", tokenizer_config())
+    write_json("config.json", model_config())
@@ -1,18 +1,22 @@
 import { useEffect, useState } from "react";
 import { BarChart, Card, SearchSelect, SearchSelectItem } from "@tremor/react";
 import {
+  ClusteringRunRow,
+  describeClusteringRun,
   getEventBucketData,
   EventCountBucketInfo,
   getClusteringRuns,
 } from "../utils/eventBucketData";
 
 function ActivityStackedBarCard() {
-  const [clusteringRunData, setClusteringRunData] = useState<string[]>([]);
+  const [clusteringRunData, setClusteringRunData] = useState<
+    ClusteringRunRow[]
+  >([]);
   const [eventBucketData, setEventBucketData] =
     useState<null | EventCountBucketInfo>(null);
 
   const refreshClusteringRunData = async () => {
-    const data: string[] = await getClusteringRuns();
+    const data: ClusteringRunRow[] = await getClusteringRuns();
     setClusteringRunData(data);
   };
 
@@ -45,13 +49,17 @@ function ActivityStackedBarCard() {
             index="timestamp_bucket"
             categories={clusterKeys}
             showLegend={false}
-            yAxisLabel="Events"
+            yAxisLabel="Minutes"
           />
         </div>
         <SearchSelect className="my-4" onValueChange={onSetClusteringRun}>
           {clusteringRunData.map((clusteringRun) => (
-            <SearchSelectItem key={clusteringRun} value={clusteringRun}>
-              {clusteringRun}
+            <SearchSelectItem
+              key={clusteringRun.clustering_run}
+              value={clusteringRun.clustering_run}
+              title={describeClusteringRun(clusteringRun)}
+            >
+              {clusteringRun.clustering_run}
             </SearchSelectItem>
           ))}
         </SearchSelect>
@@ -2,7 +2,7 @@ type EventCountBucketRow = {
   timestamp_bucket: string;
   cluster_id: string;
   cluster_name: string | null;
-  event_count: number;
+  seconds: number;
 };
 
 type TimestampPartitionedBuckets = {
@@ -23,20 +23,39 @@ type EventCountBucketInfo = {
 
 type ClusteringRunRow = {
   clustering_run: string;
+  embedding_run: string | null;
+  pipeline_name: string | null;
+  model_id: string | null;
+  embedding_dimension: number | null;
+  algorithm: string;
+  parameters: Record<string, unknown>;
+  code_version: string | null;
+  status: "running" | "completed" | "failed";
+  created_at: string;
 };
 
 const getClusteringRuns = async () => {
   const response = await fetch("http://localhost:8000/get_clustering_runs");
-  const clusteringRunsJson = await response.json();
-  const clustering_runs: string[] = clusteringRunsJson.map(
-    (row: ClusteringRunRow) => row.clustering_run
-  );
-  return clustering_runs;
+  const clusteringRuns: ClusteringRunRow[] = await response.json();
+  return clusteringRuns;
+};
+
+const describeClusteringRun = (run: ClusteringRunRow) => {
+  const parameters = Object.entries(run.parameters)
+    .map(([key, value]) => `${key}=${value}`)
+    .join(", ");
+  const model = run.model_id ?? "unknown model";
+
+  return `${run.algorithm} (${parameters}) over ${run.pipeline_name ?? "unknown pipeline"} / ${model}, ${run.status}`;
 };
 
 const getEventCountBucketRows = async (clustering_run: string) => {
+  const params = new URLSearchParams({
+    clustering_run,
+    tz: Intl.DateTimeFormat().resolvedOptions().timeZone,
+  });
   const response = await fetch(
-    `http://localhost:8000/get_event_buckets?clustering_run=${clustering_run}`
+    `http://localhost:8000/get_event_buckets?${params}`
   );
   const eventCountBucketsJson = await response.json();
   const eventCountBuckets: EventCountBucketRow[] = eventCountBucketsJson.map(
@@ -44,7 +63,7 @@ const getEventCountBucketRows = async (clustering_run: string) => {
       timestamp_bucket: row.timestamp_bucket,
       cluster_id: row.cluster_id,
       cluster_name: row.cluster_name,
-      event_count: row.event_count,
+      seconds: row.seconds,
     })
   );
 
@@ -97,7 +116,7 @@ const formatEventBucketData = (
       if (!clusterKeys.includes(clusterKey)) {
         clusterKeys.push(clusterKey);
       }
-      eventCountBucket[clusterKey] = row.event_count;
+      eventCountBucket[clusterKey] = Math.round(row.seconds / 60);
     });
 
     eventCountBuckets.push(eventCountBucket);
@@ -156,4 +175,10 @@ const getEventBucketData = async (clustering_run: string) => {
   };
 };
 
-export { getClusteringRuns, getEventBucketData, type EventCountBucketInfo };
+export {
+  describeClusteringRun,
+  getClusteringRuns,
+  getEventBucketData,
+  type ClusteringRunRow,
+  type EventCountBucketInfo,
+};